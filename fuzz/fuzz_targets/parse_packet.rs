@@ -0,0 +1,23 @@
+#![no_main]
+
+use std::net::SocketAddr;
+
+use libfuzzer_sys::fuzz_target;
+
+use ipfix_parser_rs::ValidationMode;
+use ipfix_parser_rs::parse_packet::parse_packet;
+use ipfix_parser_rs::sflow;
+use ipfix_parser_rs::template_ring::TemplateRing;
+
+fuzz_target!(|data: &[u8]| {
+    let ring = TemplateRing::new();
+    let exporter = SocketAddr::from(([127, 0, 0, 1], 4739));
+
+    //parse twice with the same ring, so data sets that come before their template in the input get decoded against it the second time
+    //lenient mode reads whatever it can of messages whose length is wrong, so it gets further into the input than strict mode does
+    let _ = parse_packet(&ring, exporter, data, ValidationMode::Lenient);
+    let _ = parse_packet(&ring, exporter, data, ValidationMode::Lenient);
+
+    //the same bytes could just as well have arrived on an sFlow listener
+    let _ = sflow::parse_datagram(ring.registry(), exporter, data);
+});
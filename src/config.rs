@@ -1,9 +1,79 @@
 use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::retention::RetentionPolicy;
 
 
 #[derive(Clone)]
 pub struct Config {
     pub ipfix_listen_addr: Ipv4Addr,
     pub ipfix_listen_port: u16,
-    pub num_threads: u32
-}
\ No newline at end of file
+    pub listeners: Vec<Listener>, //more sockets to listen on besides the IPFIX (UDP) one above, each with the protocol it expects
+    pub num_threads: u32,
+    pub template_lifetime: Option<Duration>, //templates not resent by the exporter within this long are dropped, None keeps them until they are withdrawn
+    pub ie_definition_files: Vec<(u32, PathBuf)>, //(enterprise number, CSV file) pairs of extra information elements to decode with, see IERegistry::load_csv
+    pub retention: RetentionPolicy, //how many results the aggregator holds on to before it starts evicting them
+    pub parser_queue_depth: usize, //packets waiting for each parser thread, template updates don't count
+    pub aggregator_queue_depth: usize, //parsed packets waiting for the aggregator, parser threads wait for room when it is full
+    pub subscriber_queue_depth: usize, //packets waiting for each subscriber, the aggregator doesn't wait for a subscriber that is behind, it drops the packet instead
+    pub overload_policy: OverloadPolicy, //what the coordinator does with a UDP packet when the parser thread it is meant for is full
+    pub dispatch: DispatchMode, //how the coordinator picks a parser thread for each packet
+    pub validation: ValidationMode //how closely message headers have to follow RFC 7011
+}
+
+//a message with a version other than 10 (IPFIX), 9, or 5 (NetFlow) is always rejected, it is some other protocol
+//so is an IPFIX message whose length is shorter than its own header, there would be no way to find its sets
+//NetFlow v9 headers don't carry a length, so these only change how IPFIX and NetFlow v5 messages are handled, for v5 the record count stands in for the length
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationMode {
+    Strict, //the length in the message header has to match the size of the datagram exactly
+    Lenient //a length that doesn't match the datagram is noted in PacketInfo::header_error, and whatever part of the message arrived is parsed
+}
+
+#[derive(Clone, Debug)]
+pub struct Listener {
+    pub addr: Ipv4Addr,
+    pub port: u16,
+    pub protocol: Protocol,
+    pub transport: Transport
+}
+
+//what the datagrams arriving on a listener are, this picks the parser they are handed to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Protocol {
+    IPFIX, //IPFIX, NetFlow v9, or NetFlow v5, told apart by the version at the start of each message
+    SFlow //sFlow version 5
+}
+
+//how messages reach a listener
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Transport {
+    UDP, //one message per datagram
+    TCP, //IPFIX only, any number of exporters connect and send a stream of messages, each connection has its own templates which are dropped when it closes
+        //a connection's messages already arrive in order, so they all go to one parser thread whatever the DispatchMode
+    TLS(TlsConfig) //TCP with TLS on top (RFC 7011 section 11), if exporters present a certificate it is attached to their packets (see PacketInfo::exporter_identity)
+}
+
+//files are PEM, they are read once when the collector starts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub certificate_chain: PathBuf, //the collector's certificate, followed by any intermediates
+    pub private_key: PathBuf, //the key for the collector's certificate
+    pub client_ca: Option<PathBuf> //when set, exporters have to present a certificate signed by one of these, when None they aren't asked for one
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DispatchMode {
+    RoundRobin, //spread packets evenly over the parser threads, data that arrives right behind its template can be parsed by another thread before the template is
+    ExporterAffine //every packet from an (exporter address, ODID) goes to the same parser thread, so each exporter's packets are parsed in the order they arrived
+}
+
+//only UDP packets are ever dropped, a TCP connection always waits for room so TCP flow control slows the exporter down instead
+//an exporter only sends its templates once per connection, losing one would leave the rest of the connection undecodable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverloadPolicy {
+    Block, //wait for room, packets pile up in the socket's receive buffer and the OS drops them once that is full
+    DropNewest, //drop the packet that just arrived
+    DropOldest //drop the packet that has been waiting the longest to make room
+}
@@ -0,0 +1,93 @@
+use std::fmt;
+
+//everything that can go wrong while picking apart a message
+//offsets are the number of bytes from the first byte of the IPFIX message to where the problem was found
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    //not enough bytes left for a message header, set header, template record header, or field specifier
+    TruncatedHeader { offset: usize, needed: usize, available: usize },
+    //the message header carries a version number we don't decode, 10 (IPFIX), 9 (NetFlow v9), and 5 (NetFlow v5) on IPFIX listeners, 5 on sFlow listeners
+    BadVersion { version: u16 },
+    //the length in the message header is shorter than the header itself, or doesn't match the size of the datagram it arrived in
+    //for NetFlow v5 the length is what the record count in the header works out to, which can be more than a 16 bit IPFIX length holds
+    BadMessageLength { length: usize, available: usize },
+    //a data set refers to a template we don't have (never received, withdrawn, or expired)
+    UnknownTemplate { offset: usize, set_id: u16, odid: u32 },
+    //a set says it is longer than what is left of the message, or shorter than its own header
+    SetLengthOverflow { offset: usize, set_id: u16, set_len: u16, available: usize },
+    //a field in a data record runs past the end of its set
+    FieldOutOfRange { offset: usize, set_id: u16, field_id: u16, width: u16, available: usize },
+    //a template record that can't describe a usable data record
+    MalformedTemplate { offset: usize, set_id: u16, template_id: u16, reason: &'static str },
+    //an sFlow datagram, sample, or record that doesn't add up
+    MalformedSFlow { offset: usize, reason: &'static str }
+}
+
+impl ParseError {
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            ParseError::TruncatedHeader { offset, .. } => Some(*offset),
+            ParseError::BadVersion { .. } => None,
+            ParseError::BadMessageLength { .. } => None,
+            ParseError::UnknownTemplate { offset, .. } => Some(*offset),
+            ParseError::SetLengthOverflow { offset, .. } => Some(*offset),
+            ParseError::FieldOutOfRange { offset, .. } => Some(*offset),
+            ParseError::MalformedTemplate { offset, .. } => Some(*offset),
+            ParseError::MalformedSFlow { offset, .. } => Some(*offset)
+        }
+    }
+
+    pub fn set_id(&self) -> Option<u16> {
+        match self {
+            ParseError::UnknownTemplate { set_id, .. } => Some(*set_id),
+            ParseError::SetLengthOverflow { set_id, .. } => Some(*set_id),
+            ParseError::FieldOutOfRange { set_id, .. } => Some(*set_id),
+            ParseError::MalformedTemplate { set_id, .. } => Some(*set_id),
+            _ => None
+        }
+    }
+
+    //the parsers build errors relative to the slice they were handed, this moves the offset so it is relative to an earlier byte
+    pub(crate) fn shift(self, by: usize) -> Self {
+        match self {
+            ParseError::TruncatedHeader { offset, needed, available } => ParseError::TruncatedHeader { offset: offset + by, needed, available },
+            ParseError::BadVersion { version } => ParseError::BadVersion { version },
+            ParseError::BadMessageLength { length, available } => ParseError::BadMessageLength { length, available },
+            ParseError::UnknownTemplate { offset, set_id, odid } => ParseError::UnknownTemplate { offset: offset + by, set_id, odid },
+            ParseError::SetLengthOverflow { offset, set_id, set_len, available } => ParseError::SetLengthOverflow { offset: offset + by, set_id, set_len, available },
+            ParseError::FieldOutOfRange { offset, set_id, field_id, width, available } => ParseError::FieldOutOfRange { offset: offset + by, set_id, field_id, width, available },
+            ParseError::MalformedTemplate { offset, set_id, template_id, reason } => ParseError::MalformedTemplate { offset: offset + by, set_id, template_id, reason },
+            ParseError::MalformedSFlow { offset, reason } => ParseError::MalformedSFlow { offset: offset + by, reason }
+        }
+    }
+}
+
+//builds a TruncatedHeader error for a read of needed bytes at the start of at, where at is somewhere inside start
+pub(crate) fn truncated(start: &[u8], at: &[u8], needed: usize) -> ParseError {
+    ParseError::TruncatedHeader { offset: start.len() - at.len(), needed, available: at.len() }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::TruncatedHeader { offset, needed, available } =>
+                write!(f, "header at byte {} needs {} bytes, but only {} are left", offset, needed, available),
+            ParseError::BadVersion { version } =>
+                write!(f, "message has version {}, which isn't a version we decode on this listener", version),
+            ParseError::BadMessageLength { length, available } =>
+                write!(f, "message header says the message is {} bytes long, but {} bytes arrived", length, available),
+            ParseError::UnknownTemplate { offset, set_id, odid } =>
+                write!(f, "set at byte {} uses template {} which is unknown for ODID {}", offset, set_id, odid),
+            ParseError::SetLengthOverflow { offset, set_id, set_len, available } =>
+                write!(f, "set {} at byte {} has length {}, but {} bytes are left in the message", set_id, offset, set_len, available),
+            ParseError::FieldOutOfRange { offset, set_id, field_id, width, available } =>
+                write!(f, "field {} in set {} at byte {} needs {} bytes, but only {} are left in the set", field_id, set_id, offset, width, available),
+            ParseError::MalformedTemplate { offset, set_id, template_id, reason } =>
+                write!(f, "template {} in set {} at byte {} is malformed: {}", template_id, set_id, offset, reason),
+            ParseError::MalformedSFlow { offset, reason } =>
+                write!(f, "sFlow data at byte {} is malformed: {}", offset, reason)
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
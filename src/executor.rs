@@ -1,144 +1,1183 @@
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
 use std::collections::HashMap;
-use std::sync::mpsc::{Receiver, Sender, self};
-use std::thread::{Thread, self};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, ErrorKind, Read};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError, self};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::{Config, DispatchMode, Listener, OverloadPolicy, Protocol, Transport, ValidationMode};
+use crate::information_elements::{IELoadError, IERegistry};
+use crate::parse_packet::{IPFIX_VERSION, MESSAGE_HEADER_LEN, PacketResult, PacketInfo, parse_packet};
+use crate::query::ResultQuery;
+use crate::queue::{self, QueueReceiver, QueueSender, SendError};
+use crate::retention::{ResultStore, RetentionPolicy, RetentionStats};
+use crate::sequence::{SequenceStats, SequenceTracker};
+use crate::sflow;
+use crate::template_ring::TemplateRing;
+use crate::tls::{self, ExporterIdentity, TlsSetupError};
 
-use crate::config::Config;
-use crate::parse_packet::{PacketResult, PacketInfo, parse_packet};
-use crate::templates::IPFIXTemplate;
-use crate::template_ring::{TemplateRing, self};
+const TEMPLATE_PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct IPFIXCollectorHandle {
     coordinator: Sender<MsgToCoordinatorThread>,
-    parsers: Vec<Sender<MsgToParserThread>>,
-    aggregator: Sender<MsgToAggregatorThread>
+    parsers: Vec<QueueSender<MsgToParserThread>>,
+    aggregator: QueueSender<MsgToAggregatorThread>,
+    next_subscription: AtomicU64,
+    subscriber_queue_depth: usize,
+    dropped_packets: Arc<AtomicU64>, //packets the listener threads dropped because of the overload policy
+    rejected_packets: Arc<AtomicU64>, //packets the parser threads couldn't parse at all
+    subscriber_dropped_packets: Arc<AtomicU64> //packets the aggregator didn't give a subscriber because it was too far behind
 }
 
+//what can go wrong while starting a collector, nothing has been started if start returns one of these
+#[derive(Debug)]
+pub enum StartError {
+    InformationElements(PathBuf, IELoadError), //one of Config::ie_definition_files couldn't be loaded
+    Bind(Ipv4Addr, u16, io::Error), //a listen socket couldn't be opened, usually because something else already has the port
+    Tls(Ipv4Addr, u16, TlsSetupError), //a TLS listener's certificates or key couldn't be used
+    UnsupportedTransport(Protocol, Ipv4Addr, u16) //only IPFIX can be received over TCP
+}
+
+impl fmt::Display for StartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartError::InformationElements(path, e) => write!(f, "failed to load information elements from {}: {}", path.display(), e),
+            StartError::Bind(addr, port, e) => write!(f, "failed to open listen socket on {}:{}: {}", addr, port, e),
+            StartError::Tls(addr, port, e) => write!(f, "failed to set up TLS on {}:{}: {}", addr, port, e),
+            StartError::UnsupportedTransport(protocol, addr, port) => write!(f, "{:?} can't be received over TCP, only IPFIX can ({}:{})", protocol, addr, port)
+        }
+    }
+}
+
+impl std::error::Error for StartError {}
+
+//identifies a callback subscription so it can be removed again
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubscriptionId(u64);
+
+//called on a thread of its own for every matching packet, packets that arrive while it is busy wait for it (see Config::subscriber_queue_depth)
+pub type SubscriberCallback = Box<dyn FnMut(PacketInfo) + Send>;
+
 impl IPFIXCollectorHandle {
-    pub fn start(config: &Config) -> Self {
-        let (agg_tx, agg_rx) = mpsc::channel();
-        thread::spawn(move ||{ agg_thread(agg_rx); });
+    //everything in the config that can be wrong (element definitions, sockets, certificates) is checked before any thread is started
+    pub fn start(config: &Config) -> Result<Self, StartError> {
+        //every parser thread decodes with the same information elements, the IANA ones plus whatever enterprise elements we were given
+        let mut registry = IERegistry::iana();
+        for (en, path) in config.ie_definition_files.iter() {
+            registry.load_csv_file(path, *en).map_err(|e| StartError::InformationElements(path.clone(), e))?;
+        }
+
+        let sockets = open_listeners(config)?;
+
+        let (agg_tx, agg_rx) = queue::bounded(config.aggregator_queue_depth);
+        let retention = config.retention.clone();
+        let subscriber_dropped_packets = Arc::new(AtomicU64::new(0));
+        let subscriber_dropped_clone = subscriber_dropped_packets.clone();
+        thread::spawn(move ||{ agg_thread(agg_rx, retention, subscriber_dropped_clone); });
 
-        //need this early so parsers can talk with coordinator for template updates
         let (coord_tx, coord_rx) = mpsc::channel();
 
+        //and they all share one set of templates, a template one thread learns is immediately known to the rest
+        let templates = Arc::new(TemplateRing::with_registry(Arc::new(registry)));
+
+        let rejected_packets = Arc::new(AtomicU64::new(0));
+
         let mut parser_threads_recs = Vec::new();
         for i in 0..config.num_threads {
-            let (tx, rx) = mpsc::channel();
+            let (tx, rx) = queue::bounded(config.parser_queue_depth);
             parser_threads_recs.push(tx);
 
             let agg_sender_clone = agg_tx.clone();
-            let coord_sender_clone = coord_tx.clone();
+            let templates_clone = templates.clone();
+            let rejected_clone = rejected_packets.clone();
+            let validation = config.validation;
 
-            thread::spawn(move ||{ parser_thread(i, rx, coord_sender_clone, agg_sender_clone); });
+            thread::spawn(move ||{ parser_thread(i, rx, agg_sender_clone, templates_clone, validation, rejected_clone); });
         }
 
         let cfg_clone = (*config).clone();
         let parser_threads_clone = parser_threads_recs.clone();
-        thread::spawn(move ||{ coord_thread(coord_rx, parser_threads_clone, cfg_clone); });
+        let dropped_packets = Arc::new(AtomicU64::new(0));
+        let dropped_clone = dropped_packets.clone();
+        thread::spawn(move ||{ coord_thread(coord_rx, sockets, parser_threads_clone, cfg_clone, dropped_clone, templates); });
+
+        Ok(IPFIXCollectorHandle {
+            coordinator: coord_tx,
+            parsers: parser_threads_recs,
+            aggregator: agg_tx,
+            next_subscription: AtomicU64::new(0),
+            subscriber_queue_depth: config.subscriber_queue_depth.max(1),
+            dropped_packets,
+            rejected_packets,
+            subscriber_dropped_packets
+        })
+    }
+
+    //requests from the handle skip ahead of the parsed packets waiting for the aggregator, so they are answered even when it is busy
+    //that also means packets still in the queue when a request is made aren't included in the answer
+
+    //copies of the stored results that match the query, the results stay stored
+    pub fn query(&self, query: &ResultQuery) -> Vec<PacketInfo> {
+        let (tx, rx) = mpsc::channel();
+        self.aggregator.send_priority(MsgToAggregatorThread::Query(query.clone(), tx)).expect("Failed to send query to aggregator thread");
+        rx.recv().expect("Aggregator thread did not answer query")
+    }
+
+    //the stored results that match the query, they are removed from the aggregator
+    pub fn drain(&self, query: &ResultQuery) -> Vec<PacketInfo> {
+        let (tx, rx) = mpsc::channel();
+        self.aggregator.send_priority(MsgToAggregatorThread::Drain(query.clone(), tx)).expect("Failed to send drain to aggregator thread");
+        rx.recv().expect("Aggregator thread did not answer drain")
+    }
+
+    //a copy of everything stored, per protocol and ODID (sFlow sub-agent id)
+    pub fn snapshot(&self) -> HashMap<(Protocol, u32), Vec<PacketInfo>> {
+        let (tx, rx) = mpsc::channel();
+        self.aggregator.send_priority(MsgToAggregatorThread::Snapshot(tx)).expect("Failed to send snapshot request to aggregator thread");
+        rx.recv().expect("Aggregator thread did not answer snapshot request")
+    }
+
+    //how much the retention policy has thrown away so far
+    pub fn retention_stats(&self) -> RetentionStats {
+        let (tx, rx) = mpsc::channel();
+        self.aggregator.send_priority(MsgToAggregatorThread::RetentionStats(tx)).expect("Failed to send retention stats request to aggregator thread");
+        rx.recv().expect("Aggregator thread did not answer retention stats request")
+    }
+
+    //per (exporter, protocol, ODID), how many data records the sequence numbers say we never got
+    //packets the coordinator dropped (see dropped_packets) and packets that failed to parse count as lost too, they never reached the aggregator
+    pub fn sequence_stats(&self) -> HashMap<(SocketAddr, Protocol, u32), SequenceStats> {
+        let (tx, rx) = mpsc::channel();
+        self.aggregator.send_priority(MsgToAggregatorThread::SequenceStats(tx)).expect("Failed to send sequence stats request to aggregator thread");
+        rx.recv().expect("Aggregator thread did not answer sequence stats request")
+    }
+
+    //every packet the aggregator receives from now on that matches the query is also sent down the returned channel, as the aggregator receives it
+    //like query, a template ID filter trims each packet down to the data sets for that template
+    //the channel holds Config::subscriber_queue_depth packets, the aggregator never waits for a subscriber, packets that don't fit are dropped (see subscriber_dropped_packets)
+    //dropping the receiver ends the subscription
+    pub fn subscribe(&self, query: &ResultQuery) -> Receiver<PacketInfo> {
+        let (tx, rx) = mpsc::sync_channel(self.subscriber_queue_depth);
+        let id = self.new_subscription_id();
+        self.aggregator.send_priority(MsgToAggregatorThread::Subscribe(id, query.clone(), tx)).expect("Failed to send subscription to aggregator thread");
+        rx
+    }
+
+    //same as subscribe, but callback is called with each matching packet instead of it being sent down a channel
+    //the callback gets a thread of its own, which goes away after unsubscribe once the packets already waiting for it have been handled
+    pub fn subscribe_with(&self, query: &ResultQuery, mut callback: SubscriberCallback) -> SubscriptionId {
+        let (tx, rx) = mpsc::sync_channel(self.subscriber_queue_depth);
+        thread::spawn(move ||{
+            for info in rx {
+                callback(info);
+            }
+        });
+
+        let id = self.new_subscription_id();
+        self.aggregator.send_priority(MsgToAggregatorThread::Subscribe(id, query.clone(), tx)).expect("Failed to send subscription to aggregator thread");
+        id
+    }
+
+    pub fn unsubscribe(&self, id: SubscriptionId) {
+        self.aggregator.send_priority(MsgToAggregatorThread::Unsubscribe(id)).expect("Failed to send unsubscribe to aggregator thread");
+    }
+
+    //packets a subscriber missed because it already had Config::subscriber_queue_depth packets waiting, across every subscriber
+    pub fn subscriber_dropped_packets(&self) -> u64 {
+        self.subscriber_dropped_packets.load(Ordering::Relaxed)
+    }
+
+    fn new_subscription_id(&self) -> SubscriptionId {
+        SubscriptionId(self.next_subscription.fetch_add(1, Ordering::Relaxed))
+    }
+
+    //packets the listener threads dropped because a parser thread's queue was full (see Config::overload_policy)
+    pub fn dropped_packets(&self) -> u64 {
+        self.dropped_packets.load(Ordering::Relaxed)
+    }
 
-        IPFIXCollectorHandle { coordinator: coord_tx, parsers: parser_threads_recs, aggregator: agg_tx }
+    //packets thrown away because they couldn't be parsed at all, a bad message header (wrong version, a length that doesn't fit, see Config::validation) or a set that doesn't fit in the message
+    pub fn rejected_packets(&self) -> u64 {
+        self.rejected_packets.load(Ordering::Relaxed)
     }
 
+    //stopping twice is fine, the second time the threads are already gone
     pub fn stop(&mut self) {
-        self.coordinator.send(MsgToCoordinatorThread::STOP).expect("Failed to stop coordinator thread");
-        self.aggregator.send(MsgToAggregatorThread::STOP).expect("Failed to stop aggregator thread");
+        let _ = self.coordinator.send(MsgToCoordinatorThread::Stop);
+        let _ = self.aggregator.send_priority(MsgToAggregatorThread::Stop);
         for t in self.parsers.iter() {
-            t.send(MsgToParserThread::STOP).expect("Failed to stop parser thread");
+            let _ = t.send_priority(MsgToParserThread::Stop);
         }
     }
 }
 
 //INTER THREAD MESSAGES
 enum MsgToParserThread {
-    STOP, //stops thread
-    TEMPLATE(IPFIXTemplate), //send new template to parser thread to add to parser ring
-    WORK(Box<[u8]>) //packet that arrived
+    Stop, //stops thread
+    Work(Protocol, SocketAddr, Box<[u8]>), //packet that arrived, who sent it, and what the listener it arrived on expects
+    SessionWork(SocketAddr, Box<[u8]>), //an IPFIX message from a TCP connection, never dropped since the exporter only sends its templates once per connection
+    SessionStarted(SocketAddr, u64, Option<Arc<ExporterIdentity>>), //a TCP exporter connected (the u64 tells its connections apart), sent before its first message, with the certificate it presented over TLS
+    SessionEnded(SocketAddr, u64) //a TCP exporter disconnected, sent after its last message so its templates are dropped once that has been parsed
 }
 
 enum MsgToCoordinatorThread {
-    STOP, //stops thread
-    NEW_TEMPLATE(IPFIXTemplate) //parser thread found a new template and needs everyone to be updated
+    Stop //stops thread
+}
+
+enum MsgToListenerThread {
+    Stop //stops thread
 }
 
 enum MsgToAggregatorThread {
-    RESULT(PacketInfo), //parser thread finished picking apart a packet
-    STOP //stops thread
+    Result(PacketInfo), //parser thread finished picking apart a packet
+    Query(ResultQuery, Sender<Vec<PacketInfo>>), //send back copies of the stored results that match
+    Drain(ResultQuery, Sender<Vec<PacketInfo>>), //send back and forget the stored results that match
+    Snapshot(Sender<HashMap<(Protocol, u32), Vec<PacketInfo>>>), //send back a copy of everything
+    RetentionStats(Sender<RetentionStats>), //send back the eviction counters
+    SequenceStats(Sender<HashMap<(SocketAddr, Protocol, u32), SequenceStats>>), //send back the loss counters
+    Subscribe(SubscriptionId, ResultQuery, SyncSender<PacketInfo>), //push matching results to the subscriber as they arrive
+    Unsubscribe(SubscriptionId), //stop pushing results to a subscriber
+    Stop //stops thread
+}
+
+//hands a packet to a subscriber without waiting for it, a subscriber whose channel is full misses the packet
+//returns false once the subscriber has gone away
+fn deliver(subscriber: &SyncSender<PacketInfo>, info: PacketInfo, dropped_packets: &AtomicU64) -> bool {
+    match subscriber.try_send(info) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            dropped_packets.fetch_add(1, Ordering::Relaxed);
+            true
+        },
+        Err(TrySendError::Disconnected(_)) => false
+    }
 }
 
-fn parser_thread(idx: u32, parser_rec: Receiver<MsgToParserThread>, coord_snd: Sender<MsgToCoordinatorThread>, agg_snd: Sender<MsgToAggregatorThread>) {
-    let mut templates = TemplateRing::new();
+fn parser_thread(idx: u32, parser_rec: QueueReceiver<MsgToParserThread>, agg_snd: QueueSender<MsgToAggregatorThread>, templates: Arc<TemplateRing>, validation: ValidationMode, rejected_packets: Arc<AtomicU64>) {
+    //the TCP connections given to this thread, and the certificate each one's exporter presented if it was TLS
+    let mut sessions = HashMap::<SocketAddr, (u64, Option<Arc<ExporterIdentity>>)>::new();
 
     loop {
-        match parser_rec.recv().expect(format!("Thread {} failed to receive message from coordinator", idx).as_str()) {
-            MsgToParserThread::STOP => { return; },
-            MsgToParserThread::TEMPLATE(t) => { 
-                let odid = t.odid;
-                templates.insert_template(t, odid); 
+        //every sender is gone once the handle and the listeners are, the collector is shutting down
+        let Ok(msg) = parser_rec.recv() else { return; };
+        let (protocol, exporter, pkt) = match msg {
+            MsgToParserThread::Stop => { return; },
+            MsgToParserThread::Work(protocol, exporter, pkt) => (protocol, exporter, pkt),
+            MsgToParserThread::SessionWork(exporter, pkt) => (Protocol::IPFIX, exporter, pkt),
+            MsgToParserThread::SessionStarted(exporter, session, identity) => {
+                //an exporter can reconnect from the same address and port before the end of its old connection has been noticed
+                //the old connection is gone either way, so its templates are too
+                if sessions.insert(exporter, (session, identity)).is_some() {
+                    templates.close_session(exporter);
+                }
+                templates.open_session(exporter);
+                continue;
             },
-            MsgToParserThread::WORK(pkt) => {
-                match parse_packet(&templates, &pkt) {
-                    PacketResult::AbortError => { eprintln!("Thread {} failed to parse a full packet", idx); },
-                    PacketResult::Ok(info) => {
-                        for t in info.templates.iter() {
-                            coord_snd.send(MsgToCoordinatorThread::NEW_TEMPLATE(t.clone())).expect("Failed to send template to coordinator");
-                        }
-                        agg_snd.send(MsgToAggregatorThread::RESULT(info)).expect("Failed to send message to aggregator");
-                    }
+            MsgToParserThread::SessionEnded(exporter, session) => {
+                //the end of a connection that has already been replaced must not close the new one
+                if sessions.get(&exporter).is_some_and(|(current, _identity)| *current == session) {
+                    sessions.remove(&exporter);
+                    templates.close_session(exporter);
                 }
-            }//end work block
-        }//end match
+                continue;
+            }
+        };
+
+        //templates in the packet go straight into the shared ring as they are parsed, sFlow has no templates and only needs the element definitions
+        let result = match protocol {
+            Protocol::IPFIX => parse_packet(&templates, exporter, &pkt, validation),
+            Protocol::SFlow => sflow::parse_datagram(templates.registry(), exporter, &pkt)
+        };
+        match result {
+            PacketResult::AbortError(e) => {
+                rejected_packets.fetch_add(1, Ordering::Relaxed);
+                eprintln!("Thread {} failed to parse a full packet from {}: {}", idx, exporter, e);
+            },
+            PacketResult::Ok(mut info) => {
+                info.exporter_identity = sessions.get(&exporter).and_then(|(_session, identity)| identity.clone());
+                //waits for room if the aggregator is behind, which in turn backs up this thread's queue
+                //the aggregator only goes away when the collector is stopping
+                if agg_snd.send(MsgToAggregatorThread::Result(info)).is_err() {
+                    return;
+                }
+            }
+        }
     }//end loop
 }
 
-//coordinator thread, passes work and new templates to each parser thread
-fn coord_thread(coord_rec: Receiver<MsgToCoordinatorThread>, parser_threads: Vec<Sender<MsgToParserThread>>, cfg: Config) {
+//a listener's socket, opened by start so a port that is already taken is reported to the caller
+enum ListenSocket {
+    Udp(UdpSocket, Protocol),
+    Tcp(TcpListener, Option<Arc<ServerConfig>>) //with the TLS configuration for TLS listeners
+}
+
+//opens a socket for every listener, the IPFIX socket is always there and the configured listeners come on top of it
+fn open_listeners(cfg: &Config) -> Result<Vec<ListenSocket>, StartError> {
+    let timeout = std::time::Duration::from_millis(50);
+
+    let ipfix = Listener { addr: cfg.ipfix_listen_addr, port: cfg.ipfix_listen_port, protocol: Protocol::IPFIX, transport: Transport::UDP };
+    let mut sockets = Vec::new();
+    for l in std::iter::once(&ipfix).chain(cfg.listeners.iter()) {
+        match &l.transport {
+            Transport::UDP => {
+                let socket = UdpSocket::bind((l.addr, l.port)).map_err(|e| StartError::Bind(l.addr, l.port, e))?;
+                socket.set_read_timeout(Some(timeout)).expect("Failed to set socket timeout");
+                sockets.push(ListenSocket::Udp(socket, l.protocol));
+            },
+            Transport::TCP | Transport::TLS(_) => {
+                //there is no length in an sFlow or NetFlow header to find where one message ends and the next starts
+                if l.protocol != Protocol::IPFIX {
+                    return Err(StartError::UnsupportedTransport(l.protocol, l.addr, l.port));
+                }
+                let tls = match &l.transport {
+                    Transport::TLS(tls_cfg) => Some(tls::server_config(tls_cfg).map_err(|e| StartError::Tls(l.addr, l.port, e))?),
+                    _ => None
+                };
+                let socket = TcpListener::bind((l.addr, l.port)).map_err(|e| StartError::Bind(l.addr, l.port, e))?;
+                socket.set_nonblocking(true).expect("Failed to make TCP listen socket nonblocking");
+                sockets.push(ListenSocket::Tcp(socket, tls));
+            }
+        }
+    }
+    Ok(sockets)
+}
+
+//coordinator thread, gives every listen socket its own thread, then keeps the templates pruned until it is stopped
+fn coord_thread(coord_rec: Receiver<MsgToCoordinatorThread>, sockets: Vec<ListenSocket>, parser_threads: Vec<QueueSender<MsgToParserThread>>, cfg: Config, dropped_packets: Arc<AtomicU64>, templates: Arc<TemplateRing>) {
     let timeout = std::time::Duration::from_millis(50);
-    let socket = std::net::UdpSocket::bind(format!("{}:{}", cfg.ipfix_listen_addr, cfg.ipfix_listen_port)).expect("Failed to open IPFIX listen socket");
-    socket.set_read_timeout(Some(timeout)).expect("Failed to set socket timeout");
 
+    let mut listener_threads = Vec::new();
+    for socket in sockets {
+        let (tx, rx) = mpsc::channel();
+        listener_threads.push(tx);
+
+        let parser_threads_clone = parser_threads.clone();
+        let (dispatch, overload_policy) = (cfg.dispatch, cfg.overload_policy);
+        let dropped_clone = dropped_packets.clone();
+
+        match socket {
+            ListenSocket::Udp(socket, protocol) => {
+                thread::spawn(move ||{ listener_thread(rx, socket, protocol, parser_threads_clone, dispatch, overload_policy, dropped_clone); });
+            },
+            ListenSocket::Tcp(socket, tls) => {
+                thread::spawn(move ||{ tcp_listener_thread(rx, socket, tls, parser_threads_clone); });
+            }
+        }
+    }
+
+    let mut last_prune = Instant::now();
+
+    loop {
+        if let Ok(MsgToCoordinatorThread::Stop) = coord_rec.try_recv() {
+            //a listener thread that is already gone has nothing to stop
+            for t in listener_threads.iter() {
+                let _ = t.send(MsgToListenerThread::Stop);
+            }
+            return;
+        }
+
+        //expire templates the exporter stopped refreshing, there is no point doing this more than about once a second
+        if let Some(lifetime) = cfg.template_lifetime {
+            if last_prune.elapsed() >= TEMPLATE_PRUNE_INTERVAL {
+                templates.prune_old_templates(lifetime);
+                last_prune = Instant::now();
+            }
+        }
+
+        thread::sleep(timeout);
+    }
+}
+
+//listener thread, reads datagrams off one socket and passes them to the parser threads along with the protocol the socket expects
+fn listener_thread(listener_rec: Receiver<MsgToListenerThread>, socket: UdpSocket, protocol: Protocol, parser_threads: Vec<QueueSender<MsgToParserThread>>, dispatch: DispatchMode, overload_policy: OverloadPolicy, dropped_packets: Arc<AtomicU64>) {
     let mut cur_parser_thread = 0;
 
     let mut buf = [0u8; 10000]; //This just needs to be larger than the max sized IPFIX report, and reports are capped in size by the MTU of the link they travel across
 
     loop {
+        if let Ok(MsgToListenerThread::Stop) = listener_rec.try_recv() {
+            return;
+        }
+
         match socket.recv_from(&mut buf) {
-            Err(_e) => match coord_rec.try_recv() { //happens when the socket times out
-                Ok(msg) => match msg { //see if we have a stop or template message waiting
-                    MsgToCoordinatorThread::STOP => { return; },
-                    MsgToCoordinatorThread::NEW_TEMPLATE(tmp) => {
-                        for thread in parser_threads.iter() {
-                            thread.send(MsgToParserThread::TEMPLATE(tmp.clone())).expect("Could not send template to parser thread");
-                        }
-                    }
-                },
-                Err(_e) => { continue; } //nothing in coord rec queue
-            },
-            Ok((count, _sock_addr)) => {
+            Err(_e) => { continue; }, //happens when the socket times out, go check for a stop message again
+            Ok((count, sock_addr)) => {
                 let trimmed_buf = &buf[..count];
                 let mut vec: Vec<u8> = Vec::with_capacity(count);
-                vec.extend_from_slice(&trimmed_buf);
+                vec.extend_from_slice(trimmed_buf);
                 let boxed_buf = vec.into_boxed_slice();
-                parser_threads[cur_parser_thread].send(MsgToParserThread::WORK(boxed_buf)).expect("Could not send work to parser thread");
-                cur_parser_thread = (cur_parser_thread + 1) % parser_threads.len();
+
+                let thread_idx = match dispatch {
+                    DispatchMode::RoundRobin => {
+                        let idx = cur_parser_thread;
+                        cur_parser_thread = (cur_parser_thread + 1) % parser_threads.len();
+                        idx
+                    },
+                    DispatchMode::ExporterAffine => exporter_thread(protocol, &sock_addr, &boxed_buf, parser_threads.len())
+                };
+
+                if !hand_off(&parser_threads[thread_idx], MsgToParserThread::Work(protocol, sock_addr, boxed_buf), overload_policy, &dropped_packets) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+//TCP listener thread, accepts exporter connections and gives each one its own thread, which does the TLS handshake first if tls is set
+//every connection is its own transport session, the exporter's address and port tell them apart in the template ring
+//the overload policy doesn't apply here, a connection waits for room in its parser thread's queue and TCP flow control holds the exporter back
+fn tcp_listener_thread(listener_rec: Receiver<MsgToListenerThread>, socket: TcpListener, tls: Option<Arc<ServerConfig>>, parser_threads: Vec<QueueSender<MsgToParserThread>>) {
+    let timeout = std::time::Duration::from_millis(50);
+    let mut connections = Vec::<(Sender<MsgToListenerThread>, JoinHandle<()>)>::new();
+    let mut next_session = 0u64; //every connection gets its own number, so a parser thread can tell a reconnect from the connection before it
+
+    loop {
+        if let Ok(MsgToListenerThread::Stop) = listener_rec.try_recv() {
+            //connections that already closed have dropped their receiver, nothing to stop there
+            for (c, _handle) in connections.iter() {
+                let _ = c.send(MsgToListenerThread::Stop);
             }
+            return;
         }
+
+        //forget the connections that closed on their own
+        connections.retain(|(_c, handle)| !handle.is_finished());
+
+        match socket.accept() {
+            Ok((mut stream, exporter)) => {
+                stream.set_nonblocking(false).expect("Failed to make TCP connection blocking");
+                stream.set_read_timeout(Some(timeout)).expect("Failed to set TCP connection timeout");
+
+                //the messages on a connection have to be parsed in order, so they all go to the same parser thread
+                //the session is opened and closed on that thread too, in between its messages, see parser_thread for reconnects
+                let parser = parser_threads[session_thread(&exporter, parser_threads.len())].clone();
+                let session = next_session;
+                next_session += 1;
+                let (tx, rx) = mpsc::channel();
+                let tls_clone = tls.clone();
+                let handle = thread::spawn(move ||{
+                    //its templates must not expire while it is connected, the start has to reach the parser thread before any message does and the queue keeps them in order
+                    //a parser thread that is gone means the collector is stopping, there is no point reading the connection then
+                    match tls_clone {
+                        None => if parser.send(MsgToParserThread::SessionStarted(exporter, session, None)).is_ok() {
+                            connection_thread(rx, stream, exporter, session, parser);
+                        },
+                        //the session only starts once the handshake is done, a failed handshake has nothing to end
+                        Some(tls_cfg) => if let Some((conn, identity)) = tls_handshake(&rx, &mut stream, exporter, tls_cfg) {
+                            if parser.send(MsgToParserThread::SessionStarted(exporter, session, identity)).is_ok() {
+                                connection_thread(rx, StreamOwned::new(conn, stream), exporter, session, parser);
+                            }
+                        }
+                    }
+                });
+                connections.push((tx, handle));
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => { thread::sleep(timeout); },
+            Err(e) => {
+                eprintln!("Failed to accept TCP connection: {}", e);
+                thread::sleep(timeout);
+            }
+        }
+    }
+}
+
+//connection thread, cuts the byte stream from one TCP exporter into IPFIX messages using the length in each message header
+//stream is either the TCP connection itself or TLS on top of it, reads have to time out so stop messages are noticed
+//the session has already been started on the parser thread, this ends it once the connection closes
+//every message waits for room in the parser thread's queue, a dropped message could take the connection's only copy of a template with it
+fn connection_thread(connection_rec: Receiver<MsgToListenerThread>, mut stream: impl Read, exporter: SocketAddr, session: u64, parser: QueueSender<MsgToParserThread>) {
+    let mut buf = [0u8; 65536]; //an IPFIX message can't be longer than this, its length is 16 bits
+    let mut pending: Vec<u8> = Vec::new(); //what has arrived of messages that haven't fully arrived yet
+
+    'connection: loop {
+        //the whole collector is stopping, the parser threads might already be gone so there is no session to end
+        if let Ok(MsgToListenerThread::Stop) = connection_rec.try_recv() {
+            return;
+        }
+
+        let count = match stream.read(&mut buf) {
+            Ok(0) => { break; }, //the exporter closed the connection
+            Ok(count) => count,
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::Interrupted => { continue; }, //go check for a stop message again
+            Err(e) => {
+                eprintln!("TCP connection from {} failed: {}", exporter, e);
+                break;
+            }
+        };
+        pending.extend_from_slice(&buf[..count]);
+
+        while pending.len() >= 4 {
+            let version = u16::from_be_bytes([pending[0], pending[1]]);
+            let len = u16::from_be_bytes([pending[2], pending[3]]) as usize;
+
+            //a header that isn't IPFIX means we have lost track of where messages start, and there is no way to find it again
+            //the parser thread rejects what is left so it is counted, then we hang up
+            if version != IPFIX_VERSION || len < MESSAGE_HEADER_LEN {
+                let rest = std::mem::take(&mut pending).into_boxed_slice();
+                if parser.send(MsgToParserThread::SessionWork(exporter, rest)).is_err() {
+                    return;
+                }
+                eprintln!("TCP connection from {} sent a message header that isn't IPFIX, closing it", exporter);
+                break 'connection;
+            }
+
+            if pending.len() < len {
+                break;
+            }
+
+            let msg: Box<[u8]> = pending.drain(..len).collect();
+            //the parser thread is gone when the collector is stopping, and so is the session
+            if parser.send(MsgToParserThread::SessionWork(exporter, msg)).is_err() {
+                return;
+            }
+        }
+    }
+
+    //has to queue up behind the last message instead of skipping ahead, or its templates would be gone before it is parsed
+    //normal sends never skip ahead of the queue, and DropOldest only ever drops UDP packets (see hand_off)
+    let _ = parser.send(MsgToParserThread::SessionEnded(exporter, session));
+}
+
+//does the TLS handshake on a connection thread, returns the connection and the certificate the exporter presented (if it sent one)
+//None if the handshake failed or the collector is stopping
+fn tls_handshake(connection_rec: &Receiver<MsgToListenerThread>, stream: &mut TcpStream, exporter: SocketAddr, tls: Arc<ServerConfig>) -> Option<(ServerConnection, Option<Arc<ExporterIdentity>>)> {
+    let mut conn = ServerConnection::new(tls).expect("Failed to start TLS connection");
+
+    while conn.is_handshaking() {
+        if let Ok(MsgToListenerThread::Stop) = connection_rec.try_recv() {
+            return None;
+        }
+
+        match conn.complete_io(stream) {
+            Ok(_) => {},
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::Interrupted => {},
+            Err(e) => {
+                eprintln!("TLS handshake with {} failed: {}", exporter, e);
+                return None;
+            }
+        }
+    }
+
+    let identity = conn.peer_certificates().and_then(|certs| certs.first()).and_then(|cert| ExporterIdentity::from_certificate(cert)).map(Arc::new);
+    Some((conn, identity))
+}
+
+//queues a UDP packet for a parser thread, following the overload policy if its queue is full
+//returns false once the parser thread has gone away, which only happens when the collector is stopping
+fn hand_off(parser: &QueueSender<MsgToParserThread>, work: MsgToParserThread, overload_policy: OverloadPolicy, dropped_packets: &AtomicU64) -> bool {
+    let dropped = match overload_policy {
+        OverloadPolicy::Block => parser.send(work).map(|_| false),
+        OverloadPolicy::DropNewest => match parser.try_send(work) {
+            Err(SendError::Full(_)) => Ok(true),
+            r => r.map(|_| false)
+        },
+        //only UDP packets are ever dropped, messages from TCP connections and session starts and ends have to reach the parser thread
+        OverloadPolicy::DropOldest => parser.send_dropping_oldest(work, |m| matches!(m, MsgToParserThread::Work(..))).map(|d| d.is_some())
+    };
+    match dropped {
+        Ok(true) => {
+            dropped_packets.fetch_add(1, Ordering::Relaxed);
+            true
+        },
+        Ok(false) => true,
+        Err(_) => false
     }
 }
 
-//aggregator thread: receives data from parser threads and stores it in a hashmap as a vector of datasets per ODID
-fn agg_thread(agg_rec: Receiver<MsgToAggregatorThread>) {
-    let mut odid_map = HashMap::<u32, Vec<PacketInfo>>::new();
+//picks the parser thread for a packet from its exporter and ODID, so every packet from one exporter's observation domain goes to the same thread
+//packets too short to have an ODID are all treated as having an empty one, the parser thread will reject them anyway
+fn exporter_thread(protocol: Protocol, exporter: &SocketAddr, pkt: &[u8], thread_count: usize) -> usize {
+    //NetFlow headers are laid out differently, the v9 source id and the v5 engine type and id play the part of the ODID
+    //in sFlow it is the sub agent id, which comes after an agent address that is either IPv4 or IPv6
+    let odid_bytes = match protocol {
+        Protocol::SFlow => match pkt.get(4..8) {
+            Some([0, 0, 0, 2]) => 24..28,
+            _ => 12..16
+        },
+        Protocol::IPFIX => match pkt.get(0..2) {
+            Some([0, 9]) => 16..20,
+            Some([0, 5]) => 20..22,
+            _ => 12..16
+        }
+    };
+    let odid = pkt.get(odid_bytes).unwrap_or(&[]);
+
+    let mut hasher = DefaultHasher::new();
+    (exporter, odid).hash(&mut hasher);
+    (hasher.finish() % thread_count as u64) as usize
+}
+
+//picks the parser thread for a TCP connection from the exporter's address and port alone
+fn session_thread(exporter: &SocketAddr, thread_count: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    exporter.hash(&mut hasher);
+    (hasher.finish() % thread_count as u64) as usize
+}
+
+//aggregator thread: receives data from parser threads and stores it per ODID, within the retention policy
+//every packet from every parser thread passes through here, so this is also where sequence numbers are checked
+fn agg_thread(agg_rec: QueueReceiver<MsgToAggregatorThread>, retention: RetentionPolicy, subscriber_dropped_packets: Arc<AtomicU64>) {
+    let mut store = ResultStore::new(retention);
+    let mut sequences = SequenceTracker::new();
+    let mut subscribers = Vec::<(SubscriptionId, ResultQuery, SyncSender<PacketInfo>)>::new();
+    let mut last_expiry = Instant::now();
 
     loop {
-        match agg_rec.recv().expect("Aggregator failed to receive message") {
-            MsgToAggregatorThread::STOP => { return; },
-            MsgToAggregatorThread::RESULT(d) => {
-                let odid = d.odid;
-                match odid_map.get_mut(&odid) {
-                    None => { odid_map.insert(odid, Vec::from([d])); },
-                    Some(v) => { v.push(d); }
+        //old results have to be expired even when nothing is arriving
+        if last_expiry.elapsed() >= RETENTION_CHECK_INTERVAL {
+            store.expire(SystemTime::now());
+            last_expiry = Instant::now();
+        }
+
+        let msg = match agg_rec.recv_timeout(RETENTION_CHECK_INTERVAL) {
+            Ok(msg) => msg,
+            Err(RecvTimeoutError::Timeout) => { continue; },
+            Err(RecvTimeoutError::Disconnected) => { return; } //the handle and every parser thread are gone
+        };
+
+        match msg {
+            MsgToAggregatorThread::Stop => { return; },
+            MsgToAggregatorThread::Result(d) => {
+                sequences.observe(&d);
+
+                //subscribers get their copy before it is stored, and the ones whose receiver was dropped are forgotten
+                subscribers.retain(|(_id, q, sub)| match q.select(&d) {
+                    Some(selected) => deliver(sub, selected, &subscriber_dropped_packets),
+                    None => true
+                });
+
+                store.insert(d);
+            },
+            //the handle might have given up waiting, nothing to do about it if it did
+            MsgToAggregatorThread::Query(q, reply) => { let _ = reply.send(store.collect(&q)); },
+            MsgToAggregatorThread::Drain(q, reply) => { let _ = reply.send(store.take(&q)); },
+            MsgToAggregatorThread::Snapshot(reply) => { let _ = reply.send(store.snapshot()); },
+            MsgToAggregatorThread::RetentionStats(reply) => { let _ = reply.send(store.stats()); },
+            MsgToAggregatorThread::SequenceStats(reply) => { let _ = reply.send(sequences.stats()); },
+            MsgToAggregatorThread::Subscribe(id, q, sub) => { subscribers.push((id, q, sub)); },
+            MsgToAggregatorThread::Unsubscribe(id) => { subscribers.retain(|(sub_id, _q, _sub)| *sub_id != id); }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    use std::fs;
+    use std::io::Write;
+
+    use rcgen::{BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+    use rustls::{ClientConfig, ClientConnection, RootCertStore};
+    use rustls::pki_types::{PrivateKeyDer, ServerName};
+
+    use crate::config::TlsConfig;
+    use crate::parse_data::DataType;
+    use crate::parse_packet::{test_exporter, test_path};
+    use crate::retention::RetentionPolicy;
+
+    //a collector on an ephemeral port, so tests don't fight over ports
+    fn config() -> Config {
+        Config {
+            ipfix_listen_addr: Ipv4Addr::LOCALHOST,
+            ipfix_listen_port: 0,
+            listeners: Vec::new(),
+            num_threads: 2,
+            template_lifetime: None,
+            ie_definition_files: Vec::new(),
+            retention: RetentionPolicy::default(),
+            parser_queue_depth: 16,
+            aggregator_queue_depth: 16,
+            subscriber_queue_depth: 16,
+            overload_policy: OverloadPolicy::Block,
+            dispatch: DispatchMode::ExporterAffine,
+            validation: ValidationMode::Strict
+        }
+    }
+
+    #[test]
+    fn start_reports_config_errors() {
+        let missing = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("no-such-elements.csv");
+        let cfg = Config { ie_definition_files: vec![(9, missing.clone())], ..config() };
+        match IPFIXCollectorHandle::start(&cfg) {
+            Err(StartError::InformationElements(path, IELoadError::Io(_))) => assert_eq!(path, missing),
+            r => panic!("expected the missing element file to be reported, got {:?}", r.err())
+        }
+
+        let sflow_over_tcp = Listener { addr: Ipv4Addr::LOCALHOST, port: 0, protocol: Protocol::SFlow, transport: Transport::TCP };
+        let cfg = Config { listeners: vec![sflow_over_tcp], ..config() };
+        assert!(matches!(IPFIXCollectorHandle::start(&cfg).err(), Some(StartError::UnsupportedTransport(Protocol::SFlow, _, _))));
+
+        //the port is taken for as long as this socket is open
+        let taken = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("failed to open a socket");
+        let cfg = Config { ipfix_listen_port: taken.local_addr().expect("socket has no address").port(), ..config() };
+        assert!(matches!(IPFIXCollectorHandle::start(&cfg).err(), Some(StartError::Bind(..))));
+
+        let mut collector = IPFIXCollectorHandle::start(&config()).expect("a good config should start");
+        collector.stop();
+    }
+
+    //hands a packet to the aggregator as if a parser thread had parsed it
+    fn deliver_to_aggregator(collector: &IPFIXCollectorHandle, info: PacketInfo) {
+        collector.aggregator.send(MsgToAggregatorThread::Result(info)).expect("Failed to send result to aggregator thread");
+    }
+
+    //requests skip ahead of results, so wait until the aggregator has stored everything sent to it
+    fn wait_for_stored(collector: &IPFIXCollectorHandle, count: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while collector.query(&ResultQuery::all()).len() < count {
+            assert!(Instant::now() < deadline, "aggregator never stored {} packets", count);
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn subscribers_get_matching_packets() {
+        let mut collector = IPFIXCollectorHandle::start(&config()).expect("a good config should start");
+        let everything = collector.subscribe(&ResultQuery::all());
+        let odid_2 = collector.subscribe(&ResultQuery::odid(2));
+        let template_257 = collector.subscribe(&ResultQuery { template_id: Some(257), ..ResultQuery::default() });
+
+        deliver_to_aggregator(&collector, PacketInfo::with_records(1, 0, &[256, 257]));
+        deliver_to_aggregator(&collector, PacketInfo::with_records(2, 0, &[256]));
+        wait_for_stored(&collector, 2);
+
+        let timeout = Duration::from_secs(1);
+        assert_eq!(everything.recv_timeout(timeout).expect("first packet").odid, 1);
+        assert_eq!(everything.recv_timeout(timeout).expect("second packet").odid, 2);
+        assert_eq!(odid_2.recv_timeout(timeout).expect("ODID 2 packet").odid, 2);
+        let trimmed = template_257.recv_timeout(timeout).expect("template 257 packet");
+        assert_eq!(trimmed.data.iter().map(|d| d.template).collect::<Vec<_>>(), vec![257]);
+
+        //everything was delivered before it was stored, so anything else would already be there
+        assert!(everything.try_recv().is_err());
+        assert!(odid_2.try_recv().is_err());
+        assert!(template_257.try_recv().is_err());
+        collector.stop();
+    }
+
+    #[test]
+    fn unsubscribed_callbacks_stop_getting_packets() {
+        let mut collector = IPFIXCollectorHandle::start(&config()).expect("a good config should start");
+        let (tx, rx) = mpsc::channel();
+        let id = collector.subscribe_with(&ResultQuery::all(), Box::new(move |info| { tx.send(info.odid).expect("test went away"); }));
+
+        deliver_to_aggregator(&collector, PacketInfo::with_records(1, 0, &[256]));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(1));
+
+        collector.unsubscribe(id);
+        deliver_to_aggregator(&collector, PacketInfo::with_records(2, 0, &[256]));
+        wait_for_stored(&collector, 2);
+
+        //the callback's thread ends once it is unsubscribed, and the callback (and tx) go with it
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Err(RecvTimeoutError::Disconnected));
+        collector.stop();
+    }
+
+    #[test]
+    fn slow_subscribers_miss_packets_instead_of_holding_up_the_aggregator() {
+        let mut collector = IPFIXCollectorHandle::start(&Config { subscriber_queue_depth: 2, ..config() }).expect("a good config should start");
+        let slow = collector.subscribe(&ResultQuery::all());
+        let (tx, rx) = mpsc::channel::<()>();
+        //a callback that never returns until the test is done
+        collector.subscribe_with(&ResultQuery::all(), Box::new(move |_info| { let _ = rx.recv(); }));
+
+        for odid in 0..5 {
+            deliver_to_aggregator(&collector, PacketInfo::with_records(odid, 0, &[256]));
+        }
+        wait_for_stored(&collector, 5);
+
+        //the channel subscriber kept the first 2, the callback is busy with the first and has room for 2 more
+        assert_eq!(slow.try_iter().map(|p| p.odid).collect::<Vec<_>>(), vec![0, 1]);
+        let dropped = collector.subscriber_dropped_packets();
+        assert!(dropped == 5 || dropped == 6, "expected 5 or 6 dropped packets, got {}", dropped);
+        drop(tx);
+        collector.stop();
+    }
+
+    //a port nothing is listening on, for tests that have to send to the collector
+    fn free_port() -> u16 {
+        UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).and_then(|s| s.local_addr()).expect("failed to find a free port").port()
+    }
+
+    //an IPFIX message holding the given sets
+    fn message(odid: u32, seq_num: u32, sets: &[Vec<u8>]) -> Vec<u8> {
+        let len = MESSAGE_HEADER_LEN + sets.iter().map(|s| s.len()).sum::<usize>();
+        let mut msg = Vec::with_capacity(len);
+        msg.extend_from_slice(&IPFIX_VERSION.to_be_bytes());
+        msg.extend_from_slice(&(len as u16).to_be_bytes());
+        msg.extend_from_slice(&0u32.to_be_bytes());
+        msg.extend_from_slice(&seq_num.to_be_bytes());
+        msg.extend_from_slice(&odid.to_be_bytes());
+        for set in sets {
+            msg.extend_from_slice(set);
+        }
+        msg
+    }
+
+    //template 256 is a single sourceIPv4Address
+    fn template_set() -> Vec<u8> {
+        vec![0, 2, 0, 12, 1, 0, 0, 1, 0, 8, 0, 4]
+    }
+
+    fn data_set(addr: [u8; 4]) -> Vec<u8> {
+        let mut set = vec![1, 0, 0, 8];
+        set.extend_from_slice(&addr);
+        set
+    }
+
+    #[test]
+    fn exporter_affine_dispatch_is_stable_per_observation_domain() {
+        let a = SocketAddr::from(([10, 0, 0, 1], 4739));
+        let b = SocketAddr::from(([10, 0, 0, 2], 4739));
+
+        //what is in the message besides the ODID doesn't matter
+        let thread = exporter_thread(Protocol::IPFIX, &a, &message(7, 0, &[template_set()]), 8);
+        assert_eq!(exporter_thread(Protocol::IPFIX, &a, &message(7, 1, &[data_set([1, 2, 3, 4])]), 8), thread);
+        assert_eq!(exporter_thread(Protocol::IPFIX, &a, &message(7, 2, &[]), 8), thread);
+
+        //but a different exporter or ODID spreads out over the threads
+        let threads: HashSet<usize> = (0..64).map(|odid| exporter_thread(Protocol::IPFIX, &a, &message(odid, 0, &[]), 8)).collect();
+        assert!(threads.len() > 1);
+        let threads: HashSet<usize> = (0..64u16).map(|port| exporter_thread(Protocol::IPFIX, &SocketAddr::from(([10, 0, 0, 1], port)), &message(7, 0, &[]), 8)).collect();
+        assert!(threads.len() > 1);
+        assert_eq!(exporter_thread(Protocol::IPFIX, &b, &message(7, 0, &[]), 8), exporter_thread(Protocol::IPFIX, &b, &message(7, 9, &[template_set()]), 8));
+
+        //NetFlow v9 keeps its source id at 16, v5 its engine type and id at 20, sFlow its sub agent id after the agent address
+        let v9 = |source_id: u32, seq: u32| { let mut m = vec![0, 9, 0, 0]; m.extend_from_slice(&[0; 8]); m.extend_from_slice(&seq.to_be_bytes()); m.extend_from_slice(&source_id.to_be_bytes()); m };
+        assert_eq!(exporter_thread(Protocol::IPFIX, &a, &v9(3, 1), 8), exporter_thread(Protocol::IPFIX, &a, &v9(3, 2), 8));
+        let v5 = |engine: [u8; 2], seq: u32| { let mut m = vec![0, 5, 0, 0]; m.extend_from_slice(&[0; 12]); m.extend_from_slice(&seq.to_be_bytes()); m.extend_from_slice(&engine); m };
+        assert_eq!(exporter_thread(Protocol::IPFIX, &a, &v5([1, 2], 1), 8), exporter_thread(Protocol::IPFIX, &a, &v5([1, 2], 30), 8));
+        let sflow = |sub_agent: u32, seq: u32| { let mut m = vec![0, 0, 0, 5, 0, 0, 0, 1, 10, 0, 0, 1]; m.extend_from_slice(&sub_agent.to_be_bytes()); m.extend_from_slice(&seq.to_be_bytes()); m };
+        assert_eq!(exporter_thread(Protocol::SFlow, &a, &sflow(4, 1), 8), exporter_thread(Protocol::SFlow, &a, &sflow(4, 2), 8));
+
+        //a packet too short to have an ODID still goes somewhere
+        assert!(exporter_thread(Protocol::IPFIX, &a, &[0, 10], 8) < 8);
+    }
+
+    #[test]
+    fn templates_are_applied_before_the_data_behind_them() {
+        let port = free_port();
+        let mut collector = IPFIXCollectorHandle::start(&Config { ipfix_listen_port: port, num_threads: 4, subscriber_queue_depth: 64, ..config() }).expect("a good config should start");
+        let results = collector.subscribe(&ResultQuery::all());
+
+        //a template and data in one message, then for every other observation domain a template right before the data that needs it
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("failed to open a socket");
+        socket.send_to(&message(0, 0, &[template_set(), data_set([10, 0, 0, 0])]), (Ipv4Addr::LOCALHOST, port)).expect("failed to send");
+        for odid in 1..16 {
+            socket.send_to(&message(odid, 0, &[template_set()]), (Ipv4Addr::LOCALHOST, port)).expect("failed to send");
+            socket.send_to(&message(odid, 0, &[data_set([10, 0, 0, odid as u8])]), (Ipv4Addr::LOCALHOST, port)).expect("failed to send");
+        }
+
+        let mut decoded = 0;
+        for _ in 0..31 {
+            let info = results.recv_timeout(Duration::from_secs(5)).expect("collector should have parsed every message");
+            assert_eq!(info.set_error_count, 0, "data for ODID {} was parsed before its template", info.odid);
+            for d in info.data.iter() {
+                assert_eq!(d.fields[0].name(), Some("sourceIPv4Address"));
+                assert!(matches!(d.fields[0].data, DataType::IPV4(addr) if addr == Ipv4Addr::new(10, 0, 0, info.odid as u8)));
+                decoded += 1;
+            }
+        }
+        assert_eq!(decoded, 16);
+        collector.stop();
+    }
+
+    //a parser thread on its own, fed straight through its queue, agg_depth parsed packets can wait for the test to take them
+    fn spawn_parser(depth: usize, agg_depth: usize) -> (QueueSender<MsgToParserThread>, QueueReceiver<MsgToAggregatorThread>, Arc<TemplateRing>, JoinHandle<()>) {
+        let (parser, parser_rec) = queue::bounded(depth);
+        let (agg_snd, agg_rec) = queue::bounded(agg_depth);
+        let templates = Arc::new(TemplateRing::new());
+        let templates_clone = templates.clone();
+        let handle = thread::spawn(move || parser_thread(0, parser_rec, agg_snd, templates_clone, ValidationMode::Strict, Arc::new(AtomicU64::new(0))));
+        (parser, agg_rec, templates, handle)
+    }
+
+    fn work(exporter: SocketAddr, seq_num: u32) -> MsgToParserThread {
+        MsgToParserThread::Work(Protocol::IPFIX, exporter, message(1, seq_num, &[template_set()]).into_boxed_slice())
+    }
+
+    fn session_work(exporter: SocketAddr, seq_num: u32) -> MsgToParserThread {
+        MsgToParserThread::SessionWork(exporter, message(1, seq_num, &[template_set()]).into_boxed_slice())
+    }
+
+    fn parsed(agg_rec: &QueueReceiver<MsgToAggregatorThread>) -> PacketInfo {
+        match agg_rec.recv_timeout(Duration::from_secs(5)) {
+            Ok(MsgToAggregatorThread::Result(info)) => info,
+            _ => panic!("parser thread should have passed on a packet")
+        }
+    }
+
+    #[test]
+    fn drop_oldest_never_drops_the_end_of_a_session() {
+        let tcp_exporter = test_exporter();
+        let udp_exporter = SocketAddr::from(([127, 0, 0, 2], 4739));
+        let dropped = AtomicU64::new(0);
+        let (parser, agg_rec, templates, handle) = spawn_parser(4, 1);
+
+        parser.send(MsgToParserThread::SessionStarted(tcp_exporter, 0, None)).expect("parser thread went away");
+        parser.send(session_work(tcp_exporter, 0)).expect("parser thread went away");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while templates.get_template(tcp_exporter, 256, 1).is_none() {
+            assert!(Instant::now() < deadline, "parser thread never stored the template");
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        //nothing takes from the aggregator's queue, so the parser thread gets stuck on its second packet and its own queue fills up behind the session end
+        parser.send(session_work(tcp_exporter, 0)).expect("parser thread went away");
+        parser.send(MsgToParserThread::SessionEnded(tcp_exporter, 0)).expect("parser thread went away");
+        for seq in 0..16 {
+            hand_off(&parser, work(udp_exporter, seq), OverloadPolicy::DropOldest, &dropped);
+        }
+        assert!(dropped.load(Ordering::Relaxed) > 0);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while templates.get_template(tcp_exporter, 256, 1).is_some() {
+            assert!(Instant::now() < deadline, "the session end was dropped, its templates are still there");
+            let _ = agg_rec.recv_timeout(Duration::from_millis(5));
+        }
+        assert!(templates.get_template(udp_exporter, 256, 1).is_some());
+
+        //the parser thread might be waiting for room in the aggregator's queue again, it has to get there to see the stop
+        parser.send_priority(MsgToParserThread::Stop).expect("parser thread went away");
+        while !handle.is_finished() {
+            let _ = agg_rec.recv_timeout(Duration::from_millis(5));
+        }
+        handle.join().expect("parser thread panicked");
+    }
+
+    #[test]
+    fn drop_oldest_never_drops_a_tcp_exporters_templates() {
+        let tcp_exporter = test_exporter();
+        let udp_exporter = SocketAddr::from(([127, 0, 0, 2], 4739));
+        let dropped = AtomicU64::new(0);
+        let (parser, agg_rec, _templates, handle) = spawn_parser(4, 1);
+
+        //the exporter sends its template once at the start of the connection, then enough data to fill the parser thread's queue
+        let mut stream = message(1, 0, &[template_set()]);
+        for seq in 1..=8u32 {
+            stream.extend_from_slice(&message(1, seq, &[data_set([10, 0, 0, seq as u8])]));
+        }
+        parser.send(MsgToParserThread::SessionStarted(tcp_exporter, 0, None)).expect("parser thread went away");
+        let (_stop, connection_rec) = mpsc::channel();
+        let connection_parser = parser.clone();
+        let connection = thread::spawn(move || connection_thread(connection_rec, io::Cursor::new(stream), tcp_exporter, 0, connection_parser));
+
+        //UDP packets keep arriving faster than they are taken off the queue while the connection waits for room
+        let mut from_tcp = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while from_tcp.len() < 9 {
+            assert!(Instant::now() < deadline, "only {} of the connection's messages were parsed", from_tcp.len());
+            if !connection.is_finished() {
+                for seq in 0..4 {
+                    hand_off(&parser, work(udp_exporter, seq), OverloadPolicy::DropOldest, &dropped);
+                }
+            }
+            if let Ok(MsgToAggregatorThread::Result(info)) = agg_rec.recv_timeout(Duration::from_millis(5)) {
+                if info.exporter == tcp_exporter {
+                    from_tcp.push(info);
                 }
             }
         }
+        assert!(dropped.load(Ordering::Relaxed) > 0);
+        connection.join().expect("connection thread panicked");
+
+        //every message made it, in order, and the data behind the template could be decoded
+        assert_eq!(from_tcp.iter().map(|info| info.seq_num).collect::<Vec<_>>(), (0..=8).collect::<Vec<_>>());
+        for info in from_tcp.iter().skip(1) {
+            assert_eq!(info.set_error_count, 0);
+            assert!(matches!(info.data[0].fields[0].data, DataType::IPV4(addr) if addr == Ipv4Addr::new(10, 0, 0, info.seq_num as u8)));
+        }
+
+        parser.send_priority(MsgToParserThread::Stop).expect("parser thread went away");
+        while !handle.is_finished() {
+            let _ = agg_rec.recv_timeout(Duration::from_millis(5));
+        }
+        handle.join().expect("parser thread panicked");
+    }
+
+    #[test]
+    fn the_end_of_an_old_connection_does_not_close_a_reconnect() {
+        let tcp_exporter = test_exporter();
+        let other = SocketAddr::from(([127, 0, 0, 2], 4739));
+        let (parser, agg_rec, templates, handle) = spawn_parser(16, 16);
+        let send = |msg| parser.send(msg).expect("parser thread went away");
+
+        //the exporter reconnects from the same port before its old connection has noticed it is closed
+        send(MsgToParserThread::SessionStarted(tcp_exporter, 0, None));
+        send(work(tcp_exporter, 0));
+        send(MsgToParserThread::SessionStarted(tcp_exporter, 1, None));
+        send(work(tcp_exporter, 0));
+        send(MsgToParserThread::SessionEnded(tcp_exporter, 0));
+        //packets from someone else show when everything before them has been handled
+        send(work(other, 0));
+        for _ in 0..3 {
+            parsed(&agg_rec);
+        }
+        assert!(templates.get_template(tcp_exporter, 256, 1).is_some());
+
+        send(MsgToParserThread::SessionEnded(tcp_exporter, 1));
+        send(work(other, 1));
+        parsed(&agg_rec);
+        assert!(templates.get_template(tcp_exporter, 256, 1).is_none());
+
+        parser.send_priority(MsgToParserThread::Stop).expect("parser thread went away");
+        handle.join().expect("parser thread panicked");
+    }
+
+    #[test]
+    fn threads_stop_quietly_once_the_other_end_of_their_queue_is_gone() {
+        //a parser thread whose aggregator has stopped
+        let (parser, agg_rec, _templates, handle) = spawn_parser(4, 1);
+        drop(agg_rec);
+        parser.send(work(test_exporter(), 0)).expect("parser thread went away");
+        handle.join().expect("parser thread panicked");
+
+        //a listener handing a packet to a parser thread that has stopped
+        let dropped = AtomicU64::new(0);
+        for policy in [OverloadPolicy::Block, OverloadPolicy::DropNewest, OverloadPolicy::DropOldest] {
+            assert!(!hand_off(&parser, work(test_exporter(), 0), policy, &dropped));
+        }
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+
+        //and a connection with messages left for it
+        let (_stop, connection_rec) = mpsc::channel();
+        let stream = io::Cursor::new([message(1, 0, &[template_set()]), message(1, 1, &[template_set()])].concat());
+        let connection = thread::spawn(move || connection_thread(connection_rec, stream, test_exporter(), 0, parser));
+        connection.join().expect("connection thread panicked");
+
+        //stopping a stopped collector does nothing
+        let mut collector = IPFIXCollectorHandle::start(&config()).expect("a good config should start");
+        collector.stop();
+        collector.stop();
+    }
+
+    //a certificate authority and the key it signs with
+    fn certificate_authority(name: &str) -> (rcgen::Certificate, KeyPair) {
+        let key = KeyPair::generate().expect("failed to generate a key");
+        let mut params = CertificateParams::new(Vec::<String>::new()).expect("bad certificate parameters");
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, name);
+        (params.self_signed(&key).expect("failed to sign the CA certificate"), key)
+    }
+
+    //a certificate for name signed by ca, for the collector (ServerAuth) or an exporter (ClientAuth)
+    fn issue(name: &str, usage: ExtendedKeyUsagePurpose, ca: &(rcgen::Certificate, KeyPair)) -> (rcgen::Certificate, KeyPair) {
+        let key = KeyPair::generate().expect("failed to generate a key");
+        let mut params = CertificateParams::new(vec![format!("{}.example", name)]).expect("bad certificate parameters");
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.extended_key_usages = vec![usage];
+        (params.signed_by(&key, &ca.0, &ca.1).expect("failed to sign the certificate"), key)
+    }
+
+    //a collector with a single TLS listener, its certificate comes from ca and exporters need one from client_ca if that is set
+    //returns the collector and the listener's port
+    fn start_tls_collector(name: &str, ca: &(rcgen::Certificate, KeyPair), client_ca: Option<&rcgen::Certificate>) -> (IPFIXCollectorHandle, u16) {
+        let dir = test_path(name);
+        fs::create_dir_all(&dir).expect("failed to make a directory for the certificates");
+        let (cert, key) = issue("collector", ExtendedKeyUsagePurpose::ServerAuth, ca);
+        fs::write(dir.join("collector.pem"), cert.pem()).expect("failed to write the collector certificate");
+        fs::write(dir.join("collector.key"), key.serialize_pem()).expect("failed to write the collector key");
+        let client_ca = client_ca.map(|c| {
+            fs::write(dir.join("client_ca.pem"), c.pem()).expect("failed to write the client CA certificate");
+            dir.join("client_ca.pem")
+        });
+
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).and_then(|s| s.local_addr()).expect("failed to find a free port").port();
+        let tls = TlsConfig { certificate_chain: dir.join("collector.pem"), private_key: dir.join("collector.key"), client_ca };
+        let listener = Listener { addr: Ipv4Addr::LOCALHOST, port, protocol: Protocol::IPFIX, transport: Transport::TLS(tls) };
+        let collector = IPFIXCollectorHandle::start(&Config { listeners: vec![listener], ..config() }).expect("a good config should start");
+        //start has read everything it needs
+        let _ = fs::remove_dir_all(&dir);
+        (collector, port)
+    }
+
+    //connects over TLS trusting ca, presenting client if there is one, sends a template and data, then hangs up
+    //returns once the collector has closed the connection, with the error the collector sent if it turned the exporter down
+    fn send_over_tls(port: u16, ca: &rcgen::Certificate, client: Option<&(rcgen::Certificate, KeyPair)>) -> io::Result<()> {
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der().clone()).expect("rustls won't use the CA certificate");
+        let builder = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions().expect("no TLS versions")
+            .with_root_certificates(roots);
+        let config = match client {
+            None => builder.with_no_client_auth(),
+            Some((cert, key)) => builder.with_client_auth_cert(vec![cert.der().clone()], PrivateKeyDer::Pkcs8(key.serialize_der().into())).expect("rustls won't use the exporter certificate")
+        };
+        let server_name = ServerName::try_from("collector.example").expect("bad server name");
+        let conn = ClientConnection::new(Arc::new(config), server_name).expect("failed to start TLS connection");
+        let mut tls = StreamOwned::new(conn, TcpStream::connect((Ipv4Addr::LOCALHOST, port))?);
+
+        tls.write_all(&message(1, 0, &[template_set(), data_set([10, 0, 0, 1])]))?;
+        tls.conn.send_close_notify();
+        tls.flush()?;
+        //the collector drops the connection without a close notify of its own, so a clean hang up is an unexpected EOF
+        match tls.read_to_end(&mut Vec::new()) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(()),
+            r => r.map(|_| ())
+        }
+    }
+
+    #[test]
+    fn tls_exporters_are_identified_by_their_certificate() {
+        let ca = certificate_authority("test CA");
+        let (mut collector, port) = start_tls_collector("tls-client-auth", &ca, Some(&ca.0));
+        let results = collector.subscribe(&ResultQuery::all());
+        let exporter = issue("exporter1", ExtendedKeyUsagePurpose::ClientAuth, &ca);
+
+        send_over_tls(port, &ca.0, Some(&exporter)).expect("the collector should have taken the exporter's certificate");
+        let info = results.recv_timeout(Duration::from_secs(5)).expect("collector should have parsed the message");
+        assert_eq!(info.data.len(), 1);
+        let identity = info.exporter_identity.expect("the exporter's certificate should be attached");
+        assert_eq!(identity.subject, "CN=exporter1");
+        assert_eq!(identity.subject_alt_names, vec!["exporter1.example".to_string()]);
+        assert_eq!(identity.certificate, exporter.0.der().to_vec());
+        collector.stop();
+    }
+
+    #[test]
+    fn tls_exporters_with_a_certificate_from_another_ca_are_turned_down() {
+        let ca = certificate_authority("test CA");
+        let (mut collector, port) = start_tls_collector("tls-wrong-ca", &ca, Some(&ca.0));
+        let results = collector.subscribe(&ResultQuery::all());
+
+        let other_ca = certificate_authority("someone else's CA");
+        let stranger = issue("stranger", ExtendedKeyUsagePurpose::ClientAuth, &other_ca);
+        assert!(send_over_tls(port, &ca.0, Some(&stranger)).is_err());
+        //no certificate at all is no better
+        assert!(send_over_tls(port, &ca.0, None).is_err());
+        assert!(results.recv_timeout(Duration::from_millis(200)).is_err());
+
+        //the listener is still there for exporters it does know
+        let exporter = issue("exporter1", ExtendedKeyUsagePurpose::ClientAuth, &ca);
+        send_over_tls(port, &ca.0, Some(&exporter)).expect("the collector should have taken the exporter's certificate");
+        let info = results.recv_timeout(Duration::from_secs(5)).expect("collector should have parsed the message");
+        assert_eq!(info.exporter_identity.map(|i| i.subject.clone()), Some("CN=exporter1".to_string()));
+        collector.stop();
+    }
+
+    #[test]
+    fn tls_exporters_without_a_certificate_have_no_identity() {
+        let ca = certificate_authority("test CA");
+        let (mut collector, port) = start_tls_collector("tls-no-client-auth", &ca, None);
+        let results = collector.subscribe(&ResultQuery::all());
+
+        send_over_tls(port, &ca.0, None).expect("the collector doesn't ask for a certificate");
+        let info = results.recv_timeout(Duration::from_secs(5)).expect("collector should have parsed the message");
+        assert_eq!(info.data.len(), 1);
+        assert!(info.exporter_identity.is_none());
+        collector.stop();
     }
 }
@@ -0,0 +1,769 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::templates::IPFIXField;
+
+//abstract data types from RFC 7011 section 6.1, these say how a field's bytes should be interpreted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbstractDataType {
+    OctetArray,
+    Unsigned8,
+    Unsigned16,
+    Unsigned32,
+    Unsigned64,
+    Signed8,
+    Signed16,
+    Signed32,
+    Signed64,
+    Float32,
+    Float64,
+    Boolean,
+    MacAddress,
+    String,
+    DateTimeSeconds,
+    DateTimeMilliseconds,
+    DateTimeMicroseconds,
+    DateTimeNanoseconds,
+    Ipv4Address,
+    Ipv6Address,
+    BasicList,
+    SubTemplateList,
+    SubTemplateMultiList
+}
+
+impl AbstractDataType {
+    //the number of bytes a value of this type takes up when it isn't using reduced size encoding, None for types without a fixed size
+    pub fn natural_width(&self) -> Option<u16> {
+        match self {
+            AbstractDataType::Unsigned8 | AbstractDataType::Signed8 | AbstractDataType::Boolean => Some(1),
+            AbstractDataType::Unsigned16 | AbstractDataType::Signed16 => Some(2),
+            AbstractDataType::Unsigned32 | AbstractDataType::Signed32 | AbstractDataType::Float32 => Some(4),
+            AbstractDataType::DateTimeSeconds | AbstractDataType::Ipv4Address => Some(4),
+            AbstractDataType::Unsigned64 | AbstractDataType::Signed64 | AbstractDataType::Float64 => Some(8),
+            AbstractDataType::DateTimeMilliseconds | AbstractDataType::DateTimeMicroseconds | AbstractDataType::DateTimeNanoseconds => Some(8),
+            AbstractDataType::MacAddress => Some(6),
+            AbstractDataType::Ipv6Address => Some(16),
+            _ => None
+        }
+    }
+
+    //the names used in the "Abstract Data Type" column of the IANA registry
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "octetArray" => Some(AbstractDataType::OctetArray),
+            "unsigned8" => Some(AbstractDataType::Unsigned8),
+            "unsigned16" => Some(AbstractDataType::Unsigned16),
+            "unsigned32" => Some(AbstractDataType::Unsigned32),
+            "unsigned64" => Some(AbstractDataType::Unsigned64),
+            "signed8" => Some(AbstractDataType::Signed8),
+            "signed16" => Some(AbstractDataType::Signed16),
+            "signed32" => Some(AbstractDataType::Signed32),
+            "signed64" => Some(AbstractDataType::Signed64),
+            "float32" => Some(AbstractDataType::Float32),
+            "float64" => Some(AbstractDataType::Float64),
+            "boolean" => Some(AbstractDataType::Boolean),
+            "macAddress" => Some(AbstractDataType::MacAddress),
+            "string" => Some(AbstractDataType::String),
+            "dateTimeSeconds" => Some(AbstractDataType::DateTimeSeconds),
+            "dateTimeMilliseconds" => Some(AbstractDataType::DateTimeMilliseconds),
+            "dateTimeMicroseconds" => Some(AbstractDataType::DateTimeMicroseconds),
+            "dateTimeNanoseconds" => Some(AbstractDataType::DateTimeNanoseconds),
+            "ipv4Address" => Some(AbstractDataType::Ipv4Address),
+            "ipv6Address" => Some(AbstractDataType::Ipv6Address),
+            "basicList" => Some(AbstractDataType::BasicList),
+            "subTemplateList" => Some(AbstractDataType::SubTemplateList),
+            "subTemplateMultiList" => Some(AbstractDataType::SubTemplateMultiList),
+            _ => None
+        }
+    }
+}
+
+//data type semantics from RFC 7012 section 3.2, these say what a value means rather than how it is encoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataTypeSemantics {
+    Default,
+    Quantity,
+    TotalCounter,
+    DeltaCounter,
+    Identifier,
+    Flags,
+    List,
+    SnmpCounter,
+    SnmpGauge
+}
+
+impl DataTypeSemantics {
+    //the names used in the "Data Type Semantics" column of the IANA registry, an empty column means default
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "" | "default" => Some(DataTypeSemantics::Default),
+            "quantity" => Some(DataTypeSemantics::Quantity),
+            "totalCounter" => Some(DataTypeSemantics::TotalCounter),
+            "deltaCounter" => Some(DataTypeSemantics::DeltaCounter),
+            "identifier" => Some(DataTypeSemantics::Identifier),
+            "flags" => Some(DataTypeSemantics::Flags),
+            "list" => Some(DataTypeSemantics::List),
+            "snmpCounter" => Some(DataTypeSemantics::SnmpCounter),
+            "snmpGauge" => Some(DataTypeSemantics::SnmpGauge),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InformationElement {
+    pub id: u16,
+    pub en: u32, //0 for IANA elements
+    pub name: String,
+    pub data_type: AbstractDataType,
+    pub semantics: DataTypeSemantics,
+    pub units: Option<String>
+}
+
+//(en, id) -> InformationElement
+pub struct IERegistry {
+    elements: HashMap<(u32, u16), Arc<InformationElement>>
+}
+
+impl Default for IERegistry {
+    fn default() -> Self {
+        Self::iana()
+    }
+}
+
+impl IERegistry {
+    //a registry with nothing in it, every field will be decoded based on its width alone
+    pub fn empty() -> Self {
+        IERegistry { elements: HashMap::new() }
+    }
+
+    //a registry holding the IANA IPFIX information elements (enterprise number 0)
+    pub fn iana() -> Self {
+        let mut reg = IERegistry::empty();
+        for (id, name, data_type, semantics, units) in IANA_ELEMENTS.iter() {
+            reg.insert(InformationElement {
+                id: *id,
+                en: 0,
+                name: String::from(*name),
+                data_type: *data_type,
+                semantics: *semantics,
+                units: units.map(String::from)
+            });
+        }
+        reg
+    }
+
+    //adds an element, replacing any element that already has the same (en, id)
+    pub fn insert(&mut self, element: InformationElement) {
+        self.elements.insert((element.en, element.id), Arc::new(element));
+    }
+
+    pub fn get(&self, en: u32, id: u16) -> Option<&Arc<InformationElement>> {
+        self.elements.get(&(en, id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    //reads element definitions from a CSV file laid out like the IANA registry export (ipfix-information-elements.csv)
+    //every element in the file is registered under en, returns how many elements were added
+    pub fn load_csv_file<P: AsRef<Path>>(&mut self, path: P, en: u32) -> Result<usize, IELoadError> {
+        let text = fs::read_to_string(path).map_err(IELoadError::Io)?;
+        self.load_csv(&text, en)
+    }
+
+    //same as load_csv_file, but for CSV that is already in memory
+    //columns are found by their header, only ElementID, Name and Abstract Data Type have to be there, Data Type Semantics and Units are optional
+    pub fn load_csv(&mut self, text: &str, en: u32) -> Result<usize, IELoadError> {
+        let mut records = split_csv(text).into_iter();
+        let header = match records.next() {
+            Some((_line, header)) => header,
+            None => { return Err(IELoadError::MissingColumn("ElementID")); }
+        };
+
+        let column = |name: &str| header.iter().position(|h| h.trim() == name);
+        let id_col = column("ElementID").ok_or(IELoadError::MissingColumn("ElementID"))?;
+        let name_col = column("Name").ok_or(IELoadError::MissingColumn("Name"))?;
+        let type_col = column("Abstract Data Type").ok_or(IELoadError::MissingColumn("Abstract Data Type"))?;
+        let semantics_col = column("Data Type Semantics");
+        let units_col = column("Units");
+
+        let mut elements = Vec::new();
+        for (line, record) in records {
+            let cell = |col: usize| record.get(col).map(|c| c.trim()).unwrap_or("");
+
+            //the IANA export also lists reserved and unassigned ranges ("434-32767"), those don't describe an element
+            let name = cell(name_col);
+            if name.is_empty() || name == "Reserved" || name == "Unassigned" || cell(id_col).contains('-') {
+                continue;
+            }
+
+            //the top bit of an element id is the enterprise bit in a template, so real ids stop at 32767
+            let id = match cell(id_col).parse::<u16>() {
+                Ok(id) if id & 0x8000 == 0 => id,
+                _ => { return Err(IELoadError::BadRow { line, reason: format!("\"{}\" is not an element id", cell(id_col)) }); }
+            };
+
+            let data_type = AbstractDataType::from_name(cell(type_col))
+                .ok_or_else(|| IELoadError::BadRow { line, reason: format!("\"{}\" is not an abstract data type", cell(type_col)) })?;
+
+            let semantics = match semantics_col {
+                Some(col) => DataTypeSemantics::from_name(cell(col))
+                    .ok_or_else(|| IELoadError::BadRow { line, reason: format!("\"{}\" is not a data type semantic", cell(col)) })?,
+                None => DataTypeSemantics::Default
+            };
+
+            let units = units_col.map(cell).filter(|u| !u.is_empty()).map(String::from);
+
+            elements.push(InformationElement { id, en, name: String::from(name), data_type, semantics, units });
+        }
+
+        //only touch the registry once the whole file has been read, so a bad row doesn't leave half a file loaded
+        let count = elements.len();
+        for e in elements {
+            self.insert(e);
+        }
+        Ok(count)
+    }
+
+    //looks up the element for each template field, so a data set only has to hit the map once per field instead of once per field per record
+    pub fn resolve(&self, fields: &[IPFIXField]) -> Vec<Option<Arc<InformationElement>>> {
+        fields.iter().map(|f| self.get(f.en, f.field_id).cloned()).collect()
+    }
+}
+
+//what can go wrong while loading element definitions
+#[derive(Debug)]
+pub enum IELoadError {
+    Io(std::io::Error),
+    MissingColumn(&'static str), //the header row doesn't have a column we need
+    BadRow { line: usize, reason: String } //line is the line the record starts on, counting the header as line 1
+}
+
+impl fmt::Display for IELoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IELoadError::Io(e) => write!(f, "failed to read element definitions: {}", e),
+            IELoadError::MissingColumn(name) => write!(f, "element definitions have no \"{}\" column", name),
+            IELoadError::BadRow { line, reason } => write!(f, "element definition on line {}: {}", line, reason)
+        }
+    }
+}
+
+impl std::error::Error for IELoadError {}
+
+//splits CSV text into records of cells, along with the line each record starts on
+//cells can be quoted, and quoted cells can hold commas, newlines and doubled quotes (the IANA descriptions use all of them)
+fn split_csv(text: &str) -> Vec<(usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut cell = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => { chars.next(); cell.push('"'); },
+                '"' => { in_quotes = false; },
+                '\n' => { line += 1; cell.push(c); },
+                _ => { cell.push(c); }
+            }
+            continue;
+        }
+
+        match c {
+            '"' => { in_quotes = true; },
+            ',' => { record.push(std::mem::take(&mut cell)); },
+            '\r' => {},
+            '\n' => {
+                record.push(std::mem::take(&mut cell));
+                //skip blank lines
+                if record.len() > 1 || !record[0].is_empty() {
+                    records.push((record_line, std::mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                record_line = line;
+            },
+            _ => { cell.push(c); }
+        }
+    }
+
+    //the last line doesn't need a newline
+    if !cell.is_empty() || !record.is_empty() {
+        record.push(cell);
+        records.push((record_line, record));
+    }
+
+    records
+}
+
+//IANA "IPFIX Information Elements" registry, https://www.iana.org/assignments/ipfix/ipfix.xhtml
+//(id, name, abstract data type, data type semantics, units)
+static IANA_ELEMENTS: &[(u16, &str, AbstractDataType, DataTypeSemantics, Option<&str>)] = &[
+    (1, "octetDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("octets")),
+    (2, "packetDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("packets")),
+    (3, "deltaFlowCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("flows")),
+    (4, "protocolIdentifier", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (5, "ipClassOfService", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (6, "tcpControlBits", AbstractDataType::Unsigned16, DataTypeSemantics::Flags, None),
+    (7, "sourceTransportPort", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (8, "sourceIPv4Address", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (9, "sourceIPv4PrefixLength", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("bits")),
+    (10, "ingressInterface", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (11, "destinationTransportPort", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (12, "destinationIPv4Address", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (13, "destinationIPv4PrefixLength", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("bits")),
+    (14, "egressInterface", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (15, "ipNextHopIPv4Address", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (16, "bgpSourceAsNumber", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (17, "bgpDestinationAsNumber", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (18, "bgpNextHopIPv4Address", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (19, "postMCastPacketDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("packets")),
+    (20, "postMCastOctetDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("octets")),
+    (21, "flowEndSysUpTime", AbstractDataType::Unsigned32, DataTypeSemantics::Default, Some("milliseconds")),
+    (22, "flowStartSysUpTime", AbstractDataType::Unsigned32, DataTypeSemantics::Default, Some("milliseconds")),
+    (23, "postOctetDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("octets")),
+    (24, "postPacketDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("packets")),
+    (25, "minimumIpTotalLength", AbstractDataType::Unsigned64, DataTypeSemantics::Default, Some("octets")),
+    (26, "maximumIpTotalLength", AbstractDataType::Unsigned64, DataTypeSemantics::Default, Some("octets")),
+    (27, "sourceIPv6Address", AbstractDataType::Ipv6Address, DataTypeSemantics::Identifier, None),
+    (28, "destinationIPv6Address", AbstractDataType::Ipv6Address, DataTypeSemantics::Identifier, None),
+    (29, "sourceIPv6PrefixLength", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("bits")),
+    (30, "destinationIPv6PrefixLength", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("bits")),
+    (31, "flowLabelIPv6", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (32, "icmpTypeCodeIPv4", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (33, "igmpType", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (34, "samplingInterval", AbstractDataType::Unsigned32, DataTypeSemantics::Quantity, Some("packets")),
+    (35, "samplingAlgorithm", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (36, "flowActiveTimeout", AbstractDataType::Unsigned16, DataTypeSemantics::Default, Some("seconds")),
+    (37, "flowIdleTimeout", AbstractDataType::Unsigned16, DataTypeSemantics::Default, Some("seconds")),
+    (38, "engineType", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (39, "engineId", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (40, "exportedOctetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (41, "exportedMessageTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("messages")),
+    (42, "exportedFlowRecordTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("flows")),
+    (43, "ipv4RouterSc", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (44, "sourceIPv4Prefix", AbstractDataType::Ipv4Address, DataTypeSemantics::Default, None),
+    (45, "destinationIPv4Prefix", AbstractDataType::Ipv4Address, DataTypeSemantics::Default, None),
+    (46, "mplsTopLabelType", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (47, "mplsTopLabelIPv4Address", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (48, "samplerId", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (49, "samplerMode", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (50, "samplerRandomInterval", AbstractDataType::Unsigned32, DataTypeSemantics::Quantity, None),
+    (51, "classId", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (52, "minimumTTL", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("hops")),
+    (53, "maximumTTL", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("hops")),
+    (54, "fragmentIdentification", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (55, "postIpClassOfService", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (56, "sourceMacAddress", AbstractDataType::MacAddress, DataTypeSemantics::Identifier, None),
+    (57, "postDestinationMacAddress", AbstractDataType::MacAddress, DataTypeSemantics::Identifier, None),
+    (58, "vlanId", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (59, "postVlanId", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (60, "ipVersion", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (61, "flowDirection", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (62, "ipNextHopIPv6Address", AbstractDataType::Ipv6Address, DataTypeSemantics::Identifier, None),
+    (63, "bgpNextHopIPv6Address", AbstractDataType::Ipv6Address, DataTypeSemantics::Identifier, None),
+    (64, "ipv6ExtensionHeaders", AbstractDataType::Unsigned32, DataTypeSemantics::Flags, None),
+    (70, "mplsTopLabelStackSection", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (71, "mplsLabelStackSection2", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (72, "mplsLabelStackSection3", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (73, "mplsLabelStackSection4", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (74, "mplsLabelStackSection5", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (75, "mplsLabelStackSection6", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (76, "mplsLabelStackSection7", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (77, "mplsLabelStackSection8", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (78, "mplsLabelStackSection9", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (79, "mplsLabelStackSection10", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (80, "destinationMacAddress", AbstractDataType::MacAddress, DataTypeSemantics::Identifier, None),
+    (81, "postSourceMacAddress", AbstractDataType::MacAddress, DataTypeSemantics::Identifier, None),
+    (82, "interfaceName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (83, "interfaceDescription", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (84, "samplerName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (85, "octetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (86, "packetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (87, "flagsAndSamplerId", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (88, "fragmentOffset", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (89, "forwardingStatus", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (90, "mplsVpnRouteDistinguisher", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (91, "mplsTopLabelPrefixLength", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("bits")),
+    (92, "srcTrafficIndex", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (93, "dstTrafficIndex", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (94, "applicationDescription", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (95, "applicationId", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (96, "applicationName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (98, "postIpDiffServCodePoint", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (99, "multicastReplicationFactor", AbstractDataType::Unsigned32, DataTypeSemantics::Quantity, None),
+    (100, "className", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (101, "classificationEngineId", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (102, "layer2packetSectionOffset", AbstractDataType::Unsigned16, DataTypeSemantics::Quantity, None),
+    (103, "layer2packetSectionSize", AbstractDataType::Unsigned16, DataTypeSemantics::Quantity, None),
+    (104, "layer2packetSectionData", AbstractDataType::OctetArray, DataTypeSemantics::Default, None),
+    (128, "bgpNextAdjacentAsNumber", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (129, "bgpPrevAdjacentAsNumber", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (130, "exporterIPv4Address", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (131, "exporterIPv6Address", AbstractDataType::Ipv6Address, DataTypeSemantics::Identifier, None),
+    (132, "droppedOctetDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("octets")),
+    (133, "droppedPacketDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("packets")),
+    (134, "droppedOctetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (135, "droppedPacketTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (136, "flowEndReason", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (137, "commonPropertiesId", AbstractDataType::Unsigned64, DataTypeSemantics::Identifier, None),
+    (138, "observationPointId", AbstractDataType::Unsigned64, DataTypeSemantics::Identifier, None),
+    (139, "icmpTypeCodeIPv6", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (140, "mplsTopLabelIPv6Address", AbstractDataType::Ipv6Address, DataTypeSemantics::Identifier, None),
+    (141, "lineCardId", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (142, "portId", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (143, "meteringProcessId", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (144, "exportingProcessId", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (145, "templateId", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (146, "wlanChannelId", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (147, "wlanSSID", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (148, "flowId", AbstractDataType::Unsigned64, DataTypeSemantics::Identifier, None),
+    (149, "observationDomainId", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (150, "flowStartSeconds", AbstractDataType::DateTimeSeconds, DataTypeSemantics::Default, Some("seconds")),
+    (151, "flowEndSeconds", AbstractDataType::DateTimeSeconds, DataTypeSemantics::Default, Some("seconds")),
+    (152, "flowStartMilliseconds", AbstractDataType::DateTimeMilliseconds, DataTypeSemantics::Default, Some("milliseconds")),
+    (153, "flowEndMilliseconds", AbstractDataType::DateTimeMilliseconds, DataTypeSemantics::Default, Some("milliseconds")),
+    (154, "flowStartMicroseconds", AbstractDataType::DateTimeMicroseconds, DataTypeSemantics::Default, Some("microseconds")),
+    (155, "flowEndMicroseconds", AbstractDataType::DateTimeMicroseconds, DataTypeSemantics::Default, Some("microseconds")),
+    (156, "flowStartNanoseconds", AbstractDataType::DateTimeNanoseconds, DataTypeSemantics::Default, Some("nanoseconds")),
+    (157, "flowEndNanoseconds", AbstractDataType::DateTimeNanoseconds, DataTypeSemantics::Default, Some("nanoseconds")),
+    (158, "flowStartDeltaMicroseconds", AbstractDataType::Unsigned32, DataTypeSemantics::Default, Some("microseconds")),
+    (159, "flowEndDeltaMicroseconds", AbstractDataType::Unsigned32, DataTypeSemantics::Default, Some("microseconds")),
+    (160, "systemInitTimeMilliseconds", AbstractDataType::DateTimeMilliseconds, DataTypeSemantics::Default, Some("milliseconds")),
+    (161, "flowDurationMilliseconds", AbstractDataType::Unsigned32, DataTypeSemantics::Default, Some("milliseconds")),
+    (162, "flowDurationMicroseconds", AbstractDataType::Unsigned32, DataTypeSemantics::Default, Some("microseconds")),
+    (163, "observedFlowTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("flows")),
+    (164, "ignoredPacketTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (165, "ignoredOctetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (166, "notSentFlowTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("flows")),
+    (167, "notSentPacketTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (168, "notSentOctetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (169, "destinationIPv6Prefix", AbstractDataType::Ipv6Address, DataTypeSemantics::Default, None),
+    (170, "sourceIPv6Prefix", AbstractDataType::Ipv6Address, DataTypeSemantics::Default, None),
+    (171, "postOctetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (172, "postPacketTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (173, "flowKeyIndicator", AbstractDataType::Unsigned64, DataTypeSemantics::Flags, None),
+    (174, "postMCastPacketTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (175, "postMCastOctetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (176, "icmpTypeIPv4", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (177, "icmpCodeIPv4", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (178, "icmpTypeIPv6", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (179, "icmpCodeIPv6", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (180, "udpSourcePort", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (181, "udpDestinationPort", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (182, "tcpSourcePort", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (183, "tcpDestinationPort", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (184, "tcpSequenceNumber", AbstractDataType::Unsigned32, DataTypeSemantics::Default, None),
+    (185, "tcpAcknowledgementNumber", AbstractDataType::Unsigned32, DataTypeSemantics::Default, None),
+    (186, "tcpWindowSize", AbstractDataType::Unsigned16, DataTypeSemantics::Default, None),
+    (187, "tcpUrgentPointer", AbstractDataType::Unsigned16, DataTypeSemantics::Default, None),
+    (188, "tcpHeaderLength", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("octets")),
+    (189, "ipHeaderLength", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("octets")),
+    (190, "totalLengthIPv4", AbstractDataType::Unsigned16, DataTypeSemantics::Default, Some("octets")),
+    (191, "payloadLengthIPv6", AbstractDataType::Unsigned16, DataTypeSemantics::Default, Some("octets")),
+    (192, "ipTTL", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("hops")),
+    (193, "nextHeaderIPv6", AbstractDataType::Unsigned8, DataTypeSemantics::Default, None),
+    (194, "mplsPayloadLength", AbstractDataType::Unsigned32, DataTypeSemantics::Default, Some("octets")),
+    (195, "ipDiffServCodePoint", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (196, "ipPrecedence", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (197, "fragmentFlags", AbstractDataType::Unsigned8, DataTypeSemantics::Flags, None),
+    (198, "octetDeltaSumOfSquares", AbstractDataType::Unsigned64, DataTypeSemantics::Default, None),
+    (199, "octetTotalSumOfSquares", AbstractDataType::Unsigned64, DataTypeSemantics::Default, Some("octets")),
+    (200, "mplsTopLabelTTL", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("hops")),
+    (201, "mplsLabelStackLength", AbstractDataType::Unsigned32, DataTypeSemantics::Default, Some("octets")),
+    (202, "mplsLabelStackDepth", AbstractDataType::Unsigned32, DataTypeSemantics::Default, Some("label stack entries")),
+    (203, "mplsTopLabelExp", AbstractDataType::Unsigned8, DataTypeSemantics::Flags, None),
+    (204, "ipPayloadLength", AbstractDataType::Unsigned32, DataTypeSemantics::Default, Some("octets")),
+    (205, "udpMessageLength", AbstractDataType::Unsigned16, DataTypeSemantics::Default, Some("octets")),
+    (206, "isMulticast", AbstractDataType::Unsigned8, DataTypeSemantics::Flags, None),
+    (207, "ipv4IHL", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("4-octet words")),
+    (208, "ipv4Options", AbstractDataType::Unsigned32, DataTypeSemantics::Flags, None),
+    (209, "tcpOptions", AbstractDataType::Unsigned64, DataTypeSemantics::Flags, None),
+    (210, "paddingOctets", AbstractDataType::OctetArray, DataTypeSemantics::Default, None),
+    (211, "collectorIPv4Address", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (212, "collectorIPv6Address", AbstractDataType::Ipv6Address, DataTypeSemantics::Identifier, None),
+    (213, "exportInterface", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (214, "exportProtocolVersion", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (215, "exportTransportProtocol", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (216, "collectorTransportPort", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (217, "exporterTransportPort", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (218, "tcpSynTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (219, "tcpFinTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (220, "tcpRstTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (221, "tcpPshTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (222, "tcpAckTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (223, "tcpUrgTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (224, "ipTotalLength", AbstractDataType::Unsigned64, DataTypeSemantics::Default, Some("octets")),
+    (225, "postNATSourceIPv4Address", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (226, "postNATDestinationIPv4Address", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (227, "postNAPTSourceTransportPort", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (228, "postNAPTDestinationTransportPort", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (229, "natOriginatingAddressRealm", AbstractDataType::Unsigned8, DataTypeSemantics::Flags, None),
+    (230, "natEvent", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (231, "initiatorOctets", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("octets")),
+    (232, "responderOctets", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("octets")),
+    (233, "firewallEvent", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (234, "ingressVRFID", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (235, "egressVRFID", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (236, "VRFname", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (237, "postMplsTopLabelExp", AbstractDataType::Unsigned8, DataTypeSemantics::Flags, None),
+    (238, "tcpWindowScale", AbstractDataType::Unsigned16, DataTypeSemantics::Default, None),
+    (239, "biflowDirection", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (240, "ethernetHeaderLength", AbstractDataType::Unsigned8, DataTypeSemantics::Default, Some("octets")),
+    (241, "ethernetPayloadLength", AbstractDataType::Unsigned16, DataTypeSemantics::Default, Some("octets")),
+    (242, "ethernetTotalLength", AbstractDataType::Unsigned16, DataTypeSemantics::Default, Some("octets")),
+    (243, "dot1qVlanId", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (244, "dot1qPriority", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (245, "dot1qCustomerVlanId", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (246, "dot1qCustomerPriority", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (247, "metroEvcId", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (248, "metroEvcType", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (249, "pseudoWireId", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (250, "pseudoWireType", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (251, "pseudoWireControlWord", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (252, "ingressPhysicalInterface", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (253, "egressPhysicalInterface", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (254, "postDot1qVlanId", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (255, "postDot1qCustomerVlanId", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (256, "ethernetType", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (257, "postIpPrecedence", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (258, "collectionTimeMilliseconds", AbstractDataType::DateTimeMilliseconds, DataTypeSemantics::Default, Some("milliseconds")),
+    (259, "exportSctpStreamId", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (260, "maxExportSeconds", AbstractDataType::DateTimeSeconds, DataTypeSemantics::Default, Some("seconds")),
+    (261, "maxFlowEndSeconds", AbstractDataType::DateTimeSeconds, DataTypeSemantics::Default, Some("seconds")),
+    (262, "messageMD5Checksum", AbstractDataType::OctetArray, DataTypeSemantics::Default, None),
+    (263, "messageScope", AbstractDataType::Unsigned8, DataTypeSemantics::Default, None),
+    (264, "minExportSeconds", AbstractDataType::DateTimeSeconds, DataTypeSemantics::Default, Some("seconds")),
+    (265, "minFlowStartSeconds", AbstractDataType::DateTimeSeconds, DataTypeSemantics::Default, Some("seconds")),
+    (266, "opaqueOctets", AbstractDataType::OctetArray, DataTypeSemantics::Default, None),
+    (267, "sessionScope", AbstractDataType::Unsigned8, DataTypeSemantics::Default, None),
+    (268, "maxFlowEndMicroseconds", AbstractDataType::DateTimeMicroseconds, DataTypeSemantics::Default, Some("microseconds")),
+    (269, "maxFlowEndMilliseconds", AbstractDataType::DateTimeMilliseconds, DataTypeSemantics::Default, Some("milliseconds")),
+    (270, "maxFlowEndNanoseconds", AbstractDataType::DateTimeNanoseconds, DataTypeSemantics::Default, Some("nanoseconds")),
+    (271, "minFlowStartMicroseconds", AbstractDataType::DateTimeMicroseconds, DataTypeSemantics::Default, Some("microseconds")),
+    (272, "minFlowStartMilliseconds", AbstractDataType::DateTimeMilliseconds, DataTypeSemantics::Default, Some("milliseconds")),
+    (273, "minFlowStartNanoseconds", AbstractDataType::DateTimeNanoseconds, DataTypeSemantics::Default, Some("nanoseconds")),
+    (274, "collectorCertificate", AbstractDataType::OctetArray, DataTypeSemantics::Default, None),
+    (275, "exporterCertificate", AbstractDataType::OctetArray, DataTypeSemantics::Default, None),
+    (276, "dataRecordsReliability", AbstractDataType::Boolean, DataTypeSemantics::Identifier, None),
+    (277, "observationPointType", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (278, "newConnectionDeltaCount", AbstractDataType::Unsigned32, DataTypeSemantics::DeltaCounter, None),
+    (279, "connectionSumDurationSeconds", AbstractDataType::Unsigned64, DataTypeSemantics::Default, Some("seconds")),
+    (280, "connectionTransactionId", AbstractDataType::Unsigned64, DataTypeSemantics::Identifier, None),
+    (281, "postNATSourceIPv6Address", AbstractDataType::Ipv6Address, DataTypeSemantics::Identifier, None),
+    (282, "postNATDestinationIPv6Address", AbstractDataType::Ipv6Address, DataTypeSemantics::Identifier, None),
+    (283, "natPoolId", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (284, "natPoolName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (285, "anonymizationFlags", AbstractDataType::Unsigned16, DataTypeSemantics::Flags, None),
+    (286, "anonymizationTechnique", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (287, "informationElementIndex", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (288, "p2pTechnology", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (289, "tunnelTechnology", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (290, "encryptedTechnology", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (291, "basicList", AbstractDataType::BasicList, DataTypeSemantics::List, None),
+    (292, "subTemplateList", AbstractDataType::SubTemplateList, DataTypeSemantics::List, None),
+    (293, "subTemplateMultiList", AbstractDataType::SubTemplateMultiList, DataTypeSemantics::List, None),
+    (294, "bgpValidityState", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (295, "IPSecSPI", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (296, "greKey", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (297, "natType", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (298, "initiatorPackets", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("packets")),
+    (299, "responderPackets", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("packets")),
+    (300, "observationDomainName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (301, "selectionSequenceId", AbstractDataType::Unsigned64, DataTypeSemantics::Identifier, None),
+    (302, "selectorId", AbstractDataType::Unsigned64, DataTypeSemantics::Identifier, None),
+    (303, "informationElementId", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (304, "selectorAlgorithm", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (305, "samplingPacketInterval", AbstractDataType::Unsigned32, DataTypeSemantics::Quantity, Some("packets")),
+    (306, "samplingPacketSpace", AbstractDataType::Unsigned32, DataTypeSemantics::Quantity, Some("packets")),
+    (307, "samplingTimeInterval", AbstractDataType::Unsigned32, DataTypeSemantics::Quantity, Some("microseconds")),
+    (308, "samplingTimeSpace", AbstractDataType::Unsigned32, DataTypeSemantics::Quantity, Some("microseconds")),
+    (309, "samplingSize", AbstractDataType::Unsigned32, DataTypeSemantics::Quantity, Some("packets")),
+    (310, "samplingPopulation", AbstractDataType::Unsigned32, DataTypeSemantics::Quantity, Some("packets")),
+    (311, "samplingProbability", AbstractDataType::Float64, DataTypeSemantics::Quantity, None),
+    (312, "dataLinkFrameSize", AbstractDataType::Unsigned16, DataTypeSemantics::Default, Some("octets")),
+    (313, "ipHeaderPacketSection", AbstractDataType::OctetArray, DataTypeSemantics::Default, None),
+    (314, "ipPayloadPacketSection", AbstractDataType::OctetArray, DataTypeSemantics::Default, None),
+    (315, "dataLinkFrameSection", AbstractDataType::OctetArray, DataTypeSemantics::Default, None),
+    (316, "mplsLabelStackSection", AbstractDataType::OctetArray, DataTypeSemantics::Default, None),
+    (317, "mplsPayloadPacketSection", AbstractDataType::OctetArray, DataTypeSemantics::Default, None),
+    (318, "selectorIdTotalPktsObserved", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (319, "selectorIdTotalPktsSelected", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (320, "absoluteError", AbstractDataType::Float64, DataTypeSemantics::Quantity, None),
+    (321, "relativeError", AbstractDataType::Float64, DataTypeSemantics::Quantity, None),
+    (322, "observationTimeSeconds", AbstractDataType::DateTimeSeconds, DataTypeSemantics::Default, Some("seconds")),
+    (323, "observationTimeMilliseconds", AbstractDataType::DateTimeMilliseconds, DataTypeSemantics::Default, Some("milliseconds")),
+    (324, "observationTimeMicroseconds", AbstractDataType::DateTimeMicroseconds, DataTypeSemantics::Default, Some("microseconds")),
+    (325, "observationTimeNanoseconds", AbstractDataType::DateTimeNanoseconds, DataTypeSemantics::Default, Some("nanoseconds")),
+    (326, "digestHashValue", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, None),
+    (327, "hashIPPayloadOffset", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, None),
+    (328, "hashIPPayloadSize", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, None),
+    (329, "hashOutputRangeMin", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, None),
+    (330, "hashOutputRangeMax", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, None),
+    (331, "hashSelectedRangeMin", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, None),
+    (332, "hashSelectedRangeMax", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, None),
+    (333, "hashDigestOutput", AbstractDataType::Boolean, DataTypeSemantics::Quantity, None),
+    (334, "hashInitialiserValue", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, None),
+    (335, "selectorName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (336, "upperCILimit", AbstractDataType::Float64, DataTypeSemantics::Quantity, None),
+    (337, "lowerCILimit", AbstractDataType::Float64, DataTypeSemantics::Quantity, None),
+    (338, "confidenceLevel", AbstractDataType::Float64, DataTypeSemantics::Quantity, None),
+    (339, "informationElementDataType", AbstractDataType::Unsigned8, DataTypeSemantics::Default, None),
+    (340, "informationElementDescription", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (341, "informationElementName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (342, "informationElementRangeBegin", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, None),
+    (343, "informationElementRangeEnd", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, None),
+    (344, "informationElementSemantics", AbstractDataType::Unsigned8, DataTypeSemantics::Default, None),
+    (345, "informationElementUnits", AbstractDataType::Unsigned16, DataTypeSemantics::Default, None),
+    (346, "privateEnterpriseNumber", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (347, "virtualStationInterfaceId", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (348, "virtualStationInterfaceName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (349, "virtualStationUUID", AbstractDataType::OctetArray, DataTypeSemantics::Identifier, None),
+    (350, "virtualStationName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (351, "layer2SegmentId", AbstractDataType::Unsigned64, DataTypeSemantics::Identifier, None),
+    (352, "layer2OctetDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("octets")),
+    (353, "layer2OctetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (354, "ingressUnicastPacketTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (355, "ingressMulticastPacketTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (356, "ingressBroadcastPacketTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (357, "egressUnicastPacketTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (358, "egressBroadcastPacketTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("packets")),
+    (359, "monitoringIntervalStartMilliSeconds", AbstractDataType::DateTimeMilliseconds, DataTypeSemantics::Default, Some("milliseconds")),
+    (360, "monitoringIntervalEndMilliSeconds", AbstractDataType::DateTimeMilliseconds, DataTypeSemantics::Default, Some("milliseconds")),
+    (361, "portRangeStart", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (362, "portRangeEnd", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (363, "portRangeStepSize", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (364, "portRangeNumPorts", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (365, "staMacAddress", AbstractDataType::MacAddress, DataTypeSemantics::Identifier, None),
+    (366, "staIPv4Address", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (367, "wtpMacAddress", AbstractDataType::MacAddress, DataTypeSemantics::Identifier, None),
+    (368, "ingressInterfaceType", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (369, "egressInterfaceType", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (370, "rtpSequenceNumber", AbstractDataType::Unsigned16, DataTypeSemantics::Default, None),
+    (371, "userName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (372, "applicationCategoryName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (373, "applicationSubCategoryName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (374, "applicationGroupName", AbstractDataType::String, DataTypeSemantics::Default, None),
+    (375, "originalFlowsPresent", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("flows")),
+    (376, "originalFlowsInitiated", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("flows")),
+    (377, "originalFlowsCompleted", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("flows")),
+    (378, "distinctCountOfSourceIPAddress", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, None),
+    (379, "distinctCountOfDestinationIPAddress", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, None),
+    (380, "distinctCountOfSourceIPv4Address", AbstractDataType::Unsigned32, DataTypeSemantics::TotalCounter, None),
+    (381, "distinctCountOfDestinationIPv4Address", AbstractDataType::Unsigned32, DataTypeSemantics::TotalCounter, None),
+    (382, "distinctCountOfSourceIPv6Address", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, None),
+    (383, "distinctCountOfDestinationIPv6Address", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, None),
+    (384, "valueDistributionMethod", AbstractDataType::Unsigned8, DataTypeSemantics::Default, None),
+    (385, "rfc3550JitterMilliseconds", AbstractDataType::Unsigned32, DataTypeSemantics::Quantity, Some("milliseconds")),
+    (386, "rfc3550JitterMicroseconds", AbstractDataType::Unsigned32, DataTypeSemantics::Quantity, Some("microseconds")),
+    (387, "rfc3550JitterNanoseconds", AbstractDataType::Unsigned32, DataTypeSemantics::Quantity, Some("nanoseconds")),
+    (388, "dot1qDEI", AbstractDataType::Boolean, DataTypeSemantics::Default, None),
+    (389, "dot1qCustomerDEI", AbstractDataType::Boolean, DataTypeSemantics::Default, None),
+    (390, "flowSelectorAlgorithm", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (391, "flowSelectedOctetDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("octets")),
+    (392, "flowSelectedPacketDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("packets")),
+    (393, "flowSelectedFlowDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("flows")),
+    (394, "selectorIDTotalFlowsObserved", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("flows")),
+    (395, "selectorIDTotalFlowsSelected", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("flows")),
+    (396, "samplingFlowInterval", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, Some("flows")),
+    (397, "samplingFlowSpacing", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, Some("flows")),
+    (398, "flowSamplingTimeInterval", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, Some("microseconds")),
+    (399, "flowSamplingTimeSpacing", AbstractDataType::Unsigned64, DataTypeSemantics::Quantity, Some("microseconds")),
+    (400, "hashFlowDomain", AbstractDataType::Unsigned16, DataTypeSemantics::Identifier, None),
+    (401, "transportOctetDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("octets")),
+    (402, "transportPacketDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("packets")),
+    (403, "originalExporterIPv4Address", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (404, "originalExporterIPv6Address", AbstractDataType::Ipv6Address, DataTypeSemantics::Identifier, None),
+    (405, "originalObservationDomainId", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (406, "intermediateProcessId", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (407, "ignoredDataRecordTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, None),
+    (408, "dataLinkFrameType", AbstractDataType::Unsigned16, DataTypeSemantics::Flags, None),
+    (409, "sectionOffset", AbstractDataType::Unsigned16, DataTypeSemantics::Quantity, None),
+    (410, "sectionExportedOctets", AbstractDataType::Unsigned16, DataTypeSemantics::Quantity, None),
+    (411, "dot1qServiceInstanceTag", AbstractDataType::OctetArray, DataTypeSemantics::Default, None),
+    (412, "dot1qServiceInstanceId", AbstractDataType::Unsigned32, DataTypeSemantics::Identifier, None),
+    (413, "dot1qServiceInstancePriority", AbstractDataType::Unsigned8, DataTypeSemantics::Identifier, None),
+    (414, "dot1qCustomerSourceMacAddress", AbstractDataType::MacAddress, DataTypeSemantics::Identifier, None),
+    (415, "dot1qCustomerDestinationMacAddress", AbstractDataType::MacAddress, DataTypeSemantics::Identifier, None),
+    (417, "postLayer2OctetDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("octets")),
+    (418, "postMCastLayer2OctetDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("octets")),
+    (420, "postLayer2OctetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (421, "postMCastLayer2OctetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (422, "minimumLayer2TotalLength", AbstractDataType::Unsigned64, DataTypeSemantics::Default, Some("octets")),
+    (423, "maximumLayer2TotalLength", AbstractDataType::Unsigned64, DataTypeSemantics::Default, Some("octets")),
+    (424, "droppedLayer2OctetDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("octets")),
+    (425, "droppedLayer2OctetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (426, "ignoredLayer2OctetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (427, "notSentLayer2OctetTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("octets")),
+    (428, "layer2OctetDeltaSumOfSquares", AbstractDataType::Unsigned64, DataTypeSemantics::Default, Some("octets")),
+    (429, "layer2OctetTotalSumOfSquares", AbstractDataType::Unsigned64, DataTypeSemantics::Default, Some("octets")),
+    (430, "layer2FrameDeltaCount", AbstractDataType::Unsigned64, DataTypeSemantics::DeltaCounter, Some("frames")),
+    (431, "layer2FrameTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("frames")),
+    (432, "pseudoWireDestinationIPv4Address", AbstractDataType::Ipv4Address, DataTypeSemantics::Identifier, None),
+    (433, "ignoredLayer2FrameTotalCount", AbstractDataType::Unsigned64, DataTypeSemantics::TotalCounter, Some("frames")),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_csv_handles_quotes() {
+        let text = "a,\"b, with a comma\",\"say \"\"hi\"\"\"\r\n\n\"two\nlines\",x\nlast,row";
+        let records = split_csv(text);
+        assert_eq!(records, vec![
+            (1, vec![String::from("a"), String::from("b, with a comma"), String::from("say \"hi\"")]),
+            (3, vec![String::from("two\nlines"), String::from("x")]),
+            (5, vec![String::from("last"), String::from("row")])
+        ]);
+    }
+
+    #[test]
+    fn load_csv_finds_columns_by_header() {
+        //columns in a different order than the IANA export, with one we don't use and a description full of commas
+        let text = "Name,Description,Abstract Data Type,ElementID,Units\n\
+                    ifDescription,\"the interface's name, as the exporter knows it\",string,100,\n\
+                    bytesSeen,\"counts octets, \"\"all\"\" of them\",unsigned64,101,octets\n\
+                    Unassigned,,,102-32767,\n";
+        let mut reg = IERegistry::empty();
+        assert_eq!(reg.load_csv(text, 9).expect("definitions should load"), 2);
+
+        let e = reg.get(9, 101).expect("bytesSeen should be registered");
+        assert_eq!(e.name, "bytesSeen");
+        assert_eq!(e.data_type, AbstractDataType::Unsigned64);
+        assert_eq!(e.semantics, DataTypeSemantics::Default);
+        assert_eq!(e.units.as_deref(), Some("octets"));
+        assert_eq!(reg.get(9, 100).expect("ifDescription should be registered").units, None);
+        assert!(reg.get(0, 100).is_none());
+    }
+
+    #[test]
+    fn load_csv_rejects_bad_rows() {
+        let mut reg = IERegistry::empty();
+        let text = "ElementID,Name,Abstract Data Type\n1,good,unsigned8\n2,bad,unsigned128\n";
+        match reg.load_csv(text, 9) {
+            Err(IELoadError::BadRow { line: 3, reason }) => assert!(reason.contains("unsigned128")),
+            r => panic!("expected a bad row on line 3, got {:?}", r)
+        }
+        //nothing from a file with a bad row is kept
+        assert!(reg.is_empty());
+
+        assert!(matches!(reg.load_csv("ElementID,Name,Abstract Data Type\n32768,enterpriseBit,unsigned8\n", 9), Err(IELoadError::BadRow { line: 2, .. })));
+        assert!(matches!(reg.load_csv("ElementID,Name,Abstract Data Type,Data Type Semantics\n1,a,unsigned8,sometimes\n", 9), Err(IELoadError::BadRow { line: 2, .. })));
+        assert!(matches!(reg.load_csv("ElementID,Name\n1,a\n", 9), Err(IELoadError::MissingColumn("Abstract Data Type"))));
+        assert!(matches!(reg.load_csv("", 9), Err(IELoadError::MissingColumn("ElementID"))));
+    }
+}
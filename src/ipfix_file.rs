@@ -0,0 +1,404 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::ValidationMode;
+use crate::error::ParseError;
+use crate::information_elements::IERegistry;
+use crate::parse_data::{DataType, OptionsDataSet};
+use crate::parse_packet::{IPFIX_VERSION, MESSAGE_HEADER_LEN, PacketInfo, PacketResult, parse_packet};
+use crate::template_ring::TemplateRing;
+use crate::templates::{IPFIXOptionsTemplate, TemplateRecord};
+
+//a file's messages only say where they came from in the export session and message details a file writer adds
+//until the first of those this stands in for the exporter, unless the caller knows better (see with_exporter)
+pub const FILE_EXPORTER: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+
+//the scope fields of export session details and message details (RFC 5655 sections 8.1.3 and 8.1.4)
+const SESSION_SCOPE: u16 = 267;
+const MESSAGE_SCOPE: u16 = 263;
+//the fields in them that name the exporter
+const EXPORTER_IPV4_ADDRESS: u16 = 130;
+const EXPORTER_IPV6_ADDRESS: u16 = 131;
+const EXPORTER_TRANSPORT_PORT: u16 = 217;
+
+//reads an IPFIX file (RFC 5655), which is just IPFIX messages one after the other, without a collector, its threads, or a socket
+//every message goes through parse_packet with templates kept for the whole file, so templates (and withdrawals) apply to every message after them
+//a file can hold messages from several exporters (RFC 5655 section 6), the export session details or message details the file writer adds say which is which
+//a message holding one of those, and every message after it, is read as coming from the exporter it names and with that exporter's templates
+//so exporters that used the same ODIDs and template ids don't clash, as long as the file names them
+//the details themselves still come out as options data like any other, so do the rest of the file writer's options (checksums etc)
+//
+//each call to next gives the next message, an error in one message doesn't stop the ones after it from being read
+//unless we can no longer tell where the next message starts (a bad version or length in a message header, the file ending part way through a message, or a read failing), after that it only returns None
+pub struct IPFIXFileReader<R: Read> {
+    reader: R,
+    templates: TemplateRing,
+    exporter: SocketAddr, //given to every message, and what its templates are kept under, the last exporter the details named
+    details_templates: TemplateRing, //the file writer's details options templates, kept under FILE_EXPORTER whichever exporter's message they came in
+    details_ids: Vec<(u16, u32)>, //(template id, ODID) of everything in details_templates, so each exporter the details name can be given them
+    offset: u64, //where the next message starts in the file
+    done: bool
+}
+
+impl IPFIXFileReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(IPFIXFileReader::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: Read> IPFIXFileReader<R> {
+    //decodes fields using the IANA information elements
+    pub fn new(reader: R) -> Self {
+        IPFIXFileReader::with_registry(reader, Arc::new(IERegistry::iana()))
+    }
+
+    //same as new, but with enterprise elements (or anything else) loaded into the registry
+    pub fn with_registry(reader: R, registry: Arc<IERegistry>) -> Self {
+        IPFIXFileReader {
+            reader,
+            templates: TemplateRing::with_registry(registry.clone()),
+            exporter: FILE_EXPORTER,
+            details_templates: TemplateRing::with_registry(registry),
+            details_ids: Vec::new(),
+            offset: 0,
+            done: false
+        }
+    }
+
+    //messages came from this exporter instead of FILE_EXPORTER until the details name one, for a file without details that is every message
+    //so packets from several files can be told apart
+    //templates already read stay under the exporter they were read with, so this belongs before the first message
+    pub fn with_exporter(mut self, exporter: SocketAddr) -> Self {
+        self.exporter = exporter;
+        self
+    }
+
+    //the templates read so far
+    pub fn templates(&self) -> &TemplateRing {
+        &self.templates
+    }
+
+    //anything that means we can't find the next message also sets done
+    fn read_message(&mut self) -> Option<Result<PacketInfo, FileReadError>> {
+        let start = self.offset;
+        self.done = true;
+
+        let mut msg = vec![0u8; MESSAGE_HEADER_LEN];
+        match read_full(&mut self.reader, &mut msg) {
+            Ok(0) => { return None; }, //the end of the file, right where a message should have started
+            Ok(n) if n < MESSAGE_HEADER_LEN => { return Some(Err(FileReadError::Truncated { offset: start, needed: MESSAGE_HEADER_LEN, available: n })); },
+            Ok(_) => {},
+            Err(e) => { return Some(Err(FileReadError::Io(e))); }
+        }
+
+        //the length is all there is to find the next message with, if it can't be trusted the rest of the file can't be read
+        let version = u16::from_be_bytes([msg[0], msg[1]]);
+        let len = u16::from_be_bytes([msg[2], msg[3]]) as usize;
+        if version != IPFIX_VERSION {
+            return Some(Err(FileReadError::Parse { offset: start, error: ParseError::BadVersion { version } }));
+        }
+        if len < MESSAGE_HEADER_LEN {
+            return Some(Err(FileReadError::Parse { offset: start, error: ParseError::BadMessageLength { length: len, available: MESSAGE_HEADER_LEN } }));
+        }
+
+        msg.resize(len, 0);
+        match read_full(&mut self.reader, &mut msg[MESSAGE_HEADER_LEN..]) {
+            Ok(n) if n < len - MESSAGE_HEADER_LEN => { return Some(Err(FileReadError::Truncated { offset: start, needed: len, available: MESSAGE_HEADER_LEN + n })); },
+            Ok(_) => {},
+            Err(e) => { return Some(Err(FileReadError::Io(e))); }
+        }
+        self.offset += len as u64;
+        self.done = false;
+
+        //the details have to be read before the message is parsed, so its templates are kept under the exporter they name
+        if let Some(exporter) = self.read_details(&msg) {
+            self.exporter = exporter;
+            //the exporter's own messages can hold more details
+            for &(id, odid) in self.details_ids.iter() {
+                if let Some(t) = self.details_templates.get_options_template(FILE_EXPORTER, id, odid) {
+                    self.templates.insert_options_template(exporter, IPFIXOptionsTemplate::clone(&t), odid);
+                }
+            }
+        }
+
+        //the message is exactly as long as it says, so strict and lenient would do the same thing
+        match parse_packet(&self.templates, self.exporter, &msg, ValidationMode::Strict) {
+            PacketResult::Ok(info) => Some(Ok(info)),
+            PacketResult::AbortError(error) => Some(Err(FileReadError::Parse { offset: start, error }))
+        }
+    }
+
+    //learns any details templates in a message and returns the exporter named by the last details record in it
+    //the exporters' own templates are left alone, and sets that don't add up are left for parse_packet to report
+    fn read_details(&mut self, msg: &[u8]) -> Option<SocketAddr> {
+        let odid = u32::from_be_bytes([msg[12], msg[13], msg[14], msg[15]]);
+        let mut exporter = None;
+
+        let mut rest = &msg[MESSAGE_HEADER_LEN..];
+        while rest.len() >= 4 {
+            let set_id = u16::from_be_bytes([rest[0], rest[1]]);
+            let set_len = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+            if set_len < 4 || set_len > rest.len() {
+                break;
+            }
+            let (set, next) = rest.split_at(set_len);
+            rest = next;
+
+            if set_id == 3 {
+                let Ok((_, records)) = IPFIXOptionsTemplate::from_set(set, odid) else { continue; };
+                for record in records {
+                    if let TemplateRecord::Template(t) = record {
+                        if t.scope_fields.first().is_some_and(|f| f.en == 0 && (f.field_id == SESSION_SCOPE || f.field_id == MESSAGE_SCOPE)) {
+                            if !self.details_ids.contains(&(t.id, odid)) {
+                                self.details_ids.push((t.id, odid));
+                            }
+                            self.details_templates.insert_options_template(FILE_EXPORTER, t, odid);
+                        }
+                    }
+                }
+            }
+            else if set_id >= 256 && self.details_ids.contains(&(set_id, odid)) {
+                let Ok((_, records)) = OptionsDataSet::get_datasets(set, &self.details_templates, FILE_EXPORTER, odid) else { continue; };
+                exporter = records.iter().rev().find_map(details_exporter).or(exporter);
+            }
+        }
+
+        exporter
+    }
+}
+
+//the exporter an export session or message details record names, None if it has no exporter address
+//a record without the exporter's port names port 0
+fn details_exporter(record: &OptionsDataSet) -> Option<SocketAddr> {
+    let port = record.fields.iter().find_map(|f| match (f.id, &f.data) {
+        (EXPORTER_TRANSPORT_PORT, DataType::U16(port)) => Some(*port),
+        _ => None
+    }).unwrap_or(0);
+
+    record.fields.iter().find_map(|f| match (f.id, &f.data) {
+        (EXPORTER_IPV4_ADDRESS, DataType::IPV4(addr)) => Some(SocketAddr::from((*addr, port))),
+        (EXPORTER_IPV6_ADDRESS, DataType::IPV6(addr)) => Some(SocketAddr::from((*addr, port))),
+        _ => None
+    })
+}
+
+impl<R: Read> Iterator for IPFIXFileReader<R> {
+    type Item = Result<PacketInfo, FileReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        self.read_message()
+    }
+}
+
+//what can go wrong while reading a file, offsets are from the start of the file to the start of the message
+#[derive(Debug)]
+pub enum FileReadError {
+    Io(io::Error),
+    Truncated { offset: u64, needed: usize, available: usize }, //the file ends part way through a message
+    Parse { offset: u64, error: ParseError } //the message couldn't be parsed, the offset in error is from the start of the message
+}
+
+impl fmt::Display for FileReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileReadError::Io(e) => write!(f, "failed to read IPFIX file: {}", e),
+            FileReadError::Truncated { offset, needed, available } =>
+                write!(f, "message at byte {} needs {} bytes, but the file ends after {}", offset, needed, available),
+            FileReadError::Parse { offset, error } => write!(f, "message at byte {}: {}", offset, error)
+        }
+    }
+}
+
+impl std::error::Error for FileReadError {}
+
+//like read_exact, but says how much it got when the reader ends early instead of failing
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => { break; },
+            Ok(n) => { filled += n; },
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => { return Err(e); }
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_packet::test_path;
+    use std::net::Ipv6Addr;
+
+    fn set(set_id: u16, records: &[&[u8]]) -> Vec<u8> {
+        let len = 4 + records.iter().map(|r| r.len()).sum::<usize>();
+        let mut set = Vec::with_capacity(len);
+        set.extend_from_slice(&set_id.to_be_bytes());
+        set.extend_from_slice(&(len as u16).to_be_bytes());
+        for r in records {
+            set.extend_from_slice(r);
+        }
+        set
+    }
+
+    fn message(odid: u32, sets: &[Vec<u8>]) -> Vec<u8> {
+        let len = MESSAGE_HEADER_LEN + sets.iter().map(|s| s.len()).sum::<usize>();
+        let mut msg = Vec::with_capacity(len);
+        msg.extend_from_slice(&IPFIX_VERSION.to_be_bytes());
+        msg.extend_from_slice(&(len as u16).to_be_bytes());
+        msg.extend_from_slice(&1_700_000_000u32.to_be_bytes());
+        msg.extend_from_slice(&0u32.to_be_bytes());
+        msg.extend_from_slice(&odid.to_be_bytes());
+        for s in sets {
+            msg.extend_from_slice(s);
+        }
+        msg
+    }
+
+    //options template 300, sessionScope then exporterIPv4Address and exporterTransportPort
+    fn session_details_template() -> Vec<u8> {
+        set(3, &[&[1, 44, 0, 3, 0, 1, 1, 11, 0, 1, 0, 130, 0, 4, 0, 217, 0, 2]])
+    }
+
+    fn session_details(exporter: SocketAddrV4) -> Vec<u8> {
+        let mut record = vec![1];
+        record.extend_from_slice(&exporter.ip().octets());
+        record.extend_from_slice(&exporter.port().to_be_bytes());
+        set(300, &[&record])
+    }
+
+    //options template 301, messageScope then exporterIPv6Address, no port
+    fn message_details_template() -> Vec<u8> {
+        set(3, &[&[1, 45, 0, 2, 0, 1, 1, 7, 0, 1, 0, 131, 0, 16]])
+    }
+
+    fn message_details(exporter: Ipv6Addr) -> Vec<u8> {
+        let mut record = vec![1];
+        record.extend_from_slice(&exporter.octets());
+        set(301, &[&record])
+    }
+
+    //the same template id with two layouts, sourceIPv4Address for one exporter and sourceTransportPort for the other
+    fn address_template() -> Vec<u8> {
+        set(2, &[&[1, 0, 0, 1, 0, 8, 0, 4]])
+    }
+
+    fn port_template() -> Vec<u8> {
+        set(2, &[&[1, 0, 0, 1, 0, 7, 0, 2]])
+    }
+
+    fn write_file(name: &str, messages: &[Vec<u8>]) -> std::path::PathBuf {
+        let path = test_path(name);
+        std::fs::write(&path, messages.concat()).unwrap_or_else(|e| panic!("could not write {}: {}", path.display(), e));
+        path
+    }
+
+    fn read_file(path: &Path) -> Vec<PacketInfo> {
+        let reader = IPFIXFileReader::open(path).unwrap_or_else(|e| panic!("could not open {}: {}", path.display(), e));
+        let infos = reader.map(|r| r.unwrap_or_else(|e| panic!("message should have parsed: {}", e))).collect();
+        let _ = std::fs::remove_file(path);
+        infos
+    }
+
+    //the one field of the one record in a message, either sourceIPv4Address or sourceTransportPort
+    fn decoded(info: &PacketInfo) -> Option<&DataType> {
+        match info.data.as_slice() {
+            [record] if record.fields.len() == 1 => Some(&record.fields[0].data),
+            _ => None
+        }
+    }
+
+    fn source_address(info: &PacketInfo) -> Option<Ipv4Addr> {
+        match decoded(info) {
+            Some(DataType::IPV4(addr)) => Some(*addr),
+            _ => None
+        }
+    }
+
+    fn source_port(info: &PacketInfo) -> Option<u16> {
+        match decoded(info) {
+            Some(DataType::U16(port)) => Some(*port),
+            _ => None
+        }
+    }
+
+    #[test]
+    fn session_details_keep_each_exporters_templates_apart() {
+        let a = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 4739);
+        let b = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 2), 4740);
+        //both exporters use ODID 1 and template 256, as two files merged into one would
+        let path = write_file("session_details", &[
+            message(1, &[session_details_template(), session_details(a), address_template(), set(256, &[&[10, 0, 0, 1]])]),
+            message(1, &[session_details(b), port_template(), set(256, &[&[0, 53]])]),
+            message(1, &[session_details(a), set(256, &[&[10, 0, 0, 2]])]),
+            message(1, &[session_details(b), set(256, &[&[0, 80]])])
+        ]);
+        let infos = read_file(&path);
+        assert_eq!(infos.len(), 4);
+
+        let exporters: Vec<_> = infos.iter().map(|i| i.exporter).collect();
+        assert_eq!(exporters, vec![SocketAddr::V4(a), SocketAddr::V4(b), SocketAddr::V4(a), SocketAddr::V4(b)]);
+        for info in &infos {
+            assert_eq!(info.set_error_count, 0);
+            //the details are still there for whoever wants them
+            assert_eq!(info.options_data.len(), 1);
+        }
+
+        //each exporter's data is read with its own template 256, B's template didn't replace A's
+        assert_eq!(source_address(&infos[0]), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(source_port(&infos[1]), Some(53));
+        assert_eq!(source_address(&infos[2]), Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(source_port(&infos[3]), Some(80));
+    }
+
+    #[test]
+    fn message_details_name_the_exporter_from_then_on() {
+        let before = SocketAddr::from(([198, 51, 100, 1], 4739));
+        let named = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let path = write_file("message_details", &[
+            message(5, &[address_template(), set(256, &[&[10, 0, 0, 1]])]),
+            message(5, &[message_details_template(), message_details(named), address_template(), set(256, &[&[10, 0, 0, 2]])]),
+            //no details, still the exporter last named
+            message(5, &[set(256, &[&[10, 0, 0, 3]])])
+        ]);
+        let mut reader = IPFIXFileReader::open(&path).unwrap_or_else(|e| panic!("could not open {}: {}", path.display(), e)).with_exporter(before);
+        let infos: Vec<_> = reader.by_ref().map(|r| r.unwrap_or_else(|e| panic!("message should have parsed: {}", e))).collect();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(infos.len(), 3);
+
+        //until the details name one, the exporter is the one the caller gave, the details don't carry a port so the named one is on port 0
+        let named = SocketAddr::from((named, 0));
+        assert_eq!(infos.iter().map(|i| i.exporter).collect::<Vec<_>>(), vec![before, named, named]);
+        assert!(infos.iter().all(|i| i.set_error_count == 0));
+        assert_eq!(source_address(&infos[2]), Some(Ipv4Addr::new(10, 0, 0, 3)));
+
+        //the templates in the message holding the details are kept under the exporter the details name
+        assert!(reader.templates().get_template(before, 256, 5).is_some());
+        assert!(reader.templates().get_template(named, 256, 5).is_some());
+        assert!(reader.templates().get_options_template(named, 301, 5).is_some());
+        assert!(reader.templates().get_template(FILE_EXPORTER, 256, 5).is_none());
+    }
+
+    #[test]
+    fn exporters_dont_borrow_each_others_templates() {
+        //data from an exporter whose templates were never in the file can't be decoded, and doesn't pick up another exporter's
+        let a = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 4739);
+        let b = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 2), 4739);
+        let path = write_file("unknown_exporter", &[
+            message(1, &[session_details_template(), session_details(a), address_template()]),
+            message(1, &[session_details(b), set(256, &[&[10, 0, 0, 1]])])
+        ]);
+        let infos = read_file(&path);
+        assert_eq!(infos[1].exporter, SocketAddr::V4(b));
+        assert!(infos[1].data.is_empty());
+        assert!(matches!(infos[1].set_errors.as_slice(), [ParseError::UnknownTemplate { set_id: 256, odid: 1, .. }]));
+    }
+}
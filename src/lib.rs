@@ -0,0 +1,23 @@
+pub mod templates;
+pub mod template_ring;
+pub mod parse_data;
+pub mod parse_packet;
+pub mod executor;
+pub mod config;
+pub mod error;
+pub mod information_elements;
+pub mod query;
+pub mod retention;
+pub mod sequence;
+pub mod ipfix_file;
+pub mod sflow;
+pub mod tls;
+mod queue;
+
+pub use executor::{IPFIXCollectorHandle, StartError, SubscriptionId, SubscriberCallback};
+pub use config::{Config, DispatchMode, Listener, OverloadPolicy, Protocol, TlsConfig, Transport, ValidationMode};
+pub use ipfix_file::{FileReadError, IPFIXFileReader};
+pub use tls::ExporterIdentity;
+pub use query::ResultQuery;
+pub use retention::{Eviction, RetentionPolicy, RetentionStats};
+pub use sequence::SequenceStats;
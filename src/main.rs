@@ -1,29 +1,40 @@
-
-mod templates;
-mod template_ring;
-mod parse_data;
-mod parse_packet;
-mod executor;
-mod config;
-
 use std::{net::Ipv4Addr, time::Duration};
 
-pub use executor::IPFIXCollectorHandle;
-pub use config::Config;
+use ipfix_parser_rs::{IPFIXCollectorHandle, Config, DispatchMode, Listener, OverloadPolicy, Protocol, Transport, ResultQuery, RetentionPolicy, ValidationMode};
 
 fn main() {
     let cfg = Config {
         ipfix_listen_addr: Ipv4Addr::new(127, 0, 0, 1),
         ipfix_listen_port: 64000,
-        num_threads: 32
+        listeners: vec![
+            Listener { addr: Ipv4Addr::new(127, 0, 0, 1), port: 64000, protocol: Protocol::IPFIX, transport: Transport::TCP },
+            Listener { addr: Ipv4Addr::new(127, 0, 0, 1), port: 6343, protocol: Protocol::SFlow, transport: Transport::UDP }
+        ],
+        num_threads: 32,
+        template_lifetime: Some(Duration::from_secs(1800)),
+        ie_definition_files: Vec::new(),
+        retention: RetentionPolicy { max_total_bytes: Some(256 * 1024 * 1024), ..RetentionPolicy::default() },
+        parser_queue_depth: 1024,
+        aggregator_queue_depth: 4096,
+        subscriber_queue_depth: 1024,
+        overload_policy: OverloadPolicy::DropOldest,
+        dispatch: DispatchMode::ExporterAffine,
+        validation: ValidationMode::Strict
     };
 
-    let collector = IPFIXCollectorHandle::start(&cfg);
-    //TODO: ADD WAY TO ACCESS DATA STORED IN THE COLLECTOR
+    let collector = IPFIXCollectorHandle::start(&cfg).unwrap_or_else(|e| panic!("Failed to start collector: {}", e));
     println!("Collector Started on {:?}:{} with {} parser threads", cfg.ipfix_listen_addr, cfg.ipfix_listen_port, cfg.num_threads);
 
     loop {
         std::thread::sleep(Duration::from_secs(1));
+
+        //take everything that arrived since last time so it doesn't pile up in the aggregator
+        let results = collector.drain(&ResultQuery::all());
+        if !results.is_empty() {
+            let records: usize = results.iter().map(|p| p.data.len() + p.options_data.len()).sum();
+            let lost: u64 = collector.sequence_stats().values().map(|s| s.lost_records).sum();
+            println!("Received {} packets holding {} records, {} packets dropped and {} records lost so far", results.len(), records, collector.dropped_packets(), lost);
+        }
     }
 
     //collector.stop();
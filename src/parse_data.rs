@@ -1,34 +1,79 @@
+use crate::error::{ParseError, truncated};
+use crate::information_elements::{AbstractDataType, InformationElement};
 use crate::template_ring::TemplateRing;
+use crate::templates::{IPFIXTemplate, IPFIXOptionsTemplate, IPFIXField};
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use nom::{number::complete::be_u16, error::VerboseError, Slice};
 
+//seconds between the NTP epoch (1900) and the unix epoch (1970), dateTimeMicroseconds and dateTimeNanoseconds count from the NTP epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
 
+//fields that aren't in the information element registry (or don't match the registered type) are decoded by width into
+//U8/U16/U32/U64/BYTES, everything else is decoded according to its abstract data type
+#[derive(Clone)]
 pub enum DataType {
     U8(u8),
     U16(u16),
     U32(u32),
     U64(u64),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    BOOL(bool),
+    MAC([u8; 6]),
+    IPV4(Ipv4Addr),
+    IPV6(Ipv6Addr),
+    STRING(String),
+    DATETIME(SystemTime),
     BYTES(Vec<u8>)
 }
 
+#[derive(Clone)]
 pub struct DataRow {
     pub id: u16,
     pub en: u32,
+    pub element: Option<Arc<InformationElement>>, //what the registry knows about this field, None if it isn't registered
     pub data: DataType
 }
 
 impl DataRow {
     pub fn new(id: u16, en: u32, val: DataType) -> Self {
-        DataRow { id, en, data: val }
+        DataRow { id, en, element: None, data: val }
+    }
+
+    pub fn with_element(id: u16, en: u32, element: Option<Arc<InformationElement>>, val: DataType) -> Self {
+        DataRow { id, en, element, data: val }
+    }
+
+    //the information element name, if the registry knows this field
+    pub fn name(&self) -> Option<&str> {
+        self.element.as_ref().map(|e| e.name.as_str())
     }
 }
 
+#[derive(Clone)]
 pub struct DataSet {
     pub id: u16,
     pub template: u16,
     pub fields: Vec<DataRow>
 }
 
+//a record described by an options template, the scope fields say what the option fields apply to (an interface, the exporting process, etc)
+#[derive(Clone)]
+pub struct OptionsDataSet {
+    pub id: u16,
+    pub template: u16,
+    pub scope_fields: Vec<DataRow>,
+    pub fields: Vec<DataRow>
+}
+
 fn read_u8(buf: &[u8]) -> u8 {
     buf[0]
 }
@@ -42,76 +87,512 @@ fn read_u32(buf: &[u8]) -> u32 {
 }
 
 fn read_u64(buf: &[u8]) -> u64 {
-    (buf[0] as u64) << 56 
-    + (buf[1] as u64) << 48
-    + (buf[2] as u64) << 40
-    + (buf[3] as u64) << 32
-    + (buf[4] as u64) << 24
-    + (buf[5] as u64) << 16
-    + (buf[6] as u64) << 40
+    ((buf[0] as u64) << 56)
+    + ((buf[1] as u64) << 48)
+    + ((buf[2] as u64) << 40)
+    + ((buf[3] as u64) << 32)
+    + ((buf[4] as u64) << 24)
+    + ((buf[5] as u64) << 16)
+    + ((buf[6] as u64) << 8)
     + (buf[7] as u64)
 }
 
+//reduced size encoding (RFC 7011 section 6.2) drops the high order bytes of an integer, so any width up to 8 is read as the low bytes of a u64
+fn read_uint(buf: &[u8]) -> u64 {
+    buf.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64)
+}
+
+//same as read_uint, but the dropped bytes of a signed integer are copies of its sign bit
+fn read_int(buf: &[u8]) -> i64 {
+    let unused_bits = 64 - 8 * buf.len() as u32;
+    ((read_uint(buf) << unused_bits) as i64) >> unused_bits
+}
+
 fn read_other(buf: &[u8], width: usize) -> Vec<u8> {
     Vec::from(buf.slice(0..width))
 }
 
-fn read_value_from_byte(i: &[u8], offset: usize, width: u16) -> Result<DataType, String> {
+fn read_value_from_byte(i: &[u8], offset: usize, field: &IPFIXField, element: Option<&InformationElement>, set_id: u16) -> Result<DataType, ParseError> {
     //get a view of the buffer that starts with at the provided offset
-    let buf = i.slice(offset..i.len());
+    let buf = i.get(offset..).unwrap_or(&[]);
+    let width = field.width;
 
     //if the buffer after the offset isn't big enough, we can't read it
-    if buf.len() <= width.into() {
-        return Result::Err(format!("Field at {} wants {} bytes, but only {} are left in the buffer", offset, width, buf.len()));
+    if buf.len() < width.into() {
+        return Result::Err(ParseError::FieldOutOfRange { offset, set_id, field_id: field.field_id, width, available: buf.len() });
     }
 
     //extract the value
-    Ok(match width {
+    Ok(decode_value(buf.slice(0..width as usize), element, false))
+}
+
+//turns the bytes of a single value into a DataType, using the element's abstract data type if we know it
+fn decode_value(buf: &[u8], element: Option<&InformationElement>, variable_length: bool) -> DataType {
+    match element.and_then(|e| decode_typed(buf, e.data_type)) {
+        Some(v) => v,
+        None => decode_by_width(buf, variable_length)
+    }
+}
+
+fn decode_by_width(buf: &[u8], variable_length: bool) -> DataType {
+    //a variable length value is never an integral type, even if it happens to be 4 bytes long
+    if variable_length {
+        return DataType::BYTES(read_other(buf, buf.len()));
+    }
+
+    match buf.len() {
         8 => DataType::U64(read_u64(buf)),
         4 => DataType::U32(read_u32(buf)),
         2 => DataType::U16(read_u16(buf)),
         1 => DataType::U8(read_u8(buf)),
-        n => DataType::BYTES(read_other(buf, n.into()))
+        n => DataType::BYTES(read_other(buf, n))
+    }
+}
+
+//returns None if the value doesn't fit the type (wrong width, a boolean that is neither 1 nor 2, etc)
+fn decode_typed(buf: &[u8], data_type: AbstractDataType) -> Option<DataType> {
+    Some(match (data_type, buf.len()) {
+        //integers can use reduced size encoding, they are still returned as the element's type whatever width they arrived in
+        (AbstractDataType::Unsigned8, 1) => DataType::U8(read_u8(buf)),
+        (AbstractDataType::Unsigned16, 1..=2) => DataType::U16(read_uint(buf) as u16),
+        (AbstractDataType::Unsigned32, 1..=4) => DataType::U32(read_uint(buf) as u32),
+        (AbstractDataType::Unsigned64, 1..=8) => DataType::U64(read_uint(buf)),
+        (AbstractDataType::Signed8, 1) => DataType::I8(read_u8(buf) as i8),
+        (AbstractDataType::Signed16, 1..=2) => DataType::I16(read_int(buf) as i16),
+        (AbstractDataType::Signed32, 1..=4) => DataType::I32(read_int(buf) as i32),
+        (AbstractDataType::Signed64, 1..=8) => DataType::I64(read_int(buf)),
+        (AbstractDataType::Float32, 4) => DataType::F32(f32::from_bits(read_u32(buf))),
+        (AbstractDataType::Float64, 8) => DataType::F64(f64::from_bits(read_u64(buf))),
+        //the only reduced size a float64 can have is a float32
+        (AbstractDataType::Float64, 4) => DataType::F64(f32::from_bits(read_u32(buf)) as f64),
+        //RFC 7011 encodes true as 1 and false as 2
+        (AbstractDataType::Boolean, 1) => match buf[0] {
+            1 => DataType::BOOL(true),
+            2 => DataType::BOOL(false),
+            _ => { return None; }
+        },
+        (AbstractDataType::MacAddress, 6) => DataType::MAC([buf[0], buf[1], buf[2], buf[3], buf[4], buf[5]]),
+        (AbstractDataType::Ipv4Address, 4) => DataType::IPV4(Ipv4Addr::from(read_u32(buf))),
+        (AbstractDataType::Ipv6Address, 16) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(buf);
+            DataType::IPV6(Ipv6Addr::from(octets))
+        },
+        (AbstractDataType::String, _) => DataType::STRING(String::from_utf8_lossy(buf).into_owned()),
+        (AbstractDataType::DateTimeSeconds, 4) => DataType::DATETIME(UNIX_EPOCH + Duration::from_secs(read_u32(buf) as u64)),
+        //64 bits of milliseconds reach further than SystemTime does on some platforms, a time it can't hold is left as the raw value
+        (AbstractDataType::DateTimeMilliseconds, 8) => DataType::DATETIME(UNIX_EPOCH.checked_add(Duration::from_millis(read_u64(buf)))?),
+        (AbstractDataType::DateTimeMicroseconds, 8) | (AbstractDataType::DateTimeNanoseconds, 8) => DataType::DATETIME(read_ntp_time(buf, data_type)?),
+        //octet arrays and the structured list types are left as raw bytes, whatever their width
+        (AbstractDataType::OctetArray, _) | (AbstractDataType::BasicList, _) | (AbstractDataType::SubTemplateList, _) | (AbstractDataType::SubTemplateMultiList, _) => DataType::BYTES(read_other(buf, buf.len())),
+        _ => { return None; }
     })
 }
 
+//NTP timestamps are 32 bits of seconds since 1900 followed by 32 bits of fractional seconds (RFC 7011 section 6.1.9)
+//for dateTimeMicroseconds the bottom 11 bits of the fraction are meaningless and have to be ignored
+fn read_ntp_time(buf: &[u8], data_type: AbstractDataType) -> Option<SystemTime> {
+    let seconds = read_u32(buf) as u64;
+    let mut fraction = read_u32(&buf[4..8]) as u64;
+    if data_type == AbstractDataType::DateTimeMicroseconds {
+        fraction &= !0x7ffu64;
+    }
+    let nanos = (fraction * 1_000_000_000) >> 32;
+    let since_ntp_epoch = Duration::from_secs(seconds) + Duration::from_nanos(nanos);
+
+    if seconds >= NTP_UNIX_OFFSET {
+        UNIX_EPOCH.checked_add(since_ntp_epoch - Duration::from_secs(NTP_UNIX_OFFSET))
+    }
+    else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(NTP_UNIX_OFFSET) - since_ntp_epoch)
+    }
+}
+
+//variable length fields are prefixed with their length, one byte if it is below 255, otherwise 255 followed by a two byte length (RFC 7011 section 7)
+//returns the length of the value and the number of bytes the prefix took up
+fn read_variable_length(i: &[u8], offset: usize, field: &IPFIXField, set_id: u16) -> Result<(u16, usize), ParseError> {
+    let buf = i.get(offset..).unwrap_or(&[]);
+
+    match buf.first() {
+        None => Result::Err(ParseError::FieldOutOfRange { offset, set_id, field_id: field.field_id, width: 1, available: 0 }),
+        Some(255) => {
+            if buf.len() < 3 {
+                return Result::Err(ParseError::FieldOutOfRange { offset, set_id, field_id: field.field_id, width: 3, available: buf.len() });
+            }
+            Ok((read_u16(&buf[1..3]), 3))
+        },
+        Some(n) => Ok((*n as u16, 1))
+    }
+}
+
+//reads the values for a list of template fields starting at offset, the offsets are worked out as we go since variable length fields shift everything after them
+//returns the offset of the first byte after the last field along with the values
+//elements has to line up with fields, it is what IERegistry::resolve returns for them
+fn read_fields(i: &[u8], offset: usize, fields: &[IPFIXField], elements: &[Option<Arc<InformationElement>>], set_id: u16) -> Result<(usize, Vec<DataRow>), ParseError> {
+    let mut rows = Vec::with_capacity(fields.len());
+    let mut offset = offset;
+
+    for (tmplt_field, element) in fields.iter().zip(elements.iter()) {
+        let val = if tmplt_field.variable_length {
+            let (width, prefix_len) = read_variable_length(i, offset, tmplt_field, set_id)?;
+            offset += prefix_len;
+            if i.len() < offset + width as usize {
+                return Result::Err(ParseError::FieldOutOfRange { offset, set_id, field_id: tmplt_field.field_id, width, available: i.len() - offset });
+            }
+            let val = decode_value(i.slice(offset..offset + width as usize), element.as_deref(), true);
+            offset += width as usize;
+            val
+        }
+        else {
+            let val = read_value_from_byte(i, offset, tmplt_field, element.as_deref(), set_id)?;
+            offset += tmplt_field.width as usize;
+            val
+        };
+
+        rows.push(DataRow::with_element(tmplt_field.field_id, tmplt_field.en, element.clone(), val));
+    }
+
+    Ok((offset, rows))
+}
+
+//reads the set header and splits the set into its records and everything after the set
+//returns (set id, records, rest)
+fn split_data_set(i: &[u8]) -> Result<(u16, &[u8], &[u8]), ParseError> {
+    //take data set header
+    let (rest, set_id) = be_u16::<&[u8], VerboseError<&[u8]>>(i)
+        .or(Result::Err(truncated(i, i, 2)))?;
+
+    //take length
+    let (_rest, len) = be_u16::<&[u8], VerboseError<&[u8]>>(rest)
+        .or(Result::Err(truncated(i, rest, 2)))?;
+
+    //the length includes the 4 byte set header, and the set has to fit in what is left of the packet
+    if len < 4 || i.len() < len as usize {
+        return Result::Err(ParseError::SetLengthOverflow { offset: 0, set_id, set_len: len, available: i.len() });
+    }
+
+    Ok((set_id, i.slice(4..len as usize), i.slice(len as usize..i.len())))
+}
+
+//pulls records out of the body of a data set until what is left is too small to be a record (RFC 7011 allows padding at the end of a set)
+//records from templates without variable length fields are sliced to exactly min_record_len, otherwise the reader reports how much it used
+//errors come back relative to the start of the set
+fn read_records<T, F>(body: &[u8], set_id: u16, min_record_len: usize, variable_length: bool, read: F) -> Result<Vec<T>, ParseError>
+where F: Fn(&[u8]) -> Result<(usize, T), ParseError> {
+    //a template made entirely of zero width fields would never make progress
+    if min_record_len == 0 {
+        return Result::Err(ParseError::MalformedTemplate { offset: 0, set_id, template_id: set_id, reason: "template describes records with a length of 0" });
+    }
+
+    let mut records = Vec::new();
+    let mut loop_rest = body;
+    while loop_rest.len() >= min_record_len {
+        let cur = if variable_length { loop_rest } else { loop_rest.slice(0..min_record_len) };
+        let record_offset = 4 + body.len() - loop_rest.len();
+        let (record_len, record) = read(cur).map_err(|e| e.shift(record_offset))?;
+        loop_rest = loop_rest.slice(record_len..loop_rest.len());
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
 impl DataSet {
-    //expects the first byte of i to be the first byte of the data packet set id
-    pub fn get_datasets<'a>(i: &'a [u8], tmp_ring: &TemplateRing, odid: u32) -> Result<(&'a [u8], Vec<Self>), String> {
-        //take data set header
-        let (rest, set_id) = be_u16::<&[u8], VerboseError<&[u8]>>(i)
-            .or(Result::Err(String::from("Failed to parse data set set_id")))?;
+    //reads a single record laid out according to the template
+    //returns the number of bytes the record took up along with the record
+    fn read_record(i: &[u8], template: &IPFIXTemplate, elements: &[Option<Arc<InformationElement>>], set_id: u16) -> Result<(usize, Self), ParseError> {
+        let (record_len, fields) = read_fields(i, 0, &template.fields, elements, set_id)?;
+        Ok((record_len, DataSet { id: set_id, fields, template: template.id }))
+    }
 
-        //take length
-        let (rest, len) = be_u16::<&[u8], VerboseError<&[u8]>>(rest)
-            .or(Result::Err(String::from("Failed to parse data set len")))?;
+    //expects the first byte of i to be the first byte of the data packet set id
+    //returns every record in the set plus the first byte after the set (nominally the first byte of the next set's set id)
+    pub fn get_datasets<'a>(i: &'a [u8], tmp_ring: &TemplateRing, exporter: SocketAddr, odid: u32) -> Result<(&'a [u8], Vec<Self>), ParseError> {
+        let (set_id, body, next) = split_data_set(i)?;
 
         //get the template
-        let template = match tmp_ring.get_template(set_id, odid) {
-            None => { return Result::Err(format!("No template with ID {} in template set", set_id)); },
+        let template = match tmp_ring.get_template(exporter, set_id, odid) {
+            None => { return Result::Err(ParseError::UnknownTemplate { offset: 0, set_id, odid }); },
             Some(t) => t
         };
 
-        //keep going until we run out of records
-        let mut bytes_read = 0;
-        let mut loop_rest = rest;
-        let mut cur;
-        let mut datasets = Vec::new();
-        while bytes_read < len {
-            //multiple "instances" of templates come in each data packet, read one here
-            (cur, loop_rest) = loop_rest.split_at(len as usize);
-            let mut ds = DataSet { id: set_id, fields: Vec::new(), template: template.id };
-
-            for tmplt_field in template.fields.iter() {
-                let val = read_value_from_byte(cur, tmplt_field.start_byte as usize, tmplt_field.width)?;
-                bytes_read += tmplt_field.width;
-                ds.fields.push(DataRow::new(tmplt_field.field_id, tmplt_field.en, val));
+        let elements = tmp_ring.registry().resolve(&template.fields);
+        let datasets = read_records(body, set_id, template.min_record_len(), template.has_variable_length_fields(), |rec| {
+            DataSet::read_record(rec, &template, &elements, set_id)
+        })?;
 
+        Ok((next, datasets))
+    }
+
+    //reads records that are all record_len bytes long, with every field at its start_byte instead of straight after the one before it
+    //this is for NetFlow v5, whose records have padding between fields and no set header, body is just the records
+    //elements are the template's fields resolved against the registry, in the same order
+    pub fn get_fixed_records(body: &[u8], template: &IPFIXTemplate, elements: &[Option<Arc<InformationElement>>], record_len: usize) -> Result<Vec<Self>, ParseError> {
+        read_records(body, template.id, record_len, false, |rec| {
+            let mut fields = Vec::with_capacity(template.fields.len());
+            for (f, element) in template.fields.iter().zip(elements.iter()) {
+                let val = read_value_from_byte(rec, f.start_byte as usize, f, element.as_deref(), template.id)?;
+                fields.push(DataRow::with_element(f.field_id, f.en, element.clone(), val));
             }
-            datasets.push(ds);
-        }
+            Ok((record_len, DataSet { id: template.id, template: template.id, fields }))
+        })
+    }
+}
+
+impl OptionsDataSet {
+    //reads a single record laid out according to the options template, scope fields come first
+    //returns the number of bytes the record took up along with the record
+    fn read_record(i: &[u8], template: &IPFIXOptionsTemplate, scope_elements: &[Option<Arc<InformationElement>>], elements: &[Option<Arc<InformationElement>>], set_id: u16) -> Result<(usize, Self), ParseError> {
+        let (scope_len, scope_fields) = read_fields(i, 0, &template.scope_fields, scope_elements, set_id)?;
+        let (record_len, fields) = read_fields(i, scope_len, &template.fields, elements, set_id)?;
+        Ok((record_len, OptionsDataSet { id: set_id, template: template.id, scope_fields, fields }))
+    }
+
+    //expects the first byte of i to be the first byte of the data packet set id, and the set id to belong to an options template
+    //returns every record in the set plus the first byte after the set (nominally the first byte of the next set's set id)
+    pub fn get_datasets<'a>(i: &'a [u8], tmp_ring: &TemplateRing, exporter: SocketAddr, odid: u32) -> Result<(&'a [u8], Vec<Self>), ParseError> {
+        let (set_id, body, next) = split_data_set(i)?;
+
+        //get the template
+        let template = match tmp_ring.get_options_template(exporter, set_id, odid) {
+            None => { return Result::Err(ParseError::UnknownTemplate { offset: 0, set_id, odid }); },
+            Some(t) => t
+        };
 
-        Ok((loop_rest, datasets))
+        let scope_elements = tmp_ring.registry().resolve(&template.scope_fields);
+        let elements = tmp_ring.registry().resolve(&template.fields);
+        let datasets = read_records(body, set_id, template.min_record_len(), template.has_variable_length_fields(), |rec| {
+            OptionsDataSet::read_record(rec, &template, &scope_elements, &elements, set_id)
+        })?;
+
+        Ok((next, datasets))
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::information_elements::DataTypeSemantics;
+    use crate::parse_packet::test_exporter;
+    use crate::templates::VARIABLE_LENGTH;
+
+    fn field(field_id: u16, width: u16) -> IPFIXField {
+        IPFIXField { width, start_byte: 0, en: 0, field_id, variable_length: false }
+    }
+
+    fn varlen_field(field_id: u16) -> IPFIXField {
+        IPFIXField { width: VARIABLE_LENGTH, start_byte: 0, en: 0, field_id, variable_length: true }
+    }
+
+    //a data set with the given id holding records, padded with zeros up to len
+    fn data_set(set_id: u16, records: &[&[u8]], len: usize) -> Vec<u8> {
+        let mut set = Vec::with_capacity(len);
+        set.extend_from_slice(&set_id.to_be_bytes());
+        set.extend_from_slice(&(len as u16).to_be_bytes());
+        for r in records {
+            set.extend_from_slice(r);
+        }
+        set.resize(len, 0);
+        set
+    }
+
+    //every byte after the first used to be added to the shift amount instead of shifted into place
+    #[test]
+    fn read_u64_puts_every_byte_in_place() {
+        assert_eq!(read_u64(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]), 0x0102_0304_0506_0708);
+        assert_eq!(read_u64(&[0, 0, 0, 0, 0, 0, 0x01, 0]), 0x100);
+        assert!(matches!(decode_by_width(&[0xff; 8], false), DataType::U64(u64::MAX)));
+    }
+
+    //a field that ends exactly where the record ends used to be out of range
+    #[test]
+    fn field_can_end_at_the_end_of_the_record() {
+        let record = [0x0a, 0x00, 0x00, 0x01];
+        assert!(matches!(read_value_from_byte(&record, 0, &field(8, 4), None, 256), Ok(DataType::U32(0x0a00_0001))));
+        assert!(matches!(read_value_from_byte(&record, 2, &field(7, 2), None, 256), Ok(DataType::U16(1))));
+        assert!(matches!(read_value_from_byte(&record, 1, &field(8, 4), None, 256), Err(ParseError::FieldOutOfRange { offset: 1, width: 4, available: 3, .. })));
+    }
+
+    #[test]
+    fn variable_lengths_have_a_short_and_a_long_form() {
+        let f = varlen_field(82);
+        assert_eq!(read_variable_length(&[3, b'e', b't', b'h'], 0, &f, 256), Ok((3, 1)));
+        assert_eq!(read_variable_length(&[0], 0, &f, 256), Ok((0, 1)));
+        //255 means the real length follows in two bytes, which also allows for lengths below 255
+        assert_eq!(read_variable_length(&[255, 0x01, 0x2c], 0, &f, 256), Ok((300, 3)));
+        assert_eq!(read_variable_length(&[0, 255, 0, 3], 1, &f, 256), Ok((3, 3)));
+
+        assert_eq!(read_variable_length(&[255, 0x01], 0, &f, 256), Err(ParseError::FieldOutOfRange { offset: 0, set_id: 256, field_id: 82, width: 3, available: 2 }));
+        assert_eq!(read_variable_length(&[4], 1, &f, 256), Err(ParseError::FieldOutOfRange { offset: 1, set_id: 256, field_id: 82, width: 1, available: 0 }));
+    }
+
+    #[test]
+    fn fields_after_a_variable_length_field_move_with_it() {
+        let registry = crate::information_elements::IERegistry::iana();
+        let fields = [varlen_field(82), field(8, 4)];
+        let elements = registry.resolve(&fields);
+
+        let short = [3, b'e', b't', b'h', 10, 0, 0, 1];
+        let (end, rows) = read_fields(&short, 0, &fields, &elements, 256).expect("record should parse");
+        assert_eq!(end, 8);
+        assert!(matches!(&rows[0].data, DataType::STRING(s) if s == "eth"));
+        assert!(matches!(rows[1].data, DataType::IPV4(a) if a == Ipv4Addr::new(10, 0, 0, 1)));
+
+        let long = [255, 0, 4, b'e', b't', b'h', b'0', 10, 0, 0, 2];
+        let (end, rows) = read_fields(&long, 0, &fields, &elements, 256).expect("record should parse");
+        assert_eq!(end, 11);
+        assert!(matches!(&rows[0].data, DataType::STRING(s) if s == "eth0"));
+        assert!(matches!(rows[1].data, DataType::IPV4(a) if a == Ipv4Addr::new(10, 0, 0, 2)));
+
+        //a length that runs past the record
+        assert_eq!(read_fields(&[9, b'e', b't', b'h'], 0, &fields, &elements, 256).err(), Some(ParseError::FieldOutOfRange { offset: 1, set_id: 256, field_id: 82, width: 9, available: 3 }));
+        //and a fixed field that no longer fits once the variable length one has taken its share
+        assert_eq!(read_fields(&short[..7], 0, &fields, &elements, 256).err(), Some(ParseError::FieldOutOfRange { offset: 4, set_id: 256, field_id: 8, width: 4, available: 3 }));
+    }
+
+    #[test]
+    fn data_sets_hold_records_of_different_lengths() {
+        let ring = TemplateRing::new();
+        ring.insert_template(test_exporter(), IPFIXTemplate { id: 256, odid: 1, fields: vec![varlen_field(82), field(8, 4)] }, 1);
+
+        //the smallest record is 5 bytes, the 4 zeros after the last record are padding
+        let records: [&[u8]; 3] = [&[2, b'l', b'o', 127, 0, 0, 1], &[0, 10, 0, 0, 1], &[255, 0, 4, b'e', b't', b'h', b'0', 10, 0, 0, 2]];
+        let mut bytes = data_set(256, &records, 4 + 7 + 5 + 11 + 4);
+        bytes.push(0xaa);
+        let (next, sets) = match DataSet::get_datasets(&bytes, &ring, test_exporter(), 1) {
+            Ok(v) => v,
+            Err(e) => panic!("data set should parse: {}", e)
+        };
+        assert_eq!(next, &[0xaa]);
+
+        let names: Vec<String> = sets.iter().map(|d| match &d.fields[0].data { DataType::STRING(s) => s.clone(), _ => panic!("interfaceName should be a string") }).collect();
+        assert_eq!(names, vec!["lo", "", "eth0"]);
+        let addrs: Vec<Ipv4Addr> = sets.iter().map(|d| match d.fields[1].data { DataType::IPV4(a) => a, _ => panic!("sourceIPv4Address should be an address") }).collect();
+        assert_eq!(addrs, vec![Ipv4Addr::new(127, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]);
+
+        //errors in a later record point at where it is in the set
+        let bytes = data_set(256, &[&[2, b'l', b'o', 127, 0, 0, 1], &[9, 10, 0, 0, 1]], 4 + 7 + 5);
+        assert_eq!(DataSet::get_datasets(&bytes, &ring, test_exporter(), 1).err(), Some(ParseError::FieldOutOfRange { offset: 12, set_id: 256, field_id: 82, width: 9, available: 4 }));
+    }
+
+    #[test]
+    fn options_records_can_have_variable_length_scope_fields() {
+        let ring = TemplateRing::new();
+        let template = IPFIXOptionsTemplate { id: 300, odid: 1, scope_field_count: 1, scope_fields: vec![varlen_field(82)], fields: vec![field(10, 4)] };
+        ring.insert_options_template(test_exporter(), template, 1);
+
+        let bytes = data_set(300, &[&[4, b'e', b't', b'h', b'0', 0, 0, 0, 7], &[2, b'l', b'o', 0, 0, 0, 1]], 4 + 9 + 7);
+        let (_next, sets) = match OptionsDataSet::get_datasets(&bytes, &ring, test_exporter(), 1) {
+            Ok(v) => v,
+            Err(e) => panic!("options data set should parse: {}", e)
+        };
+        assert_eq!(sets.len(), 2);
+        assert!(matches!(&sets[1].scope_fields[0].data, DataType::STRING(s) if s == "lo"));
+        assert!(matches!(sets[0].fields[0].data, DataType::U32(7)));
+        assert!(matches!(sets[1].fields[0].data, DataType::U32(1)));
+    }
+
+    #[test]
+    fn reduced_size_integers_keep_their_type() {
+        assert!(matches!(decode_typed(&[0x01, 0x02, 0x03], AbstractDataType::Unsigned32), Some(DataType::U32(0x01_0203))));
+        assert!(matches!(decode_typed(&[0xff], AbstractDataType::Unsigned16), Some(DataType::U16(0xff))));
+        assert!(matches!(decode_typed(&[0x80, 0, 0, 0, 0], AbstractDataType::Unsigned64), Some(DataType::U64(0x80_0000_0000))));
+        //too wide for the type isn't a reduced size, it is something else
+        assert!(decode_typed(&[0, 0, 1], AbstractDataType::Unsigned16).is_none());
+        assert!(decode_typed(&[], AbstractDataType::Unsigned32).is_none());
+    }
+
+    #[test]
+    fn reduced_size_signed_integers_are_sign_extended() {
+        assert!(matches!(decode_typed(&[0xfe], AbstractDataType::Signed16), Some(DataType::I16(-2))));
+        assert!(matches!(decode_typed(&[0xff, 0x85], AbstractDataType::Signed32), Some(DataType::I32(-123))));
+        assert!(matches!(decode_typed(&[0x80, 0x00, 0x00], AbstractDataType::Signed32), Some(DataType::I32(-0x80_0000))));
+        assert!(matches!(decode_typed(&[0x7f], AbstractDataType::Signed64), Some(DataType::I64(127))));
+        assert!(matches!(decode_typed(&[0xff; 8], AbstractDataType::Signed64), Some(DataType::I64(-1))));
+        assert_eq!(read_int(&[0x80, 0, 0, 0, 0, 0, 0, 0]), i64::MIN);
+    }
+
+    #[test]
+    fn floats_decode_by_width() {
+        let one_and_a_half = 1.5f32.to_be_bytes();
+        assert!(matches!(decode_typed(&one_and_a_half, AbstractDataType::Float32), Some(DataType::F32(f)) if f == 1.5));
+        //a float64 sent as a float32 still comes out as a float64
+        assert!(matches!(decode_typed(&one_and_a_half, AbstractDataType::Float64), Some(DataType::F64(f)) if f == 1.5));
+        assert!(matches!(decode_typed(&(-0.1f64).to_be_bytes(), AbstractDataType::Float64), Some(DataType::F64(f)) if f == -0.1));
+        assert!(decode_typed(&[0, 0], AbstractDataType::Float32).is_none());
+    }
+
+    #[test]
+    fn booleans_are_one_and_two() {
+        assert!(matches!(decode_typed(&[1], AbstractDataType::Boolean), Some(DataType::BOOL(true))));
+        assert!(matches!(decode_typed(&[2], AbstractDataType::Boolean), Some(DataType::BOOL(false))));
+        assert!(decode_typed(&[0], AbstractDataType::Boolean).is_none());
+        //anything else falls back to the raw value
+        let element = InformationElement { id: 1, en: 9, name: String::from("flag"), data_type: AbstractDataType::Boolean, semantics: DataTypeSemantics::Default, units: None };
+        assert!(matches!(decode_value(&[3], Some(&element), false), DataType::U8(3)));
+    }
+
+    fn element(data_type: AbstractDataType) -> InformationElement {
+        InformationElement { id: 1, en: 9, name: String::from("test"), data_type, semantics: DataTypeSemantics::Default, units: None }
+    }
+
+    #[test]
+    fn addresses_decode_by_type() {
+        let mac = [0x00, 0x1b, 0x21, 0x3c, 0x4d, 0x5e];
+        assert!(matches!(decode_typed(&mac, AbstractDataType::MacAddress), Some(DataType::MAC(m)) if m == mac));
+        assert!(matches!(decode_typed(&[192, 0, 2, 1], AbstractDataType::Ipv4Address), Some(DataType::IPV4(a)) if a == Ipv4Addr::new(192, 0, 2, 1)));
+        let v6: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        assert!(matches!(decode_typed(&v6.octets(), AbstractDataType::Ipv6Address), Some(DataType::IPV6(a)) if a == v6));
+
+        //an address of the wrong width is left as the raw value
+        assert!(decode_typed(&mac[..5], AbstractDataType::MacAddress).is_none());
+        assert!(matches!(decode_value(&mac[..5], Some(&element(AbstractDataType::MacAddress)), false), DataType::BYTES(b) if b == mac[..5]));
+        assert!(decode_typed(&[192, 0, 2, 1], AbstractDataType::Ipv6Address).is_none());
+        assert!(matches!(decode_value(&[192, 0, 2, 1], Some(&element(AbstractDataType::Ipv6Address)), false), DataType::U32(0xc000_0201)));
+        assert!(matches!(decode_value(&v6.octets(), Some(&element(AbstractDataType::Ipv4Address)), false), DataType::BYTES(b) if b == v6.octets()));
+    }
+
+    #[test]
+    fn strings_take_any_width() {
+        assert!(matches!(decode_typed(b"eth0", AbstractDataType::String), Some(DataType::STRING(s)) if s == "eth0"));
+        assert!(matches!(decode_typed(b"", AbstractDataType::String), Some(DataType::STRING(s)) if s.is_empty()));
+        //bytes that aren't UTF-8 are replaced rather than failing the whole value
+        assert!(matches!(decode_typed(&[b'a', 0xff, b'b'], AbstractDataType::String), Some(DataType::STRING(s)) if s == "a\u{fffd}b"));
+        //a variable length string of 4 bytes is still a string, and a variable length value without an element is never an integer
+        assert!(matches!(decode_value(b"lo00", Some(&element(AbstractDataType::String)), true), DataType::STRING(s) if s == "lo00"));
+        assert!(matches!(decode_value(b"lo00", None, true), DataType::BYTES(b) if b == b"lo00"));
+    }
+
+    #[test]
+    fn timestamps_count_from_their_epochs() {
+        let mut ntp = Vec::new();
+        ntp.extend_from_slice(&((NTP_UNIX_OFFSET + 1) as u32).to_be_bytes());
+        ntp.extend_from_slice(&0x8000_0000u32.to_be_bytes());
+        let one_and_a_half = UNIX_EPOCH + Duration::from_millis(1500);
+        assert!(matches!(decode_typed(&ntp, AbstractDataType::DateTimeNanoseconds), Some(DataType::DATETIME(t)) if t == one_and_a_half));
+
+        //the bottom 11 bits of a dateTimeMicroseconds fraction are ignored
+        ntp[4..8].copy_from_slice(&0x8000_07ffu32.to_be_bytes());
+        assert!(matches!(decode_typed(&ntp, AbstractDataType::DateTimeMicroseconds), Some(DataType::DATETIME(t)) if t == one_and_a_half));
+        assert!(matches!(decode_typed(&ntp, AbstractDataType::DateTimeNanoseconds), Some(DataType::DATETIME(t)) if t > one_and_a_half));
+
+        //the NTP epoch is 70 years before the unix one
+        let before_unix = UNIX_EPOCH - Duration::from_secs(NTP_UNIX_OFFSET - 1) + Duration::from_millis(500);
+        assert!(matches!(decode_typed(&[0, 0, 0, 1, 0x80, 0, 0, 0], AbstractDataType::DateTimeNanoseconds), Some(DataType::DATETIME(t)) if t == before_unix));
+
+        assert!(matches!(decode_typed(&[0, 0, 0, 60], AbstractDataType::DateTimeSeconds), Some(DataType::DATETIME(t)) if t == UNIX_EPOCH + Duration::from_secs(60)));
+        assert!(matches!(decode_typed(&1500u64.to_be_bytes(), AbstractDataType::DateTimeMilliseconds), Some(DataType::DATETIME(t)) if t == one_and_a_half));
+    }
+
+    //how far SystemTime reaches depends on the platform, a time past that has to come out as the raw value instead of panicking
+    #[test]
+    fn timestamps_past_what_system_time_holds_dont_panic() {
+        let element = InformationElement { id: 152, en: 0, name: String::from("flowStartMilliseconds"), data_type: AbstractDataType::DateTimeMilliseconds, semantics: DataTypeSemantics::Default, units: None };
+        match decode_value(&[0xff; 8], Some(&element), false) {
+            DataType::DATETIME(t) => assert_eq!(t.duration_since(UNIX_EPOCH).ok(), Some(Duration::from_millis(u64::MAX))),
+            DataType::U64(v) => assert_eq!(v, u64::MAX),
+            _ => panic!("a timestamp should be a time or its raw value")
+        }
+    }
+}
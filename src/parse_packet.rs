@@ -2,133 +2,452 @@ use nom::error::VerboseError;
 use nom::number::complete::{be_u16, be_u32};
 use nom::Slice;
 
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::config::{Protocol, ValidationMode};
+use crate::error::{ParseError, truncated};
 use crate::parse_data::*;
 use crate::template_ring::TemplateRing;
-use crate::templates::IPFIXTemplate;
+use crate::templates::{IPFIXTemplate, IPFIXOptionsTemplate, TemplateRecord, TemplateWithdrawal, V5_RECORD_LEN, V9_OPTIONS_TEMPLATE_FLOWSET_ID, V9_TEMPLATE_FLOWSET_ID};
+use crate::tls::ExporterIdentity;
+
+//version, length, export time, sequence number, observation domain id
+pub(crate) const MESSAGE_HEADER_LEN: usize = 16;
+//version, count, system uptime, unix seconds, sequence number, source id
+const V9_HEADER_LEN: usize = 20;
+//version, count, system uptime, unix seconds, unix nanoseconds, flow sequence, engine type, engine id, sampling interval
+const V5_HEADER_LEN: usize = 24;
+
+pub const IPFIX_VERSION: u16 = 10;
+pub const NETFLOW_V9_VERSION: u16 = 9;
+pub const NETFLOW_V5_VERSION: u16 = 5;
 
 pub enum PacketResult {
     Ok(PacketInfo),
-    AbortError
+    AbortError(ParseError)
 }
 
+//NetFlow v9 messages are decoded into the same structure, their flowsets are sets and their source id is the ODID
+//so are NetFlow v5 messages, their records come out as data sets with the template id V5_TEMPLATE_ID and their engine type and id make up the ODID
+#[derive(Clone)]
 pub struct PacketInfo {
-    pub export_time: u32,
-    pub seq_num: u32,
+    pub protocol: Protocol, //which listener the message came in on, sFlow datagrams are turned into PacketInfos too (see sflow.rs)
+    pub version: u16, //10 for IPFIX, 9 for NetFlow v9, 5 for NetFlow v5 or sFlow
+    pub export_time: u32, //seconds since the unix epoch from the message header, sFlow datagrams don't carry one so for them it is when the datagram arrived
+    pub seq_num: u32, //IPFIX and NetFlow v5 count data records, NetFlow v9 counts messages
+    pub system_uptime: u32, //NetFlow only (0 for IPFIX), milliseconds since the exporter booted when the message was sent, flow start and end times are relative to it
     pub templates: Vec<IPFIXTemplate>,
+    pub options_templates: Vec<IPFIXOptionsTemplate>,
+    pub withdrawals: Vec<TemplateWithdrawal>,
     pub data: Vec<DataSet>,
+    pub options_data: Vec<OptionsDataSet>,
     pub set_error_count: u32,
-    pub odid: u32
+    pub set_errors: Vec<ParseError>, //what went wrong with each set we had to skip, set_error_count is the length of this
+    pub odid: u32, //the sub agent id for sFlow, which can be the same number as an IPFIX ODID, protocol tells them apart
+    pub exporter: SocketAddr, //where the message came from
+    pub exporter_identity: Option<Arc<ExporterIdentity>>, //the certificate the exporter presented, only for TLS connections that sent one
+    pub header_error: Option<Box<ParseError>> //a message length (or NetFlow v5 record count) that didn't match the datagram, only ever set in lenient mode, boxed since it is nearly always None
 }
 
 impl PacketInfo {
-    fn new(export_time: u32, seq_num: u32, odid: u32, parse_results: Vec<ParseResult>) -> PacketResult {
+    fn build(header: MessageHeader, exporter: SocketAddr, header_error: Option<ParseError>, parse_results: Vec<ParseResult>) -> PacketResult {
         let mut templates = Vec::new();
+        let mut options_templates = Vec::new();
+        let mut withdrawals = Vec::new();
         let mut data = Vec::new();
-        let mut set_error_count: u32 = 0;
+        let mut options_data = Vec::new();
+        let mut set_errors = Vec::new();
 
         for r in parse_results {
             match r {
-                ParseResult::AbortError => { return PacketResult::AbortError; },
-                ParseResult::Error => { set_error_count += 1; }
-                ParseResult::Template(t) => { templates.push(t); }
+                ParseResult::AbortError(e) => { return PacketResult::AbortError(e); },
+                ParseResult::Error(e) => { set_errors.push(e); }
+                ParseResult::Template(records) => {
+                    for r in records {
+                        match r {
+                            TemplateRecord::Template(t) => { templates.push(t); },
+                            TemplateRecord::Withdrawal(w) => { withdrawals.push(w); }
+                        }
+                    }
+                }
+                ParseResult::OptionsTemplate(records) => {
+                    for r in records {
+                        match r {
+                            TemplateRecord::Template(t) => { options_templates.push(t); },
+                            TemplateRecord::Withdrawal(w) => { withdrawals.push(w); }
+                        }
+                    }
+                }
                 ParseResult::Data(d) => { data.extend(d); }
+                ParseResult::OptionsData(d) => { options_data.extend(d); }
             }
         }
 
         PacketResult::Ok(PacketInfo { 
-            export_time: export_time, 
-            seq_num: seq_num, 
-            templates: templates, 
-            data: data, 
-            set_error_count: set_error_count,
-            odid: odid
+            protocol: Protocol::IPFIX,
+            version: header.version,
+            export_time: header.export_time, 
+            seq_num: header.seq_num, 
+            system_uptime: header.system_uptime,
+            templates, 
+            options_templates,
+            withdrawals,
+            data, 
+            options_data,
+            set_error_count: set_errors.len() as u32,
+            set_errors,
+            odid: header.odid,
+            exporter,
+            exporter_identity: None,
+            header_error: header_error.map(Box::new)
         })
         
     }
 }
 
+#[cfg(test)]
+impl PacketInfo {
+    //an IPFIX message with nothing in it, for tests to fill in
+    pub(crate) fn empty(exporter: SocketAddr, odid: u32) -> Self {
+        PacketInfo {
+            protocol: Protocol::IPFIX,
+            version: IPFIX_VERSION,
+            export_time: 0,
+            seq_num: 0,
+            system_uptime: 0,
+            templates: Vec::new(),
+            options_templates: Vec::new(),
+            withdrawals: Vec::new(),
+            data: Vec::new(),
+            options_data: Vec::new(),
+            set_error_count: 0,
+            set_errors: Vec::new(),
+            odid,
+            exporter,
+            exporter_identity: None,
+            header_error: None
+        }
+    }
+
+    //an IPFIX message from test_exporter with one (empty) record for each template
+    pub(crate) fn with_records(odid: u32, seq_num: u32, templates: &[u16]) -> Self {
+        let mut info = PacketInfo::empty(test_exporter(), odid);
+        info.seq_num = seq_num;
+        info.data = templates.iter().map(|t| DataSet { id: *t, template: *t, fields: Vec::new() }).collect();
+        info
+    }
+}
+
+//where the packets tests make up come from
+#[cfg(test)]
+pub(crate) fn test_exporter() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 4739))
+}
+
+//somewhere in the temp directory that no other test, or other run of the tests, uses
+#[cfg(test)]
+pub(crate) fn test_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("ipfix_parser_rs-{}-{}", name, std::process::id()))
+}
+
+//the parts of an IPFIX or NetFlow v9 message header that end up in PacketInfo
+struct MessageHeader {
+    version: u16,
+    export_time: u32,
+    seq_num: u32,
+    odid: u32,
+    system_uptime: u32
+}
+
 enum ParseResult {
     Data(Vec<DataSet>),
-    Template(IPFIXTemplate),
-    Error, //error where we can keep reading the packet
-    AbortError //error where we can NOT keep reading the packet
+    OptionsData(Vec<OptionsDataSet>),
+    Template(Vec<TemplateRecord<IPFIXTemplate>>),
+    OptionsTemplate(Vec<TemplateRecord<IPFIXOptionsTemplate>>),
+    Error(ParseError), //error where we can keep reading the packet
+    AbortError(ParseError) //error where we can NOT keep reading the packet
 }
 
-fn handle_set<'a>(set_head: &'a [u8], odid: u32, tring: &TemplateRing) -> (ParseResult, u16, &'a [u8]) {
+//set_offset is where set_head starts in the message, it is only used to make error offsets relative to the start of the message
+//set_head must only contain what is left of the message body, the set length is checked against it before anything is read
+//templates and withdrawals are applied to tring as soon as their set is read, so data sets later in the same message can use them
+//version picks which set ids hold templates, NetFlow v9 flowsets are laid out the same way as IPFIX sets
+fn handle_set<'a>(set_head: &'a [u8], set_offset: usize, version: u16, exporter: SocketAddr, odid: u32, tring: &TemplateRing) -> (ParseResult, &'a [u8]) {
     let (id_rest, set_id) = match be_u16::<&[u8], VerboseError<&[u8]>>(set_head) {
         Ok(v) => v,
-        Err(_e) => { return (ParseResult::AbortError, 0, set_head); }
+        Err(_e) => { return (ParseResult::AbortError(truncated(set_head, set_head, 2).shift(set_offset)), set_head); }
     };
 
     let (_len_rest, set_len) = match be_u16::<&[u8], VerboseError<&[u8]>>(id_rest) {
         Ok(v) => v,
-        Err(_e) => {return (ParseResult::AbortError, 0, id_rest); }
+        Err(_e) => {return (ParseResult::AbortError(truncated(set_head, id_rest, 2).shift(set_offset)), id_rest); }
+    };
+
+    //if the set length is wrong we have no idea where the next set starts, so there is no way to keep reading the message
+    if set_len < 4 || set_len as usize > set_head.len() {
+        return (ParseResult::AbortError(ParseError::SetLengthOverflow { offset: set_offset, set_id, set_len, available: set_head.len() }), set_head);
+    }
+
+    //the set parsers only ever get to see their own set, so a bad template can't make them read into the next one
+    let set = set_head.slice(0..set_len as usize);
+    let next = set_head.slice(set_len as usize..set_head.len());
+
+    let (template_set_id, options_template_set_id) = match version {
+        NETFLOW_V9_VERSION => (V9_TEMPLATE_FLOWSET_ID, V9_OPTIONS_TEMPLATE_FLOWSET_ID),
+        _ => (2, 3)
     };
 
-    let data;
-    let next;
-    if set_id == 2 {
-        (next, data) = match IPFIXTemplate::from(set_head, odid) {
-            Ok((n, t)) => (n, ParseResult::Template(t)),
-            Err(_e) => (set_head.slice((set_len as usize)..set_head.len()), ParseResult::Error)
+    let data = if set_id == template_set_id {
+        let parsed = match version {
+            NETFLOW_V9_VERSION => IPFIXTemplate::from_v9_set(set, odid),
+            _ => IPFIXTemplate::from_set(set, odid)
         };
+        match parsed {
+            Ok((_n, t)) => {
+                for r in t.iter() {
+                    match r {
+                        TemplateRecord::Template(t) => { tring.insert_template(exporter, t.clone(), odid); },
+                        TemplateRecord::Withdrawal(w) => { tring.withdraw(exporter, w); }
+                    }
+                }
+                ParseResult::Template(t)
+            },
+            Err(e) => ParseResult::Error(e.shift(set_offset))
+        }
     }
-    else {
-        (next, data) = match DataSet::get_datasets(set_head, tring, odid) {
-            Ok((n, d)) => (n, ParseResult::Data(d)),
-            Err(_e) => (set_head.slice((set_len as usize)..set_head.len()), ParseResult::Error)
+    else if set_id == options_template_set_id {
+        let parsed = match version {
+            NETFLOW_V9_VERSION => IPFIXOptionsTemplate::from_v9_set(set, odid),
+            _ => IPFIXOptionsTemplate::from_set(set, odid)
         };
+        match parsed {
+            Ok((_n, t)) => {
+                for r in t.iter() {
+                    match r {
+                        TemplateRecord::Template(t) => { tring.insert_options_template(exporter, t.clone(), odid); },
+                        TemplateRecord::Withdrawal(w) => { tring.withdraw(exporter, w); }
+                    }
+                }
+                ParseResult::OptionsTemplate(t)
+            },
+            Err(e) => ParseResult::Error(e.shift(set_offset))
+        }
+    }
+    else if tring.get_options_template(exporter, set_id, odid).is_some() {
+        match OptionsDataSet::get_datasets(set, tring, exporter, odid) {
+            Ok((_n, d)) => ParseResult::OptionsData(d),
+            Err(e) => ParseResult::Error(e.shift(set_offset))
+        }
     }
+    else {
+        match DataSet::get_datasets(set, tring, exporter, odid) {
+            Ok((_n, d)) => ParseResult::Data(d),
+            Err(e) => ParseResult::Error(e.shift(set_offset))
+        }
+    };
 
-    return (data, set_len, next);
+    (data, next)
 }
 
-pub fn parse_packet(tring: &TemplateRing, pkt: &[u8]) -> PacketResult {
-    let (rest, _version) = match be_u16::<&[u8], VerboseError<&[u8]>>(pkt) {
+//the templates and withdrawals in the packet are applied to tring as they are read, they are also returned as part of the packet
+//exporter is the transport session the packet arrived on, templates are only used for packets from the exporter that sent them
+//mode decides what happens when the length in the header doesn't match the datagram, see ValidationMode
+//IPFIX, NetFlow v9, and NetFlow v5 messages are all accepted, the version at the start of the message decides which one it is
+pub fn parse_packet(tring: &TemplateRing, exporter: SocketAddr, pkt: &[u8], mode: ValidationMode) -> PacketResult {
+    let (rest, version) = match be_u16::<&[u8], VerboseError<&[u8]>>(pkt) {
         Ok(v) => v,
-        Err(_e) => { return PacketResult::AbortError; }
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, pkt, 2)); }
     };
 
+    match version {
+        IPFIX_VERSION => parse_ipfix(tring, exporter, pkt, rest, mode),
+        NETFLOW_V9_VERSION => parse_netflow_v9(tring, exporter, pkt, rest),
+        NETFLOW_V5_VERSION => parse_netflow_v5(tring, exporter, pkt, rest, mode),
+        //anything else is some other protocol, there is no point reading further
+        _ => PacketResult::AbortError(ParseError::BadVersion { version })
+    }
+}
+
+//rest is everything after the version
+fn parse_ipfix(tring: &TemplateRing, exporter: SocketAddr, pkt: &[u8], rest: &[u8], mode: ValidationMode) -> PacketResult {
     let (rest, len) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest) {
         Ok(v) => v,
-        Err(_e) => {return PacketResult::AbortError; }
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 2)); }
+    };
+
+    let (rest, export_time) = match be_u32::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => v,
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 4)); }
+    };
+
+    let (rest, seq_num) = match be_u32::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => v,
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 4)); }
+    };
+
+    let (_rest, odid) = match be_u32::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => v,
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 4)); }
+    };
+
+    //the length covers the header too
+    if (len as usize) < MESSAGE_HEADER_LEN {
+        return PacketResult::AbortError(ParseError::BadMessageLength { length: len as usize, available: pkt.len() });
+    }
+
+    //over UDP every datagram holds exactly one message, so any difference means the message was cut short or has junk after it
+    let mut header_error = None;
+    if len as usize != pkt.len() {
+        let e = ParseError::BadMessageLength { length: len as usize, available: pkt.len() };
+        match mode {
+            ValidationMode::Strict => { return PacketResult::AbortError(e); },
+            ValidationMode::Lenient => { header_error = Some(e); }
+        }
+    }
+
+    //in lenient mode a message cut short is read as far as it goes, and bytes after the end of the message are ignored
+    let cut_short = len as usize > pkt.len();
+    let body = pkt.slice(MESSAGE_HEADER_LEN..(len as usize).min(pkt.len()));
+
+    let header = MessageHeader { version: IPFIX_VERSION, export_time, seq_num, odid, system_uptime: 0 };
+    match read_sets(body, MESSAGE_HEADER_LEN, IPFIX_VERSION, exporter, odid, tring, cut_short) {
+        Ok(result_vec) => PacketInfo::build(header, exporter, header_error, result_vec),
+        Err(e) => PacketResult::AbortError(e)
+    }
+}
+
+//rest is everything after the version
+//v9 headers have a record count instead of a length, the message is the whole datagram so there is no length to check
+fn parse_netflow_v9(tring: &TemplateRing, exporter: SocketAddr, pkt: &[u8], rest: &[u8]) -> PacketResult {
+    //exporters get the count wrong often enough that it isn't worth checking
+    let (rest, _count) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => v,
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 2)); }
+    };
+
+    let (rest, system_uptime) = match be_u32::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => v,
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 4)); }
+    };
+
+    let (rest, export_time) = match be_u32::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => v,
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 4)); }
+    };
+
+    let (rest, seq_num) = match be_u32::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => v,
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 4)); }
+    };
+
+    let (_rest, source_id) = match be_u32::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => v,
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 4)); }
+    };
+
+    let body = pkt.slice(V9_HEADER_LEN..pkt.len());
+
+    let header = MessageHeader { version: NETFLOW_V9_VERSION, export_time, seq_num, odid: source_id, system_uptime };
+    match read_sets(body, V9_HEADER_LEN, NETFLOW_V9_VERSION, exporter, source_id, tring, false) {
+        Ok(result_vec) => PacketInfo::build(header, exporter, None, result_vec),
+        Err(e) => PacketResult::AbortError(e)
+    }
+}
+
+//rest is everything after the version
+//v5 has no sets or templates, just a record count and that many fixed size records
+fn parse_netflow_v5(tring: &TemplateRing, exporter: SocketAddr, pkt: &[u8], rest: &[u8], mode: ValidationMode) -> PacketResult {
+    let (rest, count) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => v,
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 2)); }
+    };
+
+    let (rest, system_uptime) = match be_u32::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => v,
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 4)); }
     };
 
     let (rest, export_time) = match be_u32::<&[u8], VerboseError<&[u8]>>(rest) {
         Ok(v) => v,
-        Err(_e) => { return PacketResult::AbortError; }
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 4)); }
+    };
+
+    //export_time only keeps whole seconds
+    let (rest, _export_nanos) = match be_u32::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => v,
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 4)); }
     };
 
     let (rest, seq_num) = match be_u32::<&[u8], VerboseError<&[u8]>>(rest) {
         Ok(v) => v,
-        Err(_e) => { return PacketResult::AbortError; }
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 4)); }
     };
 
-    let (rest, odid) = match be_u32::<&[u8], VerboseError<&[u8]>>(rest) {
+    //engine type and engine id identify the flow switching engine that sent the message, together they are the closest thing v5 has to an ODID
+    let (rest, engine) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest) {
         Ok(v) => v,
-        Err(_e) => { return PacketResult::AbortError; }
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 2)); }
     };
 
+    let (_rest, _sampling_interval) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => v,
+        Err(_e) => { return PacketResult::AbortError(truncated(pkt, rest, 2)); }
+    };
+
+    //the count stands in for a length, the datagram should hold exactly that many records
+    let expected_len = V5_HEADER_LEN + count as usize * V5_RECORD_LEN;
+    let mut header_error = None;
+    if expected_len != pkt.len() {
+        let e = ParseError::BadMessageLength { length: expected_len, available: pkt.len() };
+        match mode {
+            ValidationMode::Strict => { return PacketResult::AbortError(e); },
+            ValidationMode::Lenient => { header_error = Some(e); }
+        }
+    }
+
+    //in lenient mode only the records that fully arrived are read, and anything after the last counted record is ignored
+    let body = pkt.slice(V5_HEADER_LEN..expected_len.min(pkt.len()));
+    let odid = engine as u32;
+    let (template, elements) = tring.netflow_v5();
+    let data = match DataSet::get_fixed_records(body, template, elements, V5_RECORD_LEN) {
+        Ok(d) => d,
+        Err(e) => { return PacketResult::AbortError(e.shift(V5_HEADER_LEN)); }
+    };
+
+    let header = MessageHeader { version: NETFLOW_V5_VERSION, export_time, seq_num, odid, system_uptime };
+    PacketInfo::build(header, exporter, header_error, vec![ParseResult::Data(data)])
+}
+
+//reads every set in body, which starts header_len bytes into the message
+//if cut_short is set the message didn't fully arrive, so a set running off the end is expected and the sets before it are kept
+fn read_sets(body: &[u8], header_len: usize, version: u16, exporter: SocketAddr, odid: u32, tring: &TemplateRing, cut_short: bool) -> Result<Vec<ParseResult>, ParseError> {
     let mut result_vec = Vec::new();
 
-    let body_len = len - 32;
-    let mut bytes_read: u32 = 0;
-    let mut loop_rest = rest;
+    let mut loop_rest = body;
     let mut cur_data;
-    while bytes_read < body_len as u32 {
-        let set_len;
+    while !loop_rest.is_empty() {
+        let set_offset = header_len + body.len() - loop_rest.len();
+
+        //a few stray bytes at the end of the message can't be a set, note it but keep what we already read
+        if loop_rest.len() < 4 {
+            result_vec.push(ParseResult::Error(truncated(loop_rest, loop_rest, 4).shift(set_offset)));
+            break;
+        }
+
         //process the set
-        (cur_data, set_len, loop_rest) = handle_set(loop_rest, odid, tring);
-        bytes_read += set_len as u32;
+        (cur_data, loop_rest) = handle_set(loop_rest, set_offset, version, exporter, odid, tring);
 
-        match cur_data { 
-            ParseResult::AbortError => { return PacketResult::AbortError; },
-            _ => {}
-        };
+        if let ParseResult::AbortError(e) = cur_data {
+            if cut_short {
+                result_vec.push(ParseResult::Error(e));
+                break;
+            }
+            return Err(e);
+        }
 
         result_vec.push(cur_data);
     }
 
-    PacketInfo::new(export_time, seq_num, odid, result_vec)
-
-}
\ No newline at end of file
+    Ok(result_vec)
+}
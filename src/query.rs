@@ -0,0 +1,221 @@
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+
+use crate::config::Protocol;
+use crate::parse_packet::PacketInfo;
+
+//picks out stored results, every filter that is set has to match, so the default query matches everything
+#[derive(Clone, Default)]
+pub struct ResultQuery {
+    pub odid: Option<u32>, //None matches every observation domain, sFlow sub-agent ids are matched here too
+    pub protocol: Option<Protocol>, //None matches every protocol, set it to tell an IPFIX ODID from an sFlow sub-agent with the same number
+    pub exporter: Option<SocketAddr>, //None matches every exporter, exporters can share ODIDs
+    pub export_time: Option<RangeInclusive<u32>>, //seconds since the unix epoch, from the message header
+    pub template_id: Option<u16> //only keep the (options) data sets decoded with this template
+}
+
+impl ResultQuery {
+    //everything for every ODID
+    pub fn all() -> Self {
+        ResultQuery::default()
+    }
+
+    //everything for a single ODID, from any protocol
+    pub fn odid(odid: u32) -> Self {
+        ResultQuery { odid: Some(odid), ..ResultQuery::default() }
+    }
+
+    fn matches_packet(&self, info: &PacketInfo) -> bool {
+        self.odid.is_none_or(|o| o == info.odid)
+            && self.protocol.is_none_or(|p| p == info.protocol)
+            && self.exporter.is_none_or(|e| e == info.exporter)
+            && self.export_time.as_ref().is_none_or(|r| r.contains(&info.export_time))
+    }
+
+    fn matches_template(&self, template: u16) -> bool {
+        self.template_id.is_none_or(|t| t == template)
+    }
+
+    //the part of a single packet that matches, None if nothing does
+    pub(crate) fn select(&self, info: &PacketInfo) -> Option<PacketInfo> {
+        if !self.matches_packet(info) {
+            return None;
+        }
+
+        if self.template_id.is_none() {
+            return Some(info.clone());
+        }
+
+        let mut selected = empty_copy(info);
+        selected.data = info.data.iter().filter(|d| self.matches_template(d.template)).cloned().collect();
+        selected.options_data = info.options_data.iter().filter(|d| self.matches_template(d.template)).cloned().collect();
+
+        if selected.data.is_empty() && selected.options_data.is_empty() {
+            None
+        }
+        else {
+            Some(selected)
+        }
+    }
+
+    //same as select, but moves the matching part out of the packet instead of copying it
+    //returns (what matched, what is left), when filtering by template the rest of the packet is left as long as it still has data in it
+    pub(crate) fn split(&self, mut info: PacketInfo) -> (Option<PacketInfo>, Option<PacketInfo>) {
+        if !self.matches_packet(&info) {
+            return (None, Some(info));
+        }
+
+        if self.template_id.is_none() {
+            return (Some(info), None);
+        }
+
+        let mut taken = empty_copy(&info);
+        (taken.data, info.data) = info.data.into_iter().partition(|d| self.matches_template(d.template));
+        (taken.options_data, info.options_data) = info.options_data.into_iter().partition(|d| self.matches_template(d.template));
+
+        //a packet without any matching data wasn't touched, so it stays even if it has no data at all
+        if taken.data.is_empty() && taken.options_data.is_empty() {
+            return (None, Some(info));
+        }
+
+        if info.data.is_empty() && info.options_data.is_empty() {
+            (Some(taken), None)
+        }
+        else {
+            (Some(taken), Some(info))
+        }
+    }
+}
+
+//the header and template information of a packet without any of its records, for handing out part of a packet's data
+fn empty_copy(info: &PacketInfo) -> PacketInfo {
+    PacketInfo {
+        protocol: info.protocol,
+        version: info.version,
+        export_time: info.export_time,
+        seq_num: info.seq_num,
+        system_uptime: info.system_uptime,
+        templates: info.templates.clone(),
+        options_templates: info.options_templates.clone(),
+        withdrawals: info.withdrawals.clone(),
+        data: Vec::new(),
+        options_data: Vec::new(),
+        set_error_count: info.set_error_count,
+        set_errors: info.set_errors.clone(),
+        odid: info.odid,
+        exporter: info.exporter,
+        exporter_identity: info.exporter_identity.clone(),
+        header_error: info.header_error.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parse_data::OptionsDataSet;
+    use crate::parse_packet::test_exporter;
+
+    fn templates(info: &PacketInfo) -> (Vec<u16>, Vec<u16>) {
+        (info.data.iter().map(|d| d.template).collect(), info.options_data.iter().map(|d| d.template).collect())
+    }
+
+    //data from templates 256, 257 and 256 again, and options data from template 258
+    fn mixed_packet() -> PacketInfo {
+        let mut info = PacketInfo::with_records(1, 42, &[256, 257, 256]);
+        info.options_data.push(OptionsDataSet { id: 258, template: 258, scope_fields: Vec::new(), fields: Vec::new() });
+        info
+    }
+
+    fn by_template(template_id: u16) -> ResultQuery {
+        ResultQuery { template_id: Some(template_id), ..ResultQuery::all() }
+    }
+
+    #[test]
+    fn template_filters_take_part_of_a_packet_and_leave_the_rest() {
+        let selected = by_template(256).select(&mixed_packet()).expect("template 256 is in the packet");
+        assert_eq!(templates(&selected), (vec![256, 256], vec![]));
+        //the header comes along with the records
+        assert_eq!((selected.odid, selected.seq_num, selected.exporter), (1, 42, test_exporter()));
+
+        let (taken, left) = by_template(256).split(mixed_packet());
+        assert_eq!(templates(&taken.expect("template 256 is in the packet")), (vec![256, 256], vec![]));
+        let left = left.expect("templates 257 and 258 are left");
+        assert_eq!(templates(&left), (vec![257], vec![258]));
+        assert_eq!(left.seq_num, 42);
+
+        //options data is filtered the same way
+        let (taken, left) = by_template(258).split(mixed_packet());
+        assert_eq!(templates(&taken.expect("template 258 is in the packet")), (vec![], vec![258]));
+        assert_eq!(templates(&left.expect("the data sets are left")), (vec![256, 257, 256], vec![]));
+
+        //taking everything leaves nothing behind
+        let (taken, left) = by_template(256).split(PacketInfo::with_records(1, 42, &[256]));
+        assert_eq!(templates(&taken.expect("template 256 is in the packet")), (vec![256], vec![]));
+        assert!(left.is_none());
+    }
+
+    #[test]
+    fn packets_without_the_template_are_left_whole() {
+        assert!(by_template(259).select(&mixed_packet()).is_none());
+        let (taken, left) = by_template(259).split(mixed_packet());
+        assert!(taken.is_none());
+        assert_eq!(templates(&left.expect("the packet is left")), (vec![256, 257, 256], vec![258]));
+
+        //even one with no data at all, say one that only carried templates
+        let (taken, left) = by_template(256).split(PacketInfo::with_records(1, 42, &[]));
+        assert!(taken.is_none());
+        assert!(left.is_some());
+        //which matches when nothing filters by template
+        assert!(ResultQuery::all().select(&PacketInfo::with_records(1, 42, &[])).is_some());
+    }
+
+    #[test]
+    fn export_time_ranges_include_both_ends() {
+        let query = ResultQuery { export_time: Some(100..=200), ..ResultQuery::all() };
+        let matched: Vec<u32> = [0, 99, 100, 150, 200, 201, u32::MAX].into_iter().filter(|t| {
+            let mut info = PacketInfo::with_records(1, 0, &[256]);
+            info.export_time = *t;
+            query.select(&info).is_some()
+        }).collect();
+        assert_eq!(matched, vec![100, 150, 200]);
+
+        let single = ResultQuery { export_time: Some(150..=150), ..ResultQuery::all() };
+        let mut info = PacketInfo::with_records(1, 0, &[256]);
+        info.export_time = 150;
+        assert!(single.select(&info).is_some());
+        info.export_time = 151;
+        assert!(single.select(&info).is_none());
+    }
+
+    #[test]
+    fn exporter_protocol_and_odid_all_have_to_match() {
+        let other_exporter = SocketAddr::from(([127, 0, 0, 2], 4739));
+        let query = ResultQuery { odid: Some(1), protocol: Some(Protocol::SFlow), exporter: Some(test_exporter()), ..ResultQuery::all() };
+
+        let packet = |exporter: SocketAddr, protocol: Protocol, odid: u32| {
+            let mut info = PacketInfo::with_records(odid, 0, &[256]);
+            info.exporter = exporter;
+            info.protocol = protocol;
+            info
+        };
+        assert!(query.select(&packet(test_exporter(), Protocol::SFlow, 1)).is_some());
+        assert!(query.select(&packet(other_exporter, Protocol::SFlow, 1)).is_none());
+        assert!(query.select(&packet(test_exporter(), Protocol::IPFIX, 1)).is_none());
+        assert!(query.select(&packet(test_exporter(), Protocol::SFlow, 2)).is_none());
+
+        //each filter left out matches anything
+        let any_protocol = ResultQuery { protocol: None, ..query.clone() };
+        assert!(any_protocol.select(&packet(test_exporter(), Protocol::IPFIX, 1)).is_some());
+        assert!(ResultQuery::odid(1).select(&packet(other_exporter, Protocol::IPFIX, 1)).is_some());
+    }
+
+    #[test]
+    fn a_query_that_matches_nothing_takes_nothing() {
+        let query = ResultQuery { odid: Some(9), template_id: Some(256), ..ResultQuery::all() };
+        assert!(query.select(&mixed_packet()).is_none());
+        let (taken, left) = query.split(mixed_packet());
+        assert!(taken.is_none());
+        assert_eq!(templates(&left.expect("the packet is left")), (vec![256, 257, 256], vec![258]));
+    }
+}
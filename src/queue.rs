@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::mpsc::{RecvError, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+//a multi producer single consumer queue that holds at most capacity messages, like mpsc::sync_channel
+//on top of that it can make room by dropping the oldest message the sender says can go, and it has a priority lane for control messages (templates, stop, etc)
+//priority messages don't count toward the capacity and are received before anything else, so they never block and never get dropped
+pub(crate) fn bounded<T>(capacity: usize) -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State { messages: VecDeque::new(), priority: VecDeque::new(), capacity: capacity.max(1), senders: 1, receiver_alive: true }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new()
+    });
+    (QueueSender { shared: shared.clone() }, QueueReceiver { shared })
+}
+
+//why a message couldn't be queued, the message is handed back either way
+pub(crate) enum SendError<T> {
+    Full(T),
+    Disconnected(T)
+}
+
+//written by hand so the messages don't need to be Debug for expect to work
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Full(_) => write!(f, "queue is full"),
+            SendError::Disconnected(_) => write!(f, "receiver is gone")
+        }
+    }
+}
+
+struct State<T> {
+    messages: VecDeque<T>,
+    priority: VecDeque<T>,
+    capacity: usize,
+    senders: usize,
+    receiver_alive: bool
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    not_empty: Condvar,
+    not_full: Condvar
+}
+
+impl<T> Shared<T> {
+    //a thread that panicked while holding the lock can't have left the queues half updated, so a poisoned lock is still usable
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub(crate) struct QueueSender<T> {
+    shared: Arc<Shared<T>>
+}
+
+impl<T> QueueSender<T> {
+    //waits for room if the queue is full
+    pub(crate) fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        let mut state = self.shared.lock();
+        while state.receiver_alive && state.messages.len() >= state.capacity {
+            state = self.shared.not_full.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        self.push(state, msg)
+    }
+
+    //gives the message back instead of waiting if the queue is full
+    pub(crate) fn try_send(&self, msg: T) -> Result<(), SendError<T>> {
+        let state = self.shared.lock();
+        if state.receiver_alive && state.messages.len() >= state.capacity {
+            return Err(SendError::Full(msg));
+        }
+        self.push(state, msg)
+    }
+
+    //makes room by dropping the oldest message that droppable allows if the queue is full, the dropped message is returned
+    //messages droppable doesn't allow are never dropped: if nothing queued can go, msg is dropped instead, or queued past the capacity if it can't go either
+    pub(crate) fn send_dropping_oldest(&self, msg: T, droppable: impl Fn(&T) -> bool) -> Result<Option<T>, SendError<T>> {
+        let mut state = self.shared.lock();
+        if !state.receiver_alive {
+            return Err(SendError::Disconnected(msg));
+        }
+        if state.messages.len() >= state.capacity {
+            match state.messages.iter().position(&droppable) {
+                Some(oldest) => {
+                    let dropped = state.messages.remove(oldest);
+                    return self.push(state, msg).map(|_| dropped);
+                },
+                None if droppable(&msg) => { return Ok(Some(msg)); },
+                None => {}
+            }
+        }
+        self.push(state, msg).map(|_| None)
+    }
+
+    //jumps the queue and ignores the capacity
+    pub(crate) fn send_priority(&self, msg: T) -> Result<(), SendError<T>> {
+        let mut state = self.shared.lock();
+        if !state.receiver_alive {
+            return Err(SendError::Disconnected(msg));
+        }
+        state.priority.push_back(msg);
+        self.shared.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn push(&self, mut state: MutexGuard<'_, State<T>>, msg: T) -> Result<(), SendError<T>> {
+        if !state.receiver_alive {
+            return Err(SendError::Disconnected(msg));
+        }
+        state.messages.push_back(msg);
+        self.shared.not_empty.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        QueueSender { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        self.shared.lock().senders -= 1;
+        self.shared.not_empty.notify_all();
+    }
+}
+
+pub(crate) struct QueueReceiver<T> {
+    shared: Arc<Shared<T>>
+}
+
+impl<T> QueueReceiver<T> {
+    pub(crate) fn recv(&self) -> Result<T, RecvError> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(msg) = self.pop(&mut state) {
+                return Ok(msg);
+            }
+            if state.senders == 0 {
+                return Err(RecvError);
+            }
+            state = self.shared.not_empty.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    pub(crate) fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(msg) = self.pop(&mut state) {
+                return Ok(msg);
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self.shared.not_empty.wait_timeout(state, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    fn pop(&self, state: &mut MutexGuard<'_, State<T>>) -> Option<T> {
+        if let Some(msg) = state.priority.pop_front() {
+            return Some(msg);
+        }
+        let msg = state.messages.pop_front()?;
+        self.shared.not_full.notify_one();
+        Some(msg)
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receiver_alive = false;
+        self.shared.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    //the tests only send numbers, odd ones stand in for control messages that must never be dropped
+    fn droppable(msg: &u32) -> bool {
+        msg.is_multiple_of(2)
+    }
+
+    fn drain(rx: &QueueReceiver<u32>) -> Vec<u32> {
+        let mut msgs = Vec::new();
+        while let Ok(msg) = rx.recv_timeout(Duration::ZERO) {
+            msgs.push(msg);
+        }
+        msgs
+    }
+
+    #[test]
+    fn holds_at_most_capacity() {
+        let (tx, rx) = bounded(2);
+        assert!(tx.try_send(0).is_ok());
+        assert!(tx.try_send(2).is_ok());
+        assert!(matches!(tx.try_send(4), Err(SendError::Full(4))));
+        assert_eq!(rx.recv(), Ok(0));
+        assert!(tx.try_send(4).is_ok());
+        assert_eq!(drain(&rx), vec![2, 4]);
+
+        //a capacity of 0 would never hold anything
+        let (tx, rx) = bounded(0);
+        assert!(tx.try_send(0).is_ok());
+        assert!(matches!(tx.try_send(2), Err(SendError::Full(2))));
+        assert_eq!(drain(&rx), vec![0]);
+    }
+
+    #[test]
+    fn send_waits_for_room() {
+        let (tx, rx) = bounded(1);
+        tx.send(0).expect("queue has room");
+        let sender = thread::spawn(move ||{ tx.send(2).expect("receiver is still there"); });
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!sender.is_finished());
+        assert_eq!(rx.recv(), Ok(0));
+        sender.join().expect("sender panicked");
+        assert_eq!(rx.recv(), Ok(2));
+    }
+
+    #[test]
+    fn priority_messages_skip_the_queue_and_the_capacity() {
+        let (tx, rx) = bounded(1);
+        tx.send(0).expect("queue has room");
+        tx.send_priority(1).expect("priority lane has no capacity");
+        tx.send_priority(3).expect("priority lane has no capacity");
+        assert!(matches!(tx.try_send(2), Err(SendError::Full(2))));
+
+        //the priority messages don't make the bounded lane drop anything either
+        assert_eq!(tx.send_dropping_oldest(2, droppable).ok(), Some(Some(0)));
+        assert_eq!(drain(&rx), vec![1, 3, 2]);
+    }
+
+    #[test]
+    fn dropping_oldest_only_drops_what_it_is_allowed_to() {
+        let (tx, rx) = bounded(3);
+        assert_eq!(tx.send_dropping_oldest(1, droppable).ok(), Some(None));
+        assert_eq!(tx.send_dropping_oldest(2, droppable).ok(), Some(None));
+        assert_eq!(tx.send_dropping_oldest(4, droppable).ok(), Some(None));
+
+        //the oldest message is a control message, so the oldest one after it goes
+        assert_eq!(tx.send_dropping_oldest(6, droppable).ok(), Some(Some(2)));
+        //a control message makes room the same way
+        assert_eq!(tx.send_dropping_oldest(3, droppable).ok(), Some(Some(4)));
+        assert_eq!(drain(&rx), vec![1, 6, 3]);
+
+        //with only control messages queued, a new message that can be dropped is the one dropped
+        for msg in [1, 3, 5] {
+            tx.send(msg).expect("queue has room");
+        }
+        assert_eq!(tx.send_dropping_oldest(8, droppable).ok(), Some(Some(8)));
+        //and a new control message goes past the capacity rather than be lost
+        assert_eq!(tx.send_dropping_oldest(7, droppable).ok(), Some(None));
+        assert_eq!(drain(&rx), vec![1, 3, 5, 7]);
+    }
+
+    #[test]
+    fn recv_timeout_waits_then_gives_up() {
+        let (tx, rx) = bounded::<u32>(1);
+        let start = Instant::now();
+        assert_eq!(rx.recv_timeout(Duration::from_millis(20)), Err(RecvTimeoutError::Timeout));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let sender = thread::spawn(move ||{
+            thread::sleep(Duration::from_millis(20));
+            tx.send(2).expect("receiver is still there");
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(2));
+        sender.join().expect("sender panicked");
+    }
+
+    #[test]
+    fn disconnects_are_reported() {
+        //queued messages are still received after every sender is gone
+        let (tx, rx) = bounded(2);
+        let tx2 = tx.clone();
+        tx.send(0).expect("queue has room");
+        drop(tx);
+        tx2.send_priority(1).expect("receiver is still there");
+        drop(tx2);
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv_timeout(Duration::ZERO), Ok(0));
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.recv_timeout(Duration::ZERO), Err(RecvTimeoutError::Disconnected));
+
+        //senders get their message back, including one that was waiting for room
+        let (tx, rx) = bounded(1);
+        tx.send(0).expect("queue has room");
+        let tx2 = tx.clone();
+        let waiting = thread::spawn(move ||{ matches!(tx2.send(2), Err(SendError::Disconnected(2))) });
+        thread::sleep(Duration::from_millis(20));
+        drop(rx);
+        assert!(waiting.join().expect("sender panicked"));
+        assert!(matches!(tx.try_send(4), Err(SendError::Disconnected(4))));
+        assert!(matches!(tx.send_priority(5), Err(SendError::Disconnected(5))));
+        assert!(matches!(tx.send_dropping_oldest(6, droppable), Err(SendError::Disconnected(6))));
+    }
+}
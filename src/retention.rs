@@ -0,0 +1,476 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::Protocol;
+use crate::error::ParseError;
+use crate::parse_data::{DataRow, DataSet, DataType, OptionsDataSet};
+use crate::parse_packet::PacketInfo;
+use crate::query::ResultQuery;
+use crate::templates::{IPFIXField, TemplateWithdrawal};
+
+//what happens to a stored packet when a limit says something has to go
+#[derive(Clone)]
+pub enum Eviction {
+    DropOldest, //forget the oldest stored packets to make room
+    DropNewest, //keep what is stored and forget the packet that would go over the limit
+    SpillToDisk(PathBuf) //same as DropOldest, but the evicted packets are appended to this file as JSON lines first
+}
+
+//how much the aggregator is allowed to hold on to, every limit that is set is enforced, the default has no limits
+#[derive(Clone)]
+pub struct RetentionPolicy {
+    pub max_packets_per_odid: Option<usize>, //stored packets (PacketInfos) per ODID, sFlow sub-agents count separately from IPFIX ODIDs with the same number
+    pub max_total_bytes: Option<usize>, //an estimate of the memory used by every stored packet together
+    pub max_age: Option<Duration>, //packets whose export time is further in the past than this are always dropped (or spilled), whatever the eviction strategy
+    pub eviction: Eviction
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy { max_packets_per_odid: None, max_total_bytes: None, max_age: None, eviction: Eviction::DropOldest }
+    }
+}
+
+//running totals of what the retention policy threw away
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetentionStats {
+    pub evicted_packets: u64,
+    pub evicted_records: u64, //data and options data records in the evicted packets
+    pub spilled_packets: u64, //evicted packets written to the spill file
+    pub spill_errors: u64 //evicted packets that couldn't be written to the spill file, these are lost
+}
+
+struct StoredPacket {
+    size: usize,
+    info: PacketInfo
+}
+
+//sFlow sub-agent ids end up in PacketInfo::odid too, the protocol keeps them apart from IPFIX ODIDs
+type Domain = (Protocol, u32);
+
+//the aggregator's results, per ODID in the order they arrived, kept within a RetentionPolicy
+pub(crate) struct ResultStore {
+    policy: RetentionPolicy,
+    odids: HashMap<Domain, VecDeque<StoredPacket>>,
+    total_bytes: usize,
+    stats: RetentionStats,
+    spill_file: Option<File> //opened the first time something is spilled
+}
+
+impl ResultStore {
+    pub(crate) fn new(policy: RetentionPolicy) -> Self {
+        ResultStore { policy, odids: HashMap::new(), total_bytes: 0, stats: RetentionStats::default(), spill_file: None }
+    }
+
+    pub(crate) fn insert(&mut self, info: PacketInfo) {
+        let odid = (info.protocol, info.odid);
+        let size = estimate_size(&info);
+
+        if let Eviction::DropNewest = self.policy.eviction {
+            let stored = self.odids.get(&odid).map_or(0, |q| q.len());
+            let too_many = self.policy.max_packets_per_odid.is_some_and(|max| stored >= max);
+            let too_big = self.policy.max_total_bytes.is_some_and(|max| self.total_bytes + size > max);
+            if too_many || too_big {
+                self.evict(info);
+                return;
+            }
+        }
+
+        self.odids.entry(odid).or_default().push_back(StoredPacket { size, info });
+        self.total_bytes += size;
+
+        //with DropNewest we only get here if the packet fit, so these only ever evict with the other strategies
+        if let Some(max) = self.policy.max_packets_per_odid {
+            while self.odids.get(&odid).is_some_and(|q| q.len() > max) {
+                if let Some(p) = self.pop_front(odid) {
+                    self.evict(p.info);
+                }
+            }
+        }
+
+        if let Some(max) = self.policy.max_total_bytes {
+            while self.total_bytes > max {
+                match self.oldest_odid() {
+                    Some(oldest) => {
+                        if let Some(p) = self.pop_front(oldest) {
+                            self.evict(p.info);
+                        }
+                    },
+                    None => { break; }
+                }
+            }
+        }
+    }
+
+    //evicts every packet older than max_age, the aggregator calls this about once a second
+    pub(crate) fn expire(&mut self, now: SystemTime) {
+        let max_age = match self.policy.max_age {
+            Some(a) => a,
+            None => { return; }
+        };
+        let cutoff = now.checked_sub(max_age).unwrap_or(UNIX_EPOCH)
+            .duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO)
+            .as_secs();
+
+        let mut expired = Vec::new();
+        for queue in self.odids.values_mut() {
+            //packets are stored in the order they arrived, which isn't necessarily the order of their export times
+            let (old, young): (VecDeque<StoredPacket>, VecDeque<StoredPacket>) = queue.drain(..).partition(|p| (p.info.export_time as u64) < cutoff);
+            *queue = young;
+            expired.extend(old);
+        }
+        self.odids.retain(|_odid, q| !q.is_empty());
+
+        for p in expired {
+            self.total_bytes -= p.size;
+            self.evict(p.info);
+        }
+    }
+
+    //copies out the parts of the stored results that match, oldest first within an ODID and ODIDs in ascending order (IPFIX before sFlow)
+    pub(crate) fn collect(&self, query: &ResultQuery) -> Vec<PacketInfo> {
+        let mut results = Vec::new();
+        for odid in self.sorted_odids() {
+            for p in self.odids[&odid].iter() {
+                if let Some(info) = query.select(&p.info) {
+                    results.push(info);
+                }
+            }
+        }
+        results
+    }
+
+    //same as collect, but takes what matched out of the store
+    pub(crate) fn take(&mut self, query: &ResultQuery) -> Vec<PacketInfo> {
+        let mut results = Vec::new();
+        for odid in self.sorted_odids() {
+            let stored = self.odids.remove(&odid).unwrap_or_default();
+            let mut kept = VecDeque::with_capacity(stored.len());
+
+            for p in stored {
+                self.total_bytes -= p.size;
+                let (taken, left) = query.split(p.info);
+                if let Some(info) = taken {
+                    results.push(info);
+                }
+                if let Some(info) = left {
+                    let size = estimate_size(&info);
+                    self.total_bytes += size;
+                    kept.push_back(StoredPacket { size, info });
+                }
+            }
+
+            if !kept.is_empty() {
+                self.odids.insert(odid, kept);
+            }
+        }
+        results
+    }
+
+    pub(crate) fn snapshot(&self) -> HashMap<(Protocol, u32), Vec<PacketInfo>> {
+        self.odids.iter()
+            .map(|(odid, q)| (*odid, q.iter().map(|p| p.info.clone()).collect()))
+            .collect()
+    }
+
+    pub(crate) fn stats(&self) -> RetentionStats {
+        self.stats
+    }
+
+    fn sorted_odids(&self) -> Vec<Domain> {
+        let mut odids: Vec<Domain> = self.odids.keys().copied().collect();
+        odids.sort_unstable();
+        odids
+    }
+
+    fn pop_front(&mut self, odid: Domain) -> Option<StoredPacket> {
+        let queue = self.odids.get_mut(&odid)?;
+        let p = queue.pop_front()?;
+        if queue.is_empty() {
+            self.odids.remove(&odid);
+        }
+        self.total_bytes -= p.size;
+        Some(p)
+    }
+
+    //the ODID whose oldest stored packet has the earliest export time, that packet is the oldest one we have
+    fn oldest_odid(&self) -> Option<Domain> {
+        self.odids.iter()
+            .filter_map(|(odid, q)| q.front().map(|p| (p.info.export_time, *odid)))
+            .min()
+            .map(|(_export_time, odid)| odid)
+    }
+
+    fn evict(&mut self, info: PacketInfo) {
+        self.stats.evicted_packets += 1;
+        self.stats.evicted_records += (info.data.len() + info.options_data.len()) as u64;
+
+        if let Eviction::SpillToDisk(path) = &self.policy.eviction {
+            if self.spill_file.is_none() {
+                self.spill_file = OpenOptions::new().create(true).append(true).open(path).ok();
+            }
+            let written = match self.spill_file.as_mut() {
+                Some(f) => writeln!(f, "{}", packet_json(&info)).is_ok(),
+                None => false
+            };
+            if written {
+                self.stats.spilled_packets += 1;
+            }
+            else {
+                self.stats.spill_errors += 1;
+            }
+        }
+    }
+}
+
+//roughly how many bytes a packet takes up in memory, this only has to be good enough to keep the store near its byte limit
+fn estimate_size(info: &PacketInfo) -> usize {
+    let fields = |f: &Vec<IPFIXField>| f.len() * size_of::<IPFIXField>();
+    let rows = |r: &Vec<DataRow>| r.iter().map(|row| size_of::<DataRow>() + match &row.data {
+        DataType::STRING(s) => s.len(),
+        DataType::BYTES(b) => b.len(),
+        _ => 0
+    }).sum::<usize>();
+
+    size_of::<PacketInfo>()
+        + info.templates.iter().map(|t| size_of_val(t) + fields(&t.fields)).sum::<usize>()
+        + info.options_templates.iter().map(|t| size_of_val(t) + fields(&t.scope_fields) + fields(&t.fields)).sum::<usize>()
+        + info.withdrawals.len() * size_of::<TemplateWithdrawal>()
+        + info.data.iter().map(|d| size_of::<DataSet>() + rows(&d.fields)).sum::<usize>()
+        + info.options_data.iter().map(|d| size_of::<OptionsDataSet>() + rows(&d.scope_fields) + rows(&d.fields)).sum::<usize>()
+        + info.set_errors.len() * size_of::<ParseError>()
+}
+
+//one line of the spill file, the header plus every record with its values
+fn packet_json(info: &PacketInfo) -> String {
+    let data: Vec<String> = info.data.iter()
+        .map(|d| format!("{{\"template\":{},\"fields\":{}}}", d.template, rows_json(&d.fields)))
+        .collect();
+    let options_data: Vec<String> = info.options_data.iter()
+        .map(|d| format!("{{\"template\":{},\"scope_fields\":{},\"fields\":{}}}", d.template, rows_json(&d.scope_fields), rows_json(&d.fields)))
+        .collect();
+
+    let identity = match &info.exporter_identity {
+        Some(id) => json_string(&id.subject),
+        None => String::from("null")
+    };
+
+    format!("{{\"protocol\":\"{:?}\",\"version\":{},\"exporter\":\"{}\",\"exporter_identity\":{},\"odid\":{},\"export_time\":{},\"seq_num\":{},\"data\":[{}],\"options_data\":[{}]}}",
+        info.protocol, info.version, info.exporter, identity, info.odid, info.export_time, info.seq_num, data.join(","), options_data.join(","))
+}
+
+fn rows_json(rows: &[DataRow]) -> String {
+    let rows: Vec<String> = rows.iter()
+        .map(|r| {
+            let name = match r.name() {
+                Some(n) => json_string(n),
+                None => String::from("null")
+            };
+            format!("{{\"id\":{},\"en\":{},\"name\":{},\"value\":{}}}", r.id, r.en, name, value_json(&r.data))
+        })
+        .collect();
+    format!("[{}]", rows.join(","))
+}
+
+//numbers stay numbers, addresses and byte strings become strings, times become nanoseconds since the unix epoch
+fn value_json(data: &DataType) -> String {
+    match data {
+        DataType::U8(v) => v.to_string(),
+        DataType::U16(v) => v.to_string(),
+        DataType::U32(v) => v.to_string(),
+        DataType::U64(v) => v.to_string(),
+        DataType::I8(v) => v.to_string(),
+        DataType::I16(v) => v.to_string(),
+        DataType::I32(v) => v.to_string(),
+        DataType::I64(v) => v.to_string(),
+        //JSON has no NaN or infinity
+        DataType::F32(v) => if v.is_finite() { v.to_string() } else { String::from("null") },
+        DataType::F64(v) => if v.is_finite() { v.to_string() } else { String::from("null") },
+        DataType::BOOL(v) => v.to_string(),
+        DataType::MAC(m) => format!("\"{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}\"", m[0], m[1], m[2], m[3], m[4], m[5]),
+        DataType::IPV4(a) => format!("\"{}\"", a),
+        DataType::IPV6(a) => format!("\"{}\"", a),
+        DataType::STRING(s) => json_string(s),
+        DataType::DATETIME(t) => match t.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_nanos().to_string(),
+            Err(e) => format!("-{}", e.duration().as_nanos())
+        },
+        DataType::BYTES(b) => format!("\"{}\"", b.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::sync::Arc;
+
+    use crate::information_elements::IERegistry;
+    use crate::parse_packet::test_path;
+    use crate::tls::ExporterIdentity;
+
+    //a packet with one record, told apart by its sequence number
+    fn packet(odid: u32, seq_num: u32, export_time: u32) -> PacketInfo {
+        let mut info = PacketInfo::with_records(odid, seq_num, &[256]);
+        info.export_time = export_time;
+        info.data[0].fields.push(DataRow::new(1, 0, DataType::U64(seq_num as u64)));
+        info
+    }
+
+    fn stored_seq_nums(store: &ResultStore) -> Vec<(u32, u32)> {
+        store.collect(&ResultQuery::all()).iter().map(|p| (p.odid, p.seq_num)).collect()
+    }
+
+    fn store_with(max_packets_per_odid: Option<usize>, max_total_bytes: Option<usize>, eviction: Eviction) -> ResultStore {
+        ResultStore::new(RetentionPolicy { max_packets_per_odid, max_total_bytes, max_age: None, eviction })
+    }
+
+    #[test]
+    fn drop_oldest_evicts_per_odid() {
+        let mut store = store_with(Some(2), None, Eviction::DropOldest);
+        for seq in 1..=4 {
+            store.insert(packet(1, seq, seq));
+        }
+        store.insert(packet(2, 10, 1));
+
+        assert_eq!(stored_seq_nums(&store), vec![(1, 3), (1, 4), (2, 10)]);
+        assert_eq!(store.stats(), RetentionStats { evicted_packets: 2, evicted_records: 2, spilled_packets: 0, spill_errors: 0 });
+    }
+
+    #[test]
+    fn sflow_sub_agents_are_kept_apart_from_ipfix_odids() {
+        let mut store = store_with(Some(1), None, Eviction::DropOldest);
+        let mut sflow = packet(1, 2, 2);
+        sflow.protocol = Protocol::SFlow;
+        store.insert(packet(1, 1, 1));
+        store.insert(sflow);
+
+        assert_eq!(stored_seq_nums(&store), vec![(1, 1), (1, 2)]);
+        assert_eq!(store.stats().evicted_packets, 0);
+        let snapshot = store.snapshot();
+        assert_eq!(snapshot[&(Protocol::IPFIX, 1)][0].seq_num, 1);
+        assert_eq!(snapshot[&(Protocol::SFlow, 1)][0].seq_num, 2);
+
+        let sflow_only = ResultQuery { protocol: Some(Protocol::SFlow), ..ResultQuery::odid(1) };
+        assert_eq!(store.take(&sflow_only).iter().map(|p| p.seq_num).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(stored_seq_nums(&store), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn drop_newest_keeps_what_is_stored() {
+        let mut store = store_with(Some(2), None, Eviction::DropNewest);
+        for seq in 1..=4 {
+            store.insert(packet(1, seq, seq));
+        }
+
+        assert_eq!(stored_seq_nums(&store), vec![(1, 1), (1, 2)]);
+        assert_eq!(store.stats().evicted_packets, 2);
+
+        //taking packets out makes room again
+        store.take(&ResultQuery::all());
+        store.insert(packet(1, 5, 5));
+        assert_eq!(stored_seq_nums(&store), vec![(1, 5)]);
+        assert_eq!(store.stats().evicted_packets, 2);
+    }
+
+    #[test]
+    fn byte_limit_evicts_the_oldest_export_time_across_odids() {
+        let size = estimate_size(&packet(0, 0, 0));
+        let mut store = store_with(None, Some(2 * size), Eviction::DropOldest);
+        store.insert(packet(1, 1, 200));
+        store.insert(packet(2, 2, 100));
+        store.insert(packet(3, 3, 300));
+
+        assert_eq!(stored_seq_nums(&store), vec![(1, 1), (3, 3)]);
+        assert_eq!(store.stats().evicted_packets, 1);
+
+        let mut store = store_with(None, Some(2 * size), Eviction::DropNewest);
+        for odid in 1..=3 {
+            store.insert(packet(odid, odid, odid));
+        }
+        assert_eq!(stored_seq_nums(&store), vec![(1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn old_packets_expire_whatever_the_eviction() {
+        let mut store = ResultStore::new(RetentionPolicy { max_age: Some(Duration::from_secs(60)), eviction: Eviction::DropNewest, ..RetentionPolicy::default() });
+        store.insert(packet(1, 1, 1000));
+        store.insert(packet(1, 2, 900));
+        store.insert(packet(2, 3, 1100));
+
+        store.expire(UNIX_EPOCH + Duration::from_secs(1070));
+        assert_eq!(stored_seq_nums(&store), vec![(2, 3)]);
+        assert_eq!(store.stats().evicted_packets, 2);
+    }
+
+    #[test]
+    fn spilled_packets_are_json_lines() {
+        let path = test_path("spill").with_extension("jsonl");
+        let _ = fs::remove_file(&path);
+        let mut store = store_with(Some(1), None, Eviction::SpillToDisk(path.clone()));
+
+        //a string that needs every kind of escaping
+        let awkward = "quote \" backslash \\ newline \n tab \t bell \u{7} and unicode \u{e9}";
+        let registry = IERegistry::iana();
+        let mut spilled = packet(7, 41, 1234);
+        spilled.data[0].fields.push(DataRow::with_element(82, 0, registry.get(0, 82).cloned(), DataType::STRING(String::from(awkward))));
+        spilled.data[0].fields.push(DataRow::new(2000, 9, DataType::BYTES(vec![0xde, 0xad])));
+        spilled.exporter_identity = Some(Arc::new(ExporterIdentity { subject: String::from("CN=exporter \"one\""), subject_alt_names: Vec::new(), certificate: Vec::new() }));
+        store.insert(spilled);
+        store.insert(packet(7, 42, 1235));
+
+        assert_eq!(store.stats(), RetentionStats { evicted_packets: 1, evicted_records: 1, spilled_packets: 1, spill_errors: 0 });
+        let text = fs::read_to_string(&path).expect("spill file should have been written");
+        fs::remove_file(&path).expect("failed to clean up the spill file");
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let json: serde_json::Value = serde_json::from_str(lines[0]).expect("spill file line should be JSON");
+        assert_eq!(json["protocol"], "IPFIX");
+        assert_eq!(json["exporter"], "127.0.0.1:4739");
+        assert_eq!(json["exporter_identity"], "CN=exporter \"one\"");
+        assert_eq!(json["odid"], 7);
+        assert_eq!(json["seq_num"], 41);
+        assert_eq!(json["export_time"], 1234);
+
+        let fields = &json["data"][0]["fields"];
+        assert_eq!(json["data"][0]["template"], 256);
+        assert_eq!(fields[0]["value"], 41);
+        assert_eq!(fields[0]["name"], serde_json::Value::Null);
+        assert_eq!(fields[1]["name"], "interfaceName");
+        assert_eq!(fields[1]["value"], awkward);
+        assert_eq!(fields[2]["en"], 9);
+        assert_eq!(fields[2]["value"], "dead");
+    }
+
+    #[test]
+    fn spill_failures_are_counted() {
+        let path = test_path("missing").join("spill.jsonl");
+        let mut store = store_with(Some(1), None, Eviction::SpillToDisk(path));
+        store.insert(packet(1, 1, 1));
+        store.insert(packet(1, 2, 2));
+        assert_eq!(store.stats(), RetentionStats { evicted_packets: 1, evicted_records: 1, spilled_packets: 0, spill_errors: 1 });
+    }
+}
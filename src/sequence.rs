@@ -0,0 +1,290 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+use crate::config::Protocol;
+use crate::parse_packet::{NETFLOW_V9_VERSION, PacketInfo};
+
+//how far back (in packets) we still take a packet to be late instead of a sign the exporter restarted, and how many gaps we remember in case the missing packets are only late
+//a gap that falls off the end stays lost, packets spread over several parser threads can overtake each other so this has to cover a few packets per thread
+const REORDER_WINDOW: usize = 64;
+
+//what the sequence numbers of one exporter's observation domain tell us, all counts are since the collector started
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SequenceStats {
+    pub packets: u64, //packets checked against the sequence number
+    pub lost_records: u64, //data records (messages for NetFlow v9, datagrams for sFlow) the exporter says it sent that never reached the aggregator, less any that turned up late
+    pub gaps: u64, //times a packet's sequence number was ahead of what we expected
+    pub reordered: u64, //packets that arrived after a later packet, filling in a gap if they had any records
+    pub resets: u64, //times the sequence number went backwards outside of any gap, which usually means the exporter restarted
+    pub uncounted: u64 //packets with data sets we couldn't decode, their record count is unknown so the next packet is taken as the new starting point
+}
+
+//one (exporter, protocol, ODID), the protocol keeps sFlow sub-agent ids apart from IPFIX ODIDs of an exporter that sends both
+#[derive(Default)]
+struct Stream {
+    expected: Option<u32>, //sequence number of the next packet if nothing is lost, None until we have a starting point
+    open_gaps: VecDeque<(u32, u32)>, //(first missing sequence number, number of missing records), oldest first
+    recent: VecDeque<u32>, //sequence numbers of the last packets that moved expected along, oldest first
+    stats: SequenceStats
+}
+
+//checks the sequence number of every packet the aggregator receives (RFC 7011 section 3.1)
+//the sequence number counts the data records sent before the message, so the next message should start where this one's records end
+//NetFlow v9 counts messages instead (RFC 3954 section 5.1), and sFlow counts datagrams, so there lost_records is really lost messages
+pub(crate) struct SequenceTracker {
+    streams: HashMap<(SocketAddr, Protocol, u32), Stream>
+}
+
+impl SequenceTracker {
+    pub(crate) fn new() -> Self {
+        SequenceTracker { streams: HashMap::new() }
+    }
+
+    pub(crate) fn observe(&mut self, info: &PacketInfo) {
+        let stream = self.streams.entry((info.exporter, info.protocol, info.odid)).or_default();
+        stream.stats.packets += 1;
+
+        //NetFlow v9 and sFlow sequence numbers count messages, so every message moves it along by one whatever is in it
+        let per_message = info.protocol == Protocol::SFlow || info.version == NETFLOW_V9_VERSION;
+        //options data records are data records too
+        let records = if per_message { 1 } else { (info.data.len() + info.options_data.len()) as u32 };
+        //a data set we couldn't read might have had any number of records in it, errors in template sets don't matter
+        let counted = per_message || !info.set_errors.iter().any(|e| e.set_id().is_some_and(|id| id >= 256));
+
+        let seq = info.seq_num;
+        let next = if counted { Some(seq.wrapping_add(records)) } else { None };
+
+        let expected = match stream.expected {
+            Some(e) => e,
+            None => {
+                stream.advance(seq, next);
+                if !counted {
+                    stream.stats.uncounted += 1;
+                }
+                return;
+            }
+        };
+
+        //sequence numbers wrap, anything less than half way round ahead of what we expected is ahead, the rest is behind
+        let ahead = seq.wrapping_sub(expected);
+        if ahead == 0 {
+            stream.advance(seq, next);
+        }
+        else if ahead < 1 << 31 {
+            stream.stats.gaps += 1;
+            stream.stats.lost_records += ahead as u64;
+            stream.open_gaps.push_back((expected, ahead));
+            if stream.open_gaps.len() > REORDER_WINDOW {
+                stream.open_gaps.pop_front();
+            }
+            stream.advance(seq, next);
+        }
+        else if let Some(filled) = stream.fill_gap(seq, if counted { Some(records) } else { None }) {
+            //a late packet, its records were counted as lost when the gap showed up
+            //this doesn't move expected, so we still know where the stream is even if we don't know how many records the packet had
+            stream.stats.reordered += 1;
+            stream.stats.lost_records -= filled as u64;
+        }
+        else if (records == 0 || !counted) && stream.is_recent(seq, expected) {
+            //a late packet without records (templates only) doesn't leave a gap, a late packet with records that didn't fill one is a restart
+            stream.stats.reordered += 1;
+        }
+        else {
+            stream.stats.resets += 1;
+            stream.open_gaps.clear();
+            stream.recent.clear();
+            stream.advance(seq, next);
+        }
+
+        if !counted {
+            stream.stats.uncounted += 1;
+        }
+    }
+
+    pub(crate) fn stats(&self) -> HashMap<(SocketAddr, Protocol, u32), SequenceStats> {
+        self.streams.iter().map(|(key, s)| (*key, s.stats)).collect()
+    }
+}
+
+impl Stream {
+    fn advance(&mut self, seq: u32, next: Option<u32>) {
+        self.expected = next;
+        self.recent.push_back(seq);
+        if self.recent.len() > REORDER_WINDOW {
+            self.recent.pop_front();
+        }
+    }
+
+    //whether seq is somewhere between the oldest packet we remember and expected
+    fn is_recent(&self, seq: u32, expected: u32) -> bool {
+        match self.recent.front() {
+            Some(oldest) => seq.wrapping_sub(*oldest) < expected.wrapping_sub(*oldest),
+            None => false
+        }
+    }
+
+    //takes the records starting at seq out of the gap they fall in, returns how many records were missing there
+    //if we don't know how many records the packet had, all we can say is the rest of the gap from seq on is accounted for
+    fn fill_gap(&mut self, seq: u32, records: Option<u32>) -> Option<u32> {
+        let idx = self.open_gaps.iter().position(|(start, len)| seq.wrapping_sub(*start) < *len)?;
+        let (start, len) = self.open_gaps[idx];
+
+        let before = seq.wrapping_sub(start);
+        let filled = records.map_or(len - before, |r| r.min(len - before));
+        let after = len - before - filled;
+
+        let mut remaining = Vec::with_capacity(2);
+        if before > 0 {
+            remaining.push((start, before));
+        }
+        if after > 0 {
+            remaining.push((seq.wrapping_add(filled), after));
+        }
+
+        self.open_gaps.remove(idx);
+        for (i, gap) in remaining.into_iter().enumerate() {
+            self.open_gaps.insert(idx + i, gap);
+        }
+        Some(filled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::error::ParseError;
+    use crate::parse_packet::{IPFIX_VERSION, test_exporter};
+
+    //an IPFIX message from ODID 1 with records data records
+    fn packet(seq_num: u32, records: usize) -> PacketInfo {
+        PacketInfo::with_records(1, seq_num, &vec![256; records])
+    }
+
+    //feeds (sequence number, records) pairs through a fresh tracker
+    fn track(packets: &[(u32, usize)]) -> SequenceStats {
+        let mut tracker = SequenceTracker::new();
+        for (seq, records) in packets {
+            tracker.observe(&packet(*seq, *records));
+        }
+        stats(&tracker)
+    }
+
+    fn stats(tracker: &SequenceTracker) -> SequenceStats {
+        tracker.stats()[&(test_exporter(), Protocol::IPFIX, 1)]
+    }
+
+    #[test]
+    fn in_order_packets_lose_nothing() {
+        let s = track(&[(0, 2), (2, 3), (5, 0), (5, 1), (6, 4)]);
+        assert_eq!(s, SequenceStats { packets: 5, ..SequenceStats::default() });
+    }
+
+    #[test]
+    fn gaps_count_the_missing_records() {
+        let s = track(&[(0, 2), (5, 1), (6, 1), (10, 1)]);
+        assert_eq!((s.gaps, s.lost_records, s.reordered, s.resets), (2, 6, 0, 0));
+    }
+
+    #[test]
+    fn late_packets_fill_their_gap() {
+        //2 to 4 go missing, then turn up one part at a time
+        let s = track(&[(0, 2), (5, 1), (3, 1)]);
+        assert_eq!((s.gaps, s.lost_records, s.reordered), (1, 2, 1));
+        let s = track(&[(0, 2), (5, 1), (3, 1), (2, 1), (4, 1)]);
+        assert_eq!((s.gaps, s.lost_records, s.reordered, s.resets), (1, 0, 3, 0));
+
+        //a late packet with nothing but templates in it doesn't leave a gap
+        let s = track(&[(0, 2), (2, 1), (2, 0), (3, 1)]);
+        assert_eq!((s.gaps, s.lost_records, s.reordered, s.resets), (0, 0, 1, 0));
+    }
+
+    #[test]
+    fn packets_later_than_the_window_are_a_restart() {
+        //every other packet goes missing until the first gap has been forgotten
+        let mut packets = vec![(0, 1)];
+        packets.extend((0..=REORDER_WINDOW as u32).map(|i| (2 + 2 * i, 1)));
+        let before = track(&packets);
+        assert_eq!((before.gaps, before.lost_records), (REORDER_WINDOW as u64 + 1, REORDER_WINDOW as u64 + 1));
+
+        packets.push((1, 1));
+        let after = track(&packets);
+        assert_eq!((after.lost_records, after.reordered, after.resets), (before.lost_records, 0, 1));
+
+        //the most recent gap was still remembered
+        packets.pop();
+        packets.push((1 + 2 * REORDER_WINDOW as u32, 1));
+        let filled = track(&packets);
+        assert_eq!((filled.lost_records, filled.reordered, filled.resets), (before.lost_records - 1, 1, 0));
+    }
+
+    #[test]
+    fn sequence_numbers_wrap() {
+        let s = track(&[(u32::MAX - 1, 2), (0, 1), (1, 1)]);
+        assert_eq!((s.gaps, s.lost_records, s.resets), (0, 0, 0));
+
+        //a gap across the wrap, filled in late
+        let s = track(&[(u32::MAX - 1, 1), (2, 1)]);
+        assert_eq!((s.gaps, s.lost_records), (1, 3));
+        let s = track(&[(u32::MAX - 1, 1), (2, 1), (u32::MAX, 3)]);
+        assert_eq!((s.gaps, s.lost_records, s.reordered, s.resets), (1, 0, 1, 0));
+    }
+
+    #[test]
+    fn going_backwards_is_a_restart() {
+        let s = track(&[(1000, 1), (1001, 1), (0, 2), (2, 1)]);
+        assert_eq!((s.gaps, s.lost_records, s.reordered, s.resets), (0, 0, 0, 1));
+
+        //records lost before the restart stay lost
+        let s = track(&[(1000, 1), (1005, 1), (0, 2), (2, 1)]);
+        assert_eq!((s.gaps, s.lost_records, s.reordered, s.resets), (1, 4, 0, 1));
+    }
+
+    #[test]
+    fn undecodable_data_sets_restart_counting() {
+        let mut tracker = SequenceTracker::new();
+        tracker.observe(&packet(0, 2));
+        //a data set that couldn't be decoded could have had any number of records
+        let mut unknown = packet(2, 0);
+        unknown.set_errors.push(ParseError::UnknownTemplate { offset: 16, set_id: 256, odid: 1 });
+        tracker.observe(&unknown);
+        tracker.observe(&packet(9, 1));
+        tracker.observe(&packet(10, 1));
+
+        let s = stats(&tracker);
+        assert_eq!((s.gaps, s.lost_records, s.uncounted, s.resets), (0, 0, 1, 0));
+    }
+
+    #[test]
+    fn netflow_v9_and_sflow_count_messages() {
+        for (protocol, version) in [(Protocol::IPFIX, NETFLOW_V9_VERSION), (Protocol::SFlow, 5)] {
+            let mut tracker = SequenceTracker::new();
+            for seq in [1, 2, 4, 5] {
+                let mut info = packet(seq, 3);
+                info.protocol = protocol;
+                info.version = version;
+                tracker.observe(&info);
+            }
+            let s = tracker.stats()[&(test_exporter(), protocol, 1)];
+            assert_eq!((s.packets, s.gaps, s.lost_records), (4, 1, 1), "{:?} version {}", protocol, version);
+        }
+
+        //an exporter sending both keeps two sequences even when the sub-agent id and ODID match
+        let mut tracker = SequenceTracker::new();
+        for seq in [1, 2] {
+            let mut sflow = packet(seq, 3);
+            sflow.protocol = Protocol::SFlow;
+            tracker.observe(&sflow);
+            tracker.observe(&packet(seq * 100, 3));
+        }
+        let stats = tracker.stats();
+        assert_eq!(stats[&(test_exporter(), Protocol::SFlow, 1)].gaps, 0);
+        assert_eq!(stats[&(test_exporter(), Protocol::IPFIX, 1)].gaps, 1);
+        assert!(stats.values().all(|s| s.resets == 0));
+
+        //the same packets from IPFIX would be 3 records each
+        let s = track(&[(1, 3), (4, 3), (10, 3)]);
+        assert_eq!((s.gaps, s.lost_records), (1, 3));
+        assert_eq!(packet(0, 0).version, IPFIX_VERSION);
+    }
+}
@@ -0,0 +1,505 @@
+use nom::error::VerboseError;
+use nom::number::complete::{be_u32, be_u64};
+use nom::Slice;
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Protocol;
+use crate::error::{ParseError, truncated};
+use crate::information_elements::IERegistry;
+use crate::parse_data::{DataRow, DataSet, DataType, OptionsDataSet};
+use crate::parse_packet::{PacketInfo, PacketResult};
+
+//sFlow version 5 (https://sflow.org/sflow_version_5.txt), the only version we decode
+pub const SFLOW_VERSION: u32 = 5;
+
+//sFlow has no templates, flow samples come out as data sets and counter samples as options data sets with these template ids
+//both are below 256, so they can't be mistaken for an IPFIX template
+pub const SFLOW_FLOW_SAMPLE_ID: u16 = 1;
+pub const SFLOW_COUNTER_SAMPLE_ID: u16 = 2;
+
+//sample and record types are (enterprise << 12) | format, these are all enterprise 0 (sFlow.org)
+const FLOW_SAMPLE: u32 = 1;
+const COUNTER_SAMPLE: u32 = 2;
+const EXPANDED_FLOW_SAMPLE: u32 = 3;
+const EXPANDED_COUNTER_SAMPLE: u32 = 4;
+const RAW_PACKET_HEADER: u32 = 1; //flow record
+const GENERIC_INTERFACE_COUNTERS: u32 = 1; //counter record
+
+const GENERIC_INTERFACE_COUNTERS_LEN: usize = 88;
+
+//header protocols in a raw packet header record whose header starts at the IP header instead of the link layer
+const HEADER_PROTOCOL_IPV4: u32 = 11;
+const HEADER_PROTOCOL_IPV6: u32 = 12;
+
+//picks apart one sFlow v5 datagram into a PacketInfo, so it can go through the aggregator and subscribers like any IPFIX message
+//the sub agent id is the ODID (keyed together with the protocol wherever ODIDs are, so it doesn't mix with IPFIX ODIDs) and the datagram sequence number is the sequence number, it counts datagrams like NetFlow v9 does
+//sFlow datagrams don't say when they were sent, so export_time is when we decoded it
+//samples and records we don't know are skipped, a sample that can't be read is noted in set_errors and the rest of the datagram is still read
+pub fn parse_datagram(registry: &IERegistry, exporter: SocketAddr, pkt: &[u8]) -> PacketResult {
+    match read_datagram(registry, exporter, pkt) {
+        Ok(info) => PacketResult::Ok(info),
+        Err(e) => PacketResult::AbortError(e)
+    }
+}
+
+fn read_datagram(registry: &IERegistry, exporter: SocketAddr, pkt: &[u8]) -> Result<PacketInfo, ParseError> {
+    let (rest, version) = read_u32(pkt, pkt)?;
+    if version != SFLOW_VERSION {
+        return Err(ParseError::BadVersion { version: version.min(u16::MAX as u32) as u16 });
+    }
+
+    //the agent address is what the agent calls itself, it is kept on every record since the datagram could have come through a relay
+    let (rest, address_type) = read_u32(pkt, rest)?;
+    let (rest, agent) = match address_type {
+        1 => {
+            let (rest, bytes) = read_bytes(pkt, rest, 4)?;
+            let octets: [u8; 4] = bytes.try_into().expect("read_bytes returned the wrong length");
+            (rest, row(registry, 130, DataType::IPV4(Ipv4Addr::from(octets))))
+        },
+        2 => {
+            let (rest, bytes) = read_bytes(pkt, rest, 16)?;
+            let octets: [u8; 16] = bytes.try_into().expect("read_bytes returned the wrong length");
+            (rest, row(registry, 131, DataType::IPV6(Ipv6Addr::from(octets))))
+        },
+        _ => { return Err(ParseError::MalformedSFlow { offset: 4, reason: "agent address type is neither IPv4 nor IPv6" }); }
+    };
+
+    let (rest, sub_agent_id) = read_u32(pkt, rest)?;
+    let (rest, seq_num) = read_u32(pkt, rest)?;
+    let (rest, system_uptime) = read_u32(pkt, rest)?;
+    let (mut rest, num_samples) = read_u32(pkt, rest)?;
+
+    let mut data = Vec::new();
+    let mut options_data = Vec::new();
+    let mut set_errors = Vec::new();
+
+    for _ in 0..num_samples {
+        //like a set length, a sample length that doesn't fit means we can't find the next sample
+        let sample_offset = pkt.len() - rest.len();
+        let (sample_rest, sample_type) = read_u32(pkt, rest)?;
+        let (sample_rest, sample_len) = read_u32(pkt, sample_rest)?;
+        if sample_len as usize > sample_rest.len() {
+            return Err(ParseError::MalformedSFlow { offset: sample_offset, reason: "sample runs past the end of the datagram" });
+        }
+        let sample = sample_rest.slice(0..sample_len as usize);
+        rest = sample_rest.slice(sample_len as usize..sample_rest.len());
+
+        //errors from the sample readers are relative to the start of the sample body
+        let body_offset = sample_offset + 8;
+        match sample_type {
+            FLOW_SAMPLE | EXPANDED_FLOW_SAMPLE => match read_flow_sample(registry, sample, sample_type == EXPANDED_FLOW_SAMPLE, &agent) {
+                Ok(d) => { data.push(d); },
+                Err(e) => { set_errors.push(e.shift(body_offset)); }
+            },
+            COUNTER_SAMPLE | EXPANDED_COUNTER_SAMPLE => match read_counter_sample(registry, sample, sample_type == EXPANDED_COUNTER_SAMPLE, &agent) {
+                Ok(d) => { options_data.extend(d); },
+                Err(e) => { set_errors.push(e.shift(body_offset)); }
+            },
+            //other enterprises' samples, or sample types newer than the spec
+            _ => {}
+        }
+    }
+
+    let export_time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or(0);
+
+    Ok(PacketInfo {
+        protocol: Protocol::SFlow,
+        version: SFLOW_VERSION as u16,
+        export_time,
+        seq_num,
+        system_uptime,
+        templates: Vec::new(),
+        options_templates: Vec::new(),
+        withdrawals: Vec::new(),
+        data,
+        options_data,
+        set_error_count: set_errors.len() as u32,
+        set_errors,
+        odid: sub_agent_id,
+        exporter,
+        exporter_identity: None,
+        header_error: None
+    })
+}
+
+//one flow sample becomes one record, the sampling information plus whatever we could get out of its flow records
+//the input and output interfaces are only kept when they are an ifIndex, not when they say the packet was dropped or went out several interfaces
+fn read_flow_sample(registry: &IERegistry, sample: &[u8], expanded: bool, agent: &DataRow) -> Result<DataSet, ParseError> {
+    let (rest, _sample_seq) = read_u32(sample, sample)?;
+    let (rest, _source_id) = read_u32(sample, rest)?;
+    //expanded samples split the source id into a type word and an index word
+    let rest = if expanded { read_u32(sample, rest)?.0 } else { rest };
+    let (rest, sampling_rate) = read_u32(sample, rest)?;
+    let (rest, sample_pool) = read_u32(sample, rest)?;
+    let (rest, drops) = read_u32(sample, rest)?;
+
+    //compact samples pack the format into the top two bits, expanded ones give it a word of its own
+    let (rest, input, output) = if expanded {
+        let (rest, input_format) = read_u32(sample, rest)?;
+        let (rest, input_value) = read_u32(sample, rest)?;
+        let (rest, output_format) = read_u32(sample, rest)?;
+        let (rest, output_value) = read_u32(sample, rest)?;
+        (rest, (input_format == 0).then_some(input_value), (output_format == 0).then_some(output_value))
+    }
+    else {
+        let (rest, input) = read_u32(sample, rest)?;
+        let (rest, output) = read_u32(sample, rest)?;
+        (rest, (input >> 30 == 0).then_some(input & 0x3fff_ffff), (output >> 30 == 0).then_some(output & 0x3fff_ffff))
+    };
+
+    let mut fields = vec![
+        agent.clone(),
+        row(registry, 305, DataType::U32(sampling_rate)),
+        row(registry, 310, DataType::U32(sample_pool)),
+        row(registry, 135, DataType::U64(drops as u64))
+    ];
+    if let Some(i) = input {
+        fields.push(row(registry, 10, DataType::U32(i)));
+    }
+    if let Some(o) = output {
+        fields.push(row(registry, 14, DataType::U32(o)));
+    }
+
+    let (rest, num_records) = read_u32(sample, rest)?;
+    let mut records = rest;
+    for _ in 0..num_records {
+        let (record, record_type) = read_u32(sample, records)?;
+        let (record, record_len) = read_u32(sample, record)?;
+        let record_offset = sample.len() - records.len();
+        if record_len as usize > record.len() {
+            return Err(ParseError::MalformedSFlow { offset: record_offset, reason: "flow record runs past the end of its sample" });
+        }
+        let body = record.slice(0..record_len as usize);
+        records = record.slice(record_len as usize..record.len());
+
+        if record_type == RAW_PACKET_HEADER {
+            fields.extend(read_raw_packet_header(registry, body).map_err(|e| e.shift(record_offset + 8))?);
+        }
+    }
+
+    Ok(DataSet { id: SFLOW_FLOW_SAMPLE_ID, template: SFLOW_FLOW_SAMPLE_ID, fields })
+}
+
+//the frame length and the start of the sampled packet, the header goes into ipHeaderPacketSection if it starts at the IP header
+fn read_raw_packet_header(registry: &IERegistry, record: &[u8]) -> Result<Vec<DataRow>, ParseError> {
+    let (rest, header_protocol) = read_u32(record, record)?;
+    let (rest, frame_length) = read_u32(record, rest)?;
+    let (rest, _stripped) = read_u32(record, rest)?;
+    let (rest, header_len) = read_u32(record, rest)?;
+    if header_len as usize > rest.len() {
+        return Err(ParseError::MalformedSFlow { offset: record.len() - rest.len() - 4, reason: "sampled header is longer than its record" });
+    }
+    let header = rest.slice(0..header_len as usize).to_vec();
+
+    let section = match header_protocol {
+        HEADER_PROTOCOL_IPV4 | HEADER_PROTOCOL_IPV6 => 313,
+        _ => 315
+    };
+
+    Ok(vec![
+        //dataLinkFrameSize is only 16 bits, jumbo frames on some links are bigger than that
+        row(registry, 312, DataType::U16(frame_length.min(u16::MAX as u32) as u16)),
+        row(registry, section, DataType::BYTES(header))
+    ])
+}
+
+//every generic interface counters record in a counter sample becomes an options record scoped to the interface
+//the other counter records (ethernet, vlan, processor...) have no IPFIX elements to go in and are skipped
+fn read_counter_sample(registry: &IERegistry, sample: &[u8], expanded: bool, agent: &DataRow) -> Result<Vec<OptionsDataSet>, ParseError> {
+    let (rest, _sample_seq) = read_u32(sample, sample)?;
+    let (rest, _source_id) = read_u32(sample, rest)?;
+    //expanded samples split the source id into a type word and an index word
+    let rest = if expanded { read_u32(sample, rest)?.0 } else { rest };
+    let (rest, num_records) = read_u32(sample, rest)?;
+
+    let mut sets = Vec::new();
+    let mut records = rest;
+    for _ in 0..num_records {
+        let (record, record_type) = read_u32(sample, records)?;
+        let (record, record_len) = read_u32(sample, record)?;
+        let record_offset = sample.len() - records.len();
+        if record_len as usize > record.len() {
+            return Err(ParseError::MalformedSFlow { offset: record_offset, reason: "counter record runs past the end of its sample" });
+        }
+        let body = record.slice(0..record_len as usize);
+        records = record.slice(record_len as usize..record.len());
+
+        if record_type == GENERIC_INTERFACE_COUNTERS {
+            sets.push(read_interface_counters(registry, body, agent).map_err(|e| e.shift(record_offset + 8))?);
+        }
+    }
+
+    Ok(sets)
+}
+
+//ifSpeed, ifDirection, ifStatus, the error counters, ifOutDiscards, ifInUnknownProtos, and ifPromiscuousMode have no IPFIX element and are left out
+fn read_interface_counters(registry: &IERegistry, record: &[u8], agent: &DataRow) -> Result<OptionsDataSet, ParseError> {
+    if record.len() < GENERIC_INTERFACE_COUNTERS_LEN {
+        return Err(truncated(record, record, GENERIC_INTERFACE_COUNTERS_LEN));
+    }
+
+    let (rest, if_index) = read_u32(record, record)?;
+    let (rest, if_type) = read_u32(record, rest)?;
+    let (rest, _if_speed) = read_u64(record, rest)?;
+    let (rest, _if_direction) = read_u32(record, rest)?;
+    let (rest, _if_status) = read_u32(record, rest)?;
+    let (rest, in_octets) = read_u64(record, rest)?;
+    let (rest, in_ucast) = read_u32(record, rest)?;
+    let (rest, in_mcast) = read_u32(record, rest)?;
+    let (rest, in_bcast) = read_u32(record, rest)?;
+    let (rest, in_discards) = read_u32(record, rest)?;
+    let (rest, _in_errors) = read_u32(record, rest)?;
+    let (rest, _in_unknown_protos) = read_u32(record, rest)?;
+    let (rest, out_octets) = read_u64(record, rest)?;
+    let (rest, out_ucast) = read_u32(record, rest)?;
+    let (rest, out_mcast) = read_u32(record, rest)?;
+    let (_rest, out_bcast) = read_u32(record, rest)?;
+
+    Ok(OptionsDataSet {
+        id: SFLOW_COUNTER_SAMPLE_ID,
+        template: SFLOW_COUNTER_SAMPLE_ID,
+        scope_fields: vec![row(registry, 10, DataType::U32(if_index))],
+        fields: vec![
+            agent.clone(),
+            row(registry, 368, DataType::U32(if_type)),
+            row(registry, 85, DataType::U64(in_octets)),
+            row(registry, 354, DataType::U64(in_ucast as u64)),
+            row(registry, 355, DataType::U64(in_mcast as u64)),
+            row(registry, 356, DataType::U64(in_bcast as u64)),
+            row(registry, 135, DataType::U64(in_discards as u64)),
+            row(registry, 171, DataType::U64(out_octets)),
+            row(registry, 357, DataType::U64(out_ucast as u64)),
+            row(registry, 174, DataType::U64(out_mcast as u64)),
+            row(registry, 358, DataType::U64(out_bcast as u64))
+        ]
+    })
+}
+
+//an IANA element with whatever the registry knows about it
+fn row(registry: &IERegistry, id: u16, val: DataType) -> DataRow {
+    DataRow::with_element(id, 0, registry.get(0, id).cloned(), val)
+}
+
+//everything in sFlow is a 4 or 8 byte big endian word, start is the slice error offsets are relative to
+fn read_u32<'a>(start: &[u8], at: &'a [u8]) -> Result<(&'a [u8], u32), ParseError> {
+    match be_u32::<&[u8], VerboseError<&[u8]>>(at) {
+        Ok(v) => Ok(v),
+        Err(_e) => Err(truncated(start, at, 4))
+    }
+}
+
+fn read_u64<'a>(start: &[u8], at: &'a [u8]) -> Result<(&'a [u8], u64), ParseError> {
+    match be_u64::<&[u8], VerboseError<&[u8]>>(at) {
+        Ok(v) => Ok(v),
+        Err(_e) => Err(truncated(start, at, 8))
+    }
+}
+
+fn read_bytes<'a>(start: &[u8], at: &'a [u8], n: usize) -> Result<(&'a [u8], &'a [u8]), ParseError> {
+    if at.len() < n {
+        return Err(truncated(start, at, n));
+    }
+    Ok((at.slice(n..at.len()), at.slice(0..n)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parse_packet::test_exporter;
+
+    fn words(ws: &[u32]) -> Vec<u8> {
+        ws.iter().flat_map(|w| w.to_be_bytes()).collect()
+    }
+
+    //a type and length followed by the body, which is how samples and records are both laid out
+    fn tagged(tag: u32, body: &[u8]) -> Vec<u8> {
+        let mut out = words(&[tag, body.len() as u32]);
+        out.extend_from_slice(body);
+        out
+    }
+
+    //agent is 4 bytes for an IPv4 agent address or 16 for IPv6, samples are already tagged
+    fn datagram(agent: &[u8], sub_agent_id: u32, seq_num: u32, samples: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = words(&[SFLOW_VERSION, if agent.len() == 4 { 1 } else { 2 }]);
+        out.extend_from_slice(agent);
+        out.extend_from_slice(&words(&[sub_agent_id, seq_num, 5000, samples.len() as u32]));
+        for s in samples {
+            out.extend_from_slice(s);
+        }
+        out
+    }
+
+    //a raw packet header record holding the start of an IPv4 packet (header protocol 11) or an ethernet frame (1)
+    fn raw_header(header_protocol: u32, frame_length: u32, header: &[u8]) -> Vec<u8> {
+        let mut body = words(&[header_protocol, frame_length, 4, header.len() as u32]);
+        body.extend_from_slice(header);
+        tagged(RAW_PACKET_HEADER, &body)
+    }
+
+    //sampling rate 512, sample pool 4096, 3 drops, and the input and output words as they go on the wire
+    fn flow_sample(input: &[u32], output: &[u32], records: &[Vec<u8>]) -> Vec<u8> {
+        let expanded = input.len() == 2;
+        let mut body = words(if expanded { &[1, 0, 7] } else { &[1, 7] });
+        body.extend_from_slice(&words(&[512, 4096, 3]));
+        body.extend_from_slice(&words(input));
+        body.extend_from_slice(&words(output));
+        body.extend_from_slice(&words(&[records.len() as u32]));
+        for r in records {
+            body.extend_from_slice(r);
+        }
+        tagged(if expanded { EXPANDED_FLOW_SAMPLE } else { FLOW_SAMPLE }, &body)
+    }
+
+    //generic interface counters for if_index, every counter set to something that tells it apart
+    fn interface_counters(if_index: u32) -> Vec<u8> {
+        let mut body = words(&[if_index, 6]);
+        body.extend_from_slice(&1_000_000_000u64.to_be_bytes());
+        body.extend_from_slice(&words(&[1, 1]));
+        body.extend_from_slice(&10_000_000_000u64.to_be_bytes());
+        body.extend_from_slice(&words(&[11, 12, 13, 14, 15, 16]));
+        body.extend_from_slice(&20_000_000_000u64.to_be_bytes());
+        body.extend_from_slice(&words(&[21, 22, 23, 24, 25, 0]));
+        assert_eq!(body.len(), GENERIC_INTERFACE_COUNTERS_LEN);
+        tagged(GENERIC_INTERFACE_COUNTERS, &body)
+    }
+
+    fn counter_sample(expanded: bool, records: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = words(if expanded { &[1, 0, 7] } else { &[1, 7] });
+        body.extend_from_slice(&words(&[records.len() as u32]));
+        for r in records {
+            body.extend_from_slice(r);
+        }
+        tagged(if expanded { EXPANDED_COUNTER_SAMPLE } else { COUNTER_SAMPLE }, &body)
+    }
+
+    fn parse(pkt: &[u8]) -> PacketInfo {
+        match parse_datagram(&IERegistry::iana(), test_exporter(), pkt) {
+            PacketResult::Ok(info) => info,
+            PacketResult::AbortError(e) => panic!("datagram should parse: {}", e)
+        }
+    }
+
+    fn field(fields: &[DataRow], id: u16) -> Option<&DataType> {
+        fields.iter().find(|f| f.id == id).map(|f| &f.data)
+    }
+
+    const AGENT: [u8; 4] = [192, 0, 2, 1];
+
+    #[test]
+    fn flow_samples_are_data_for_template_1_and_counter_samples_options_data_for_template_2() {
+        let pkt = datagram(&AGENT, 3, 77, &[flow_sample(&[5], &[6], &[]), counter_sample(false, &[interface_counters(5)])]);
+        let info = parse(&pkt);
+        assert_eq!((info.protocol, info.version, info.odid, info.seq_num, info.system_uptime), (Protocol::SFlow, 5, 3, 77, 5000));
+        assert_eq!(info.set_error_count, 0);
+
+        assert_eq!(info.data.len(), 1);
+        assert_eq!((info.data[0].id, info.data[0].template), (SFLOW_FLOW_SAMPLE_ID, SFLOW_FLOW_SAMPLE_ID));
+        assert_eq!(info.options_data.len(), 1);
+        assert_eq!((info.options_data[0].id, info.options_data[0].template), (SFLOW_COUNTER_SAMPLE_ID, SFLOW_COUNTER_SAMPLE_ID));
+
+        let flow = &info.data[0].fields;
+        assert!(matches!(field(flow, 130), Some(DataType::IPV4(a)) if a.octets() == AGENT));
+        assert!(matches!(field(flow, 305), Some(DataType::U32(512))));
+        assert!(matches!(field(flow, 310), Some(DataType::U32(4096))));
+        assert!(matches!(field(flow, 135), Some(DataType::U64(3))));
+
+        let counters = &info.options_data[0];
+        assert_eq!(counters.scope_fields[0].name(), Some("ingressInterface"));
+        assert!(matches!(counters.scope_fields[0].data, DataType::U32(5)));
+        assert!(matches!(field(&counters.fields, 130), Some(DataType::IPV4(a)) if a.octets() == AGENT));
+        assert!(matches!(field(&counters.fields, 85), Some(DataType::U64(10_000_000_000))));
+        assert!(matches!(field(&counters.fields, 354), Some(DataType::U64(11))));
+        assert!(matches!(field(&counters.fields, 135), Some(DataType::U64(14))));
+        assert!(matches!(field(&counters.fields, 171), Some(DataType::U64(20_000_000_000))));
+        assert!(matches!(field(&counters.fields, 358), Some(DataType::U64(23))));
+    }
+
+    #[test]
+    fn compact_interfaces_keep_their_format_in_the_top_two_bits() {
+        //format 0 is an ifIndex, 1 a dropped packet, and 2 a packet that went out several interfaces
+        let interfaces = |input: u32, output: u32| {
+            let info = parse(&datagram(&AGENT, 0, 0, &[flow_sample(&[input], &[output], &[])]));
+            let fields = &info.data[0].fields;
+            (field(fields, 10).cloned(), field(fields, 14).cloned())
+        };
+        assert!(matches!(interfaces(5, 0x3fff_ffff), (Some(DataType::U32(5)), Some(DataType::U32(0x3fff_ffff)))));
+        assert!(matches!(interfaces(0x4000_0001, 0x8000_0003), (None, None)));
+        assert!(matches!(interfaces(0xc000_0000, 9), (None, Some(DataType::U32(9)))));
+    }
+
+    #[test]
+    fn expanded_samples_give_formats_a_word_of_their_own() {
+        let pkt = datagram(&AGENT, 0, 0, &[flow_sample(&[0, 0x7fff_ffff], &[1, 9], &[raw_header(1, 1514, &[0xaa; 14])]), counter_sample(true, &[interface_counters(0x7fff_ffff)])]);
+        let info = parse(&pkt);
+        assert_eq!(info.set_error_count, 0);
+
+        //an index that wouldn't fit in a compact sample, and an output that isn't an index
+        let flow = &info.data[0].fields;
+        assert!(matches!(field(flow, 10), Some(DataType::U32(0x7fff_ffff))));
+        assert!(field(flow, 14).is_none());
+        assert!(matches!(field(flow, 305), Some(DataType::U32(512))));
+
+        //an ethernet header isn't an IP header
+        assert!(matches!(field(flow, 312), Some(DataType::U16(1514))));
+        assert!(matches!(field(flow, 315), Some(DataType::BYTES(h)) if h == &[0xaa; 14]));
+        assert!(field(flow, 313).is_none());
+
+        assert!(matches!(info.options_data[0].scope_fields[0].data, DataType::U32(0x7fff_ffff)));
+    }
+
+    #[test]
+    fn raw_ip_headers_go_in_the_ip_header_section() {
+        let header = [0x45, 0, 0, 40, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        let info = parse(&datagram(&AGENT, 0, 0, &[flow_sample(&[1], &[2], &[raw_header(11, 100_000, &header)])]));
+        let flow = &info.data[0].fields;
+        assert!(matches!(field(flow, 313), Some(DataType::BYTES(h)) if h == &header));
+        //dataLinkFrameSize is only 16 bits
+        assert!(matches!(field(flow, 312), Some(DataType::U16(u16::MAX))));
+    }
+
+    #[test]
+    fn ipv6_agents_move_the_sub_agent_id_along() {
+        let agent = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        let pkt = datagram(&agent, 9, 123, &[counter_sample(false, &[interface_counters(2)])]);
+        let info = parse(&pkt);
+        assert_eq!((info.odid, info.seq_num, info.system_uptime), (9, 123, 5000));
+        let fields = &info.options_data[0].fields;
+        assert!(matches!(field(fields, 131), Some(DataType::IPV6(a)) if a.octets() == agent));
+        assert!(field(fields, 130).is_none());
+
+        //anything but 1 or 2 is no address type at all
+        let mut bad = pkt.clone();
+        bad[4..8].copy_from_slice(&3u32.to_be_bytes());
+        assert!(matches!(parse_datagram(&IERegistry::iana(), test_exporter(), &bad), PacketResult::AbortError(ParseError::MalformedSFlow { offset: 4, .. })));
+    }
+
+    #[test]
+    fn unreadable_samples_are_skipped_and_unknown_ones_ignored() {
+        //an ethernet counters record (type 2) has nothing to go in, and sample type 5 doesn't exist yet
+        let unknown_record = tagged(2, &[0; 52]);
+        let unknown_sample = tagged(5, &[0; 8]);
+        //a flow record that says it is longer than its sample
+        let mut broken = flow_sample(&[1], &[2], &[]);
+        let last = broken.len() - 4;
+        broken[last..].copy_from_slice(&1u32.to_be_bytes());
+        broken.extend_from_slice(&words(&[RAW_PACKET_HEADER, 100]));
+        let broken_len = (broken.len() - 8) as u32;
+        broken[4..8].copy_from_slice(&broken_len.to_be_bytes());
+
+        let pkt = datagram(&AGENT, 0, 0, &[unknown_sample, broken, counter_sample(false, &[unknown_record, interface_counters(4)])]);
+        let info = parse(&pkt);
+        assert!(info.data.is_empty());
+        assert_eq!(info.options_data.len(), 1);
+        assert_eq!(info.set_error_count, 1);
+        //the broken sample starts after the 28 byte header and the 16 byte unknown sample, its record after the 8 byte sample header and 32 bytes of sample
+        assert!(matches!(info.set_errors[0], ParseError::MalformedSFlow { offset: 84, .. }));
+
+        //a sample that runs past the datagram leaves no way to find the next one
+        let mut cut = datagram(&AGENT, 0, 0, &[counter_sample(false, &[interface_counters(4)])]);
+        cut.truncate(cut.len() - 1);
+        assert!(matches!(parse_datagram(&IERegistry::iana(), test_exporter(), &cut), PacketResult::AbortError(ParseError::MalformedSFlow { offset: 28, .. })));
+    }
+}
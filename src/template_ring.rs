@@ -1,44 +1,375 @@
-use crate::templates::IPFIXTemplate;
+use crate::information_elements::{IERegistry, InformationElement};
+use crate::templates::{IPFIXTemplate, IPFIXOptionsTemplate, TemplateWithdrawal};
 
-use std::{collections::HashMap};
+use std::{collections::{HashMap, HashSet}, net::SocketAddr, sync::{Arc, Mutex, OnceLock, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant}};
+
+use arc_swap::ArcSwap;
 
 //ring here is used like "keyring"
+//one ring is shared by every parser thread, reads never lock: a lookup just loads the current set of templates
+//changes copy the current set, change the copy, and swap it in (read-copy-update), so readers always see a complete set from before or after a change
 pub struct TemplateRing {
-    //(id, odid) -> IPFIXTemplate
-    templates: HashMap<(u16, u32), IPFIXTemplate>,
+    current: ArcSwap<Templates>,
+    //only one change can be made at a time, otherwise two threads could copy the same set and one of their changes would be lost
+    write_lock: Mutex<()>,
+    //refresh times are kept as milliseconds since this instant so they fit in an atomic
+    created: Instant,
+    //information elements used to give the fields of these templates names and types
+    registry: Arc<IERegistry>,
+    //the NetFlow v5 record layout and its resolved elements, built the first time a v5 packet shows up instead of for every packet
+    netflow_v5: OnceLock<(IPFIXTemplate, Vec<Option<Arc<InformationElement>>>)>,
+}
+
+//templates belong to the observation domain of the transport session they arrived on (RFC 7011 section 8), so two exporters can use the same ODID and template ids without clashing
+//for UDP the exporter's address and port stand in for the transport session, for TCP it is the connection, which the exporter's address and port also identify
+type TemplateKey = (SocketAddr, u16, u32);
+
+#[derive(Clone, Default)]
+struct Templates {
+    //(exporter, id, odid) -> IPFIXTemplate
+    templates: HashMap<TemplateKey, Arc<Entry<IPFIXTemplate>>>,
+    //(exporter, id, odid) -> IPFIXOptionsTemplate, template ids are shared between both kinds so an id only ever lives in one of these maps
+    options_templates: HashMap<TemplateKey, Arc<Entry<IPFIXOptionsTemplate>>>,
+    //exporters connected over TCP, their templates last as long as the connection instead of expiring (RFC 7011 section 8)
+    sessions: HashSet<SocketAddr>
+}
+
+struct Entry<T> {
+    template: Arc<T>,
+    //the last time the exporter sent us this template, used to expire templates over UDP (RFC 7011 section 8.4)
+    //exporters resend their templates all the time, updating this in place means a resend doesn't have to copy the whole set
+    last_refreshed: AtomicU64
+}
+
+impl<T> Entry<T> {
+    fn new(template: T, now: u64) -> Arc<Self> {
+        Arc::new(Entry { template: Arc::new(template), last_refreshed: AtomicU64::new(now) })
+    }
+
+    fn refresh(&self, now: u64) {
+        self.last_refreshed.fetch_max(now, Ordering::Relaxed);
+    }
+
+    fn age(&self, now: u64) -> Duration {
+        Duration::from_millis(now.saturating_sub(self.last_refreshed.load(Ordering::Relaxed)))
+    }
+}
+
+impl Default for TemplateRing {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TemplateRing {
+    //a ring that decodes fields using the IANA information elements
     pub fn new() -> Self {
-        TemplateRing { templates: HashMap::new() }
+        TemplateRing::with_registry(Arc::new(IERegistry::iana()))
     }
 
-    pub fn insert_template(&mut self, template: IPFIXTemplate, odid: u32) {
-        match self.templates.insert((template.id, odid), template) {
-            None => {},
-            Some(_k) => { /*TODO: log replacement of old template*/ }
-        };
+    pub fn with_registry(registry: Arc<IERegistry>) -> Self {
+        TemplateRing { current: ArcSwap::from_pointee(Templates::default()), write_lock: Mutex::new(()), created: Instant::now(), registry, netflow_v5: OnceLock::new() }
     }
 
-    pub fn get_template(&self, id: u16, odid: u32) -> Option<IPFIXTemplate> {
-        match self.templates.get(&(id, odid)){
-            None => None,
-            Some(v) => Some(v.clone())
+    pub fn registry(&self) -> &IERegistry {
+        &self.registry
+    }
+
+    //every engine uses the same layout, and a template's odid plays no part in reading records, so one copy does for all of them
+    pub(crate) fn netflow_v5(&self) -> &(IPFIXTemplate, Vec<Option<Arc<InformationElement>>>) {
+        self.netflow_v5.get_or_init(|| {
+            let template = IPFIXTemplate::netflow_v5(0);
+            let elements = self.registry.resolve(&template.fields);
+            (template, elements)
+        })
+    }
+
+    //returns true if this replaced a template with the same id that had a different layout, which usually means the exporter restarted without withdrawing its templates
+    pub fn insert_template(&self, exporter: SocketAddr, template: IPFIXTemplate, odid: u32) -> bool {
+        let key = (exporter, template.id, odid);
+        let now = self.now();
+
+        //the common case is the exporter resending a template we already have
+        if let Some(entry) = self.current.load().templates.get(&key) {
+            if *entry.template == template {
+                entry.refresh(now);
+                return false;
+            }
+        }
+
+        self.update(|t| {
+            let replaced_options = t.options_templates.remove(&key).is_some();
+            match t.templates.insert(key, Entry::new(template, now)) {
+                None => replaced_options,
+                Some(old) => *old.template != *t.templates[&key].template
+            }
+        })
+    }
+
+    pub fn get_template(&self, exporter: SocketAddr, id: u16, odid: u32) -> Option<Arc<IPFIXTemplate>> {
+        self.current.load().templates.get(&(exporter, id, odid)).map(|e| e.template.clone())
+    }
+
+    //same as insert_template, but for options templates
+    pub fn insert_options_template(&self, exporter: SocketAddr, template: IPFIXOptionsTemplate, odid: u32) -> bool {
+        let key = (exporter, template.id, odid);
+        let now = self.now();
+
+        if let Some(entry) = self.current.load().options_templates.get(&key) {
+            if *entry.template == template {
+                entry.refresh(now);
+                return false;
+            }
+        }
+
+        self.update(|t| {
+            let replaced_template = t.templates.remove(&key).is_some();
+            match t.options_templates.insert(key, Entry::new(template, now)) {
+                None => replaced_template,
+                Some(old) => *old.template != *t.options_templates[&key].template
+            }
+        })
+    }
+
+    pub fn get_options_template(&self, exporter: SocketAddr, id: u16, odid: u32) -> Option<Arc<IPFIXOptionsTemplate>> {
+        self.current.load().options_templates.get(&(exporter, id, odid)).map(|e| e.template.clone())
+    }
+
+    //removes the templates covered by the withdrawal, a withdrawal only ever applies to templates from the exporter that sent it
+    //returns how many templates were removed
+    pub fn withdraw(&self, exporter: SocketAddr, withdrawal: &TemplateWithdrawal) -> usize {
+        self.update(|t| {
+            let before = t.templates.len() + t.options_templates.len();
+
+            match withdrawal {
+                TemplateWithdrawal::Template { id, odid } => {
+                    t.templates.remove(&(exporter, *id, *odid));
+                    t.options_templates.remove(&(exporter, *id, *odid));
+                },
+                TemplateWithdrawal::AllTemplates { odid } => {
+                    t.templates.retain(|(t_exporter, _id, t_odid), _t| *t_exporter != exporter || t_odid != odid);
+                },
+                TemplateWithdrawal::AllOptionsTemplates { odid } => {
+                    t.options_templates.retain(|(t_exporter, _id, t_odid), _t| *t_exporter != exporter || t_odid != odid);
+                }
+            }
+
+            before - (t.templates.len() + t.options_templates.len())
+        })
+    }
+
+    //marks exporter as a connection, its templates are kept until close_session instead of expiring
+    pub fn open_session(&self, exporter: SocketAddr) {
+        self.update(|t| { t.sessions.insert(exporter); });
+    }
+
+    //the connection went away, so every template it sent goes with it, returns how many templates were removed
+    pub fn close_session(&self, exporter: SocketAddr) -> usize {
+        self.update(|t| {
+            let before = t.templates.len() + t.options_templates.len();
+            t.sessions.remove(&exporter);
+            t.templates.retain(|(t_exporter, _id, _odid), _t| *t_exporter != exporter);
+            t.options_templates.retain(|(t_exporter, _id, _odid), _t| *t_exporter != exporter);
+            before - (t.templates.len() + t.options_templates.len())
+        })
+    }
+
+    //drops every template (of either kind) that the exporter has not resent within max_age, returns how many templates were removed
+    //templates from open connections never expire
+    pub fn prune_old_templates(&self, max_age: Duration) -> usize {
+        let now = self.now();
+
+        //don't copy the set if nothing has expired, which is nearly every time this is called
+        let current = self.current.load();
+        let expired = |key: &TemplateKey, age: Duration| age > max_age && !current.sessions.contains(&key.0);
+        if !current.templates.iter().any(|(k, e)| expired(k, e.age(now))) && !current.options_templates.iter().any(|(k, e)| expired(k, e.age(now))) {
+            return 0;
         }
+
+        self.update(|t| {
+            let before = t.templates.len() + t.options_templates.len();
+            let sessions = &t.sessions;
+            t.templates.retain(|key, e| e.age(now) <= max_age || sessions.contains(&key.0));
+            t.options_templates.retain(|key, e| e.age(now) <= max_age || sessions.contains(&key.0));
+            before - (t.templates.len() + t.options_templates.len())
+        })
     }
 
-    // pub fn prune_old_templates(&mut self, max_age: Duration) {
-    //     let dead_ids: Vec<u16> = self.last_used.lock().unwrap().iter()
-    //         .filter(|(_id, time)| { time.elapsed() > max_age })
-    //         .map(|(id, _time)| *id)
-    //         .collect();
+    //copies the current set, lets change modify the copy, then swaps the copy in
+    //entries are shared between the old and new set, so the copy is only of the maps and the refresh times carry over
+    fn update<R>(&self, change: impl FnOnce(&mut Templates) -> R) -> R {
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut next = Templates::clone(&self.current.load());
+        let result = change(&mut next);
+        self.current.store(Arc::new(next));
+        result
+    }
 
-    //     for id in dead_ids {
-    //         self.templates.remove(&id);
-    //         self.last_used.lock().unwrap().remove(&id);
-    //     }
-    // }
+    fn now(&self) -> u64 {
+        self.created.elapsed().as_millis() as u64
+    }
 
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use crate::parse_packet::test_exporter;
+    use crate::templates::IPFIXField;
+
+    fn other_exporter() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 2], 4739))
+    }
+
+    //a template of sourceIPv4Address, with a width that sets it apart from the same template id with another layout
+    fn template(id: u16, odid: u32, width: u16) -> IPFIXTemplate {
+        IPFIXTemplate { id, odid, fields: vec![IPFIXField { width, start_byte: 0, en: 0, field_id: 8, variable_length: false }] }
+    }
+
+    //an options template scoped to an ingressInterface
+    fn options_template(id: u16, odid: u32) -> IPFIXOptionsTemplate {
+        let scope = IPFIXField { width: 4, start_byte: 0, en: 0, field_id: 10, variable_length: false };
+        IPFIXOptionsTemplate { id, odid, scope_field_count: 1, scope_fields: vec![scope], fields: Vec::new() }
+    }
+
+    //which of (exporter, id, odid) the ring has a template or options template for
+    fn known(ring: &TemplateRing, keys: &[TemplateKey]) -> Vec<(bool, bool)> {
+        keys.iter().map(|(exporter, id, odid)| (ring.get_template(*exporter, *id, *odid).is_some(), ring.get_options_template(*exporter, *id, *odid).is_some())).collect()
+    }
+
+    //templates 256 and 257 and options templates 258 and 259, for ODIDs 1 and 2 of both exporters
+    fn filled_ring() -> TemplateRing {
+        let ring = TemplateRing::new();
+        for exporter in [test_exporter(), other_exporter()] {
+            for odid in [1, 2] {
+                ring.insert_template(exporter, template(256, odid, 4), odid);
+                ring.insert_template(exporter, template(257, odid, 4), odid);
+                ring.insert_options_template(exporter, options_template(258, odid), odid);
+                ring.insert_options_template(exporter, options_template(259, odid), odid);
+            }
+        }
+        ring
+    }
+
+    fn everything() -> Vec<TemplateKey> {
+        let mut keys = Vec::new();
+        for exporter in [test_exporter(), other_exporter()] {
+            for odid in [1, 2] {
+                for id in 256..=259 {
+                    keys.push((exporter, id, odid));
+                }
+            }
+        }
+        keys
+    }
+
+    //what filled_ring holds for a key, minus the ones in removed
+    fn expected(removed: &[TemplateKey]) -> Vec<(bool, bool)> {
+        everything().iter().map(|key| if removed.contains(key) { (false, false) } else { (key.1 < 258, key.1 >= 258) }).collect()
+    }
+
+    #[test]
+    fn withdrawals_only_reach_the_exporter_and_odid_they_name() {
+        let ring = filled_ring();
+        assert_eq!(ring.withdraw(test_exporter(), &TemplateWithdrawal::Template { id: 257, odid: 1 }), 1);
+        assert_eq!(known(&ring, &everything()), expected(&[(test_exporter(), 257, 1)]));
 
+        //a single withdrawal covers options templates too
+        assert_eq!(ring.withdraw(test_exporter(), &TemplateWithdrawal::Template { id: 259, odid: 1 }), 1);
+        assert_eq!(known(&ring, &everything()), expected(&[(test_exporter(), 257, 1), (test_exporter(), 259, 1)]));
+        //withdrawing something that isn't there does nothing
+        assert_eq!(ring.withdraw(test_exporter(), &TemplateWithdrawal::Template { id: 259, odid: 1 }), 0);
+
+        let ring = filled_ring();
+        assert_eq!(ring.withdraw(other_exporter(), &TemplateWithdrawal::AllTemplates { odid: 2 }), 2);
+        assert_eq!(known(&ring, &everything()), expected(&[(other_exporter(), 256, 2), (other_exporter(), 257, 2)]));
+
+        let ring = filled_ring();
+        assert_eq!(ring.withdraw(other_exporter(), &TemplateWithdrawal::AllOptionsTemplates { odid: 1 }), 2);
+        assert_eq!(known(&ring, &everything()), expected(&[(other_exporter(), 258, 1), (other_exporter(), 259, 1)]));
+    }
+
+    #[test]
+    fn exporters_sharing_an_odid_keep_their_own_templates() {
+        let ring = TemplateRing::new();
+        ring.insert_template(test_exporter(), template(256, 1, 4), 1);
+        assert!(!ring.insert_template(other_exporter(), template(256, 1, 16), 1));
+
+        assert_eq!(ring.get_template(test_exporter(), 256, 1).map(|t| t.fields[0].width), Some(4));
+        assert_eq!(ring.get_template(other_exporter(), 256, 1).map(|t| t.fields[0].width), Some(16));
+        assert!(ring.get_template(test_exporter(), 256, 2).is_none());
+    }
+
+    #[test]
+    fn inserting_reports_a_changed_layout() {
+        let ring = TemplateRing::new();
+        assert!(!ring.insert_template(test_exporter(), template(256, 1, 4), 1));
+        //a resend is only a refresh
+        assert!(!ring.insert_template(test_exporter(), template(256, 1, 4), 1));
+        assert!(ring.insert_template(test_exporter(), template(256, 1, 16), 1));
+        assert_eq!(ring.get_template(test_exporter(), 256, 1).map(|t| t.fields[0].width), Some(16));
+
+        assert!(!ring.insert_options_template(test_exporter(), options_template(258, 1), 1));
+        assert!(!ring.insert_options_template(test_exporter(), options_template(258, 1), 1));
+        let mut wider = options_template(258, 1);
+        wider.scope_fields[0].width = 8;
+        assert!(ring.insert_options_template(test_exporter(), wider, 1));
+    }
+
+    //template ids are shared between both kinds, so a template can take over an options template's id and the other way around
+    #[test]
+    fn templates_and_options_templates_replace_each_other() {
+        let ring = TemplateRing::new();
+        ring.insert_options_template(test_exporter(), options_template(256, 1), 1);
+        assert!(ring.insert_template(test_exporter(), template(256, 1, 4), 1));
+        assert_eq!(known(&ring, &[(test_exporter(), 256, 1)]), vec![(true, false)]);
+
+        assert!(ring.insert_options_template(test_exporter(), options_template(256, 1), 1));
+        assert_eq!(known(&ring, &[(test_exporter(), 256, 1)]), vec![(false, true)]);
+
+        //and it is only ever in one of them, one withdrawal gets rid of it
+        assert_eq!(ring.withdraw(test_exporter(), &TemplateWithdrawal::Template { id: 256, odid: 1 }), 1);
+    }
+
+    #[test]
+    fn templates_expire_unless_resent_or_from_an_open_session() {
+        let ring = TemplateRing::new();
+        let tcp_exporter = other_exporter();
+        ring.open_session(tcp_exporter);
+        ring.insert_template(test_exporter(), template(256, 1, 4), 1);
+        ring.insert_template(test_exporter(), template(257, 1, 4), 1);
+        ring.insert_options_template(test_exporter(), options_template(258, 1), 1);
+        ring.insert_template(tcp_exporter, template(256, 1, 4), 1);
+        ring.insert_options_template(tcp_exporter, options_template(258, 1), 1);
+
+        assert_eq!(ring.prune_old_templates(Duration::from_secs(60)), 0);
+
+        thread::sleep(Duration::from_millis(100));
+        //a resend counts as the exporter still using the template
+        ring.insert_template(test_exporter(), template(257, 1, 4), 1);
+        assert_eq!(ring.prune_old_templates(Duration::from_millis(50)), 2);
+        let keys = [(test_exporter(), 256, 1), (test_exporter(), 257, 1), (test_exporter(), 258, 1), (tcp_exporter, 256, 1), (tcp_exporter, 258, 1)];
+        assert_eq!(known(&ring, &keys), vec![(false, false), (true, false), (false, false), (true, false), (false, true)]);
+
+        //once the session closes its templates are gone, and the exporter's next templates expire like anyone else's
+        assert_eq!(ring.close_session(tcp_exporter), 2);
+        assert_eq!(known(&ring, &keys[3..]), vec![(false, false), (false, false)]);
+        ring.insert_template(tcp_exporter, template(256, 1, 4), 1);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(ring.prune_old_templates(Duration::from_millis(50)), 2);
+        assert!(ring.get_template(tcp_exporter, 256, 1).is_none());
+    }
+
+    #[test]
+    fn closing_a_session_only_drops_its_own_templates() {
+        let ring = filled_ring();
+        ring.open_session(test_exporter());
+        assert_eq!(ring.close_session(test_exporter()), 8);
+        let removed: Vec<TemplateKey> = everything().into_iter().filter(|key| key.0 == test_exporter()).collect();
+        assert_eq!(known(&ring, &everything()), expected(&removed));
+        assert_eq!(ring.close_session(test_exporter()), 0);
+    }
+}
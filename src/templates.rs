@@ -1,115 +1,624 @@
 use nom::{number::complete::{be_u16, be_u32}, error::VerboseError};
 
-#[derive(Clone)]
+use crate::error::{ParseError, truncated};
+
+//NetFlow v5 has a single fixed record layout instead of templates, it is described with this template so v5 records decode into the same DataSets as IPFIX ones
+//the id is one no IPFIX or v9 data set can have, so v5 records can't be mistaken for records from a real template
+pub const V5_TEMPLATE_ID: u16 = 5;
+pub const V5_RECORD_LEN: usize = 48;
+
+//(information element, start byte, width) for each field of a v5 record, the three padding bytes are left out
+const V5_RECORD_FIELDS: [(u16, u32, u16); 18] = [
+    (8, 0, 4), //srcaddr -> sourceIPv4Address
+    (12, 4, 4), //dstaddr -> destinationIPv4Address
+    (15, 8, 4), //nexthop -> ipNextHopIPv4Address
+    (10, 12, 2), //input -> ingressInterface
+    (14, 14, 2), //output -> egressInterface
+    (2, 16, 4), //dPkts -> packetDeltaCount
+    (1, 20, 4), //dOctets -> octetDeltaCount
+    (22, 24, 4), //First -> flowStartSysUpTime
+    (21, 28, 4), //Last -> flowEndSysUpTime
+    (7, 32, 2), //srcport -> sourceTransportPort
+    (11, 34, 2), //dstport -> destinationTransportPort
+    (6, 37, 1), //tcp_flags -> tcpControlBits
+    (4, 38, 1), //prot -> protocolIdentifier
+    (5, 39, 1), //tos -> ipClassOfService
+    (16, 40, 2), //src_as -> bgpSourceAsNumber
+    (17, 42, 2), //dst_as -> bgpDestinationAsNumber
+    (9, 44, 1), //src_mask -> sourceIPv4PrefixLength
+    (13, 45, 1) //dst_mask -> destinationIPv4PrefixLength
+];
+
+//a field length of 65535 in a template means the length is carried in front of each value in the data records (RFC 7011 section 7)
+pub const VARIABLE_LENGTH: u16 = 65535;
+
+//a withdrawal (template id + field count of 0) is the smallest thing that can appear in a template set, anything shorter at the end of a set is padding
+const MIN_TEMPLATE_RECORD_LEN: usize = 4;
+
+//NetFlow v9 (RFC 3954) puts its templates in flowsets 0 and 1 instead of sets 2 and 3
+pub const V9_TEMPLATE_FLOWSET_ID: u16 = 0;
+pub const V9_OPTIONS_TEMPLATE_FLOWSET_ID: u16 = 1;
+
+//template id, scope length, and option length, v9 options templates have no withdrawals so this is the smallest record
+const MIN_V9_OPTIONS_TEMPLATE_RECORD_LEN: usize = 6;
+
+//v9 scope fields have their own type numbers, these are the IPFIX information elements that mean the same thing
+//anything else keeps its v9 number
+const V9_SCOPE_FIELD_ELEMENTS: [(u16, u16); 5] = [
+    (1, 130), //System -> exporterIPv4Address
+    (2, 10), //Interface -> ingressInterface
+    (3, 141), //Line Card -> lineCardId
+    (4, 143), //NetFlow Cache -> meteringProcessId
+    (5, 145) //Template -> templateId
+];
+
+//a template record with a field count of 0 withdraws a template instead of defining one (RFC 7011 section 8.1)
+#[derive(Clone, PartialEq)]
+pub enum TemplateWithdrawal {
+    Template { id: u16, odid: u32 }, //withdraw a single template, regardless of whether it is a regular or options template
+    AllTemplates { odid: u32 }, //template id 2 in a template set withdraws every template
+    AllOptionsTemplates { odid: u32 } //template id 3 in an options template set withdraws every options template
+}
+
+impl TemplateWithdrawal {
+    fn new(set_id: u16, template_id: u16, odid: u32) -> Self {
+        if template_id == set_id {
+            match set_id {
+                2 => TemplateWithdrawal::AllTemplates { odid },
+                _ => TemplateWithdrawal::AllOptionsTemplates { odid }
+            }
+        }
+        else {
+            TemplateWithdrawal::Template { id: template_id, odid }
+        }
+    }
+
+    pub fn odid(&self) -> u32 {
+        match self {
+            TemplateWithdrawal::Template { odid, .. } => *odid,
+            TemplateWithdrawal::AllTemplates { odid } => *odid,
+            TemplateWithdrawal::AllOptionsTemplates { odid } => *odid
+        }
+    }
+}
+
+//what a single record in a template set turned out to be
+pub enum TemplateRecord<T> {
+    Template(T),
+    Withdrawal(TemplateWithdrawal)
+}
+
+#[derive(Clone, PartialEq)]
 pub struct IPFIXTemplate {
     pub id: u16,
     pub odid: u32,
     pub fields: Vec<IPFIXField>
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
+pub struct IPFIXOptionsTemplate {
+    pub id: u16,
+    pub odid: u32,
+    pub scope_field_count: u16,
+    pub scope_fields: Vec<IPFIXField>,
+    pub fields: Vec<IPFIXField>
+}
+
+#[derive(Clone, PartialEq)]
 pub struct IPFIXField {
     pub width: u16,
-    pub start_byte: u32,
+    pub start_byte: u32, //only meaningful until the first variable length field, after that offsets depend on the record
     pub en: u32,
-    pub field_id: u16
+    pub field_id: u16,
+    pub variable_length: bool
 }
 
 impl IPFIXTemplate {
 
+    //This function wants a set of bytes where the first byte in this string is the first byte of a template set's set id
+    //It will parse every template record in the set, and return them plus the first byte after the set (nominally the first byte of the next set's set id)
+    pub fn from_set(i: &[u8], odid: u32) -> Result<(&[u8], Vec<TemplateRecord<Self>>), ParseError> {
+        let (body, next) = split_template_set(i, 2)?;
+
+        let mut records = Vec::new();
+        let mut loop_rest = body;
+        //anything smaller than the smallest possible record (a 4 byte withdrawal) is padding at the end of the set
+        while loop_rest.len() >= MIN_TEMPLATE_RECORD_LEN {
+            let record;
+            let record_offset = 4 + body.len() - loop_rest.len();
+            (loop_rest, record) = IPFIXTemplate::from(loop_rest, odid).map_err(|e| e.shift(record_offset))?;
+            records.push(record);
+        }
+
+        Ok((next, records))
+    }
+
     //This function wants a set of bytes where the first byte in this string is the first byte of the template id for a given template
     //It will then return the parsed template, plus the first byte that appears after this template (nominally the first byte of the next template's template id)
-    pub fn from(i: &[u8], odid: u32) -> Result<(&[u8], Self), String> {
+    pub fn from(i: &[u8], odid: u32) -> Result<(&[u8], TemplateRecord<Self>), ParseError> {
         //this can be minimum 4 bytes: template id, field count
         if i.len() < 4 {
-            return Result::Err(format!("template packet is below the minimum size ({} bytes found, 4 needed)", i.len()));
+            return Result::Err(truncated(i, i, 4));
         }
 
-        //get set id
-        let (rest, set_id) = match be_u16::<&[u8], VerboseError<&[u8]>>(i) {
-            Ok(val) => Result::Ok(val),
-            Err(_) => Result::Err(String::from("failed to parse set_id"))
+        //get template id
+        let (rest, template_id) = match be_u16::<&[u8], VerboseError<&[u8]>>(i) {
+           Ok(v) => Result::Ok(v),
+           Err(_) => Result::Err(truncated(i, i, 2))
+        }?;
+
+
+        //get the field count
+        let (rest, field_count) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest){
+            Ok(v) => Ok(v),
+            Err(_) => Err(truncated(i, rest, 2))
         }?;
 
-        if set_id != 2 {
-            return Result::Err(format!("template packet parser was given bytes that do not appear to be a template packet"))
+        if field_count == 0 {
+            return Ok((rest, TemplateRecord::Withdrawal(TemplateWithdrawal::new(2, template_id, odid))));
+        }
+
+        //make the template with the template id
+        let fields_offset = i.len() - rest.len();
+        let (loop_rest, fields) = parse_fields(rest, field_count).map_err(|e| e.shift(fields_offset))?;
+        let template = IPFIXTemplate {
+            id: template_id,
+            odid,
+            fields
+        };
+
+        Ok((loop_rest, TemplateRecord::Template(template)))
+
+    }
+
+    //Same contract as from_set, but for a NetFlow v9 template flowset (flowset id 0)
+    //v9 has no withdrawals, so every record is a template
+    pub fn from_v9_set(i: &[u8], odid: u32) -> Result<(&[u8], Vec<TemplateRecord<Self>>), ParseError> {
+        let (body, next) = split_template_set(i, V9_TEMPLATE_FLOWSET_ID)?;
+
+        let mut records = Vec::new();
+        let mut loop_rest = body;
+        //flowsets are padded out to 4 bytes, and the smallest template is 8 bytes, so anything less than 4 bytes is padding
+        while loop_rest.len() >= MIN_TEMPLATE_RECORD_LEN {
+            let template;
+            let record_offset = 4 + body.len() - loop_rest.len();
+            (loop_rest, template) = IPFIXTemplate::from_v9(loop_rest, odid).map_err(|e| e.shift(record_offset))?;
+            records.push(TemplateRecord::Template(template));
         }
 
-        //get set template length
-        let (_rest, len) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest) {
-            Ok(v) => Result::Ok(v),
-            Err(_) => Result::Err(String::from("failed to parse template packet length"))
+        Ok((next, records))
+    }
+
+    //Same contract as from, but for a NetFlow v9 template record: template id, field count, then (type, length) pairs without enterprise numbers
+    pub fn from_v9(i: &[u8], odid: u32) -> Result<(&[u8], Self), ParseError> {
+        let (rest, template_id) = match be_u16::<&[u8], VerboseError<&[u8]>>(i) {
+           Ok(v) => Result::Ok(v),
+           Err(_) => Result::Err(truncated(i, i, 2))
+        }?;
+
+        let (rest, field_count) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest){
+            Ok(v) => Ok(v),
+            Err(_) => Err(truncated(i, rest, 2))
         }?;
 
-        //if there are less bytes in our working array than the packet says it has, the template is incomplete
-        if i.len() < len as usize {
-            return Result::Err(format!("Parsing template packet with set_id: {} failed, needed {} bytes, found {}", set_id, len, i.len()))
+        if field_count == 0 {
+            return Result::Err(ParseError::MalformedTemplate { offset: 0, set_id: V9_TEMPLATE_FLOWSET_ID, template_id, reason: "a NetFlow v9 template needs at least one field" });
+        }
+
+        let fields_offset = i.len() - rest.len();
+        let (loop_rest, fields) = parse_v9_fields(rest, field_count, 0).map_err(|e| e.shift(fields_offset))?;
+
+        Ok((loop_rest, IPFIXTemplate { id: template_id, odid, fields }))
+    }
+
+    //the layout of a NetFlow v5 record, every field sits at its start_byte (there is padding between some of them)
+    pub fn netflow_v5(odid: u32) -> Self {
+        let fields = V5_RECORD_FIELDS.iter()
+            .map(|(field_id, start_byte, width)| IPFIXField { width: *width, start_byte: *start_byte, en: 0, field_id: *field_id, variable_length: false })
+            .collect();
+        IPFIXTemplate { id: V5_TEMPLATE_ID, odid, fields }
+    }
+
+    //the smallest number of bytes a data record using this template can take up (variable length fields count as their 1 byte length prefix)
+    pub fn min_record_len(&self) -> usize {
+        min_record_len(&self.fields)
+    }
+
+    //if there are no variable length fields every record is exactly min_record_len bytes long
+    pub fn has_variable_length_fields(&self) -> bool {
+        self.fields.iter().any(|f| f.variable_length)
+    }
+}
+
+impl IPFIXOptionsTemplate {
+
+    //Same contract as IPFIXTemplate::from_set, but for options template sets (set id 3)
+    pub fn from_set(i: &[u8], odid: u32) -> Result<(&[u8], Vec<TemplateRecord<Self>>), ParseError> {
+        let (body, next) = split_template_set(i, 3)?;
+
+        let mut records = Vec::new();
+        let mut loop_rest = body;
+        //anything smaller than the smallest possible record (a 4 byte withdrawal) is padding at the end of the set
+        while loop_rest.len() >= MIN_TEMPLATE_RECORD_LEN {
+            let record;
+            let record_offset = 4 + body.len() - loop_rest.len();
+            (loop_rest, record) = IPFIXOptionsTemplate::from(loop_rest, odid).map_err(|e| e.shift(record_offset))?;
+            records.push(record);
+        }
+
+        Ok((next, records))
+    }
+
+    //Same contract as IPFIXTemplate::from, but for options template records
+    //The first scope_field_count fields of an options template are the scope, the rest are the option fields themselves
+    pub fn from(i: &[u8], odid: u32) -> Result<(&[u8], TemplateRecord<Self>), ParseError> {
+        //this can be minimum 4 bytes: template id, field count (withdrawals stop here, everything else also has a scope field count)
+        if i.len() < 4 {
+            return Result::Err(truncated(i, i, 4));
         }
 
         //get template id
         let (rest, template_id) = match be_u16::<&[u8], VerboseError<&[u8]>>(i) {
            Ok(v) => Result::Ok(v),
-           Err(_) => Result::Err(String::from("failed to parse template packet id"))
+           Err(_) => Result::Err(truncated(i, i, 2))
         }?;
 
-
-        //get the field count
+        //get the field count, this includes the scope fields
         let (rest, field_count) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest){
             Ok(v) => Ok(v),
-            Err(_) => Err(String::from("failed to parse template packet field count"))
+            Err(_) => Err(truncated(i, rest, 2))
         }?;
 
-        //make the template with the template id
-        let mut template = IPFIXTemplate {
+        //withdrawals don't carry a scope field count
+        if field_count == 0 {
+            return Ok((rest, TemplateRecord::Withdrawal(TemplateWithdrawal::new(3, template_id, odid))));
+        }
+
+        //get the scope field count
+        let (rest, scope_field_count) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest){
+            Ok(v) => Ok(v),
+            Err(_) => Err(truncated(i, rest, 2))
+        }?;
+
+        //RFC 7011 says there has to be at least one scope field, and there can't be more scope fields than fields
+        if scope_field_count == 0 || scope_field_count > field_count {
+            return Result::Err(ParseError::MalformedTemplate { offset: 0, set_id: 3, template_id, reason: "scope field count must be between 1 and the field count" });
+        }
+
+        let fields_offset = i.len() - rest.len();
+        let (loop_rest, mut fields) = parse_fields(rest, field_count).map_err(|e| e.shift(fields_offset))?;
+        let option_fields = fields.split_off(scope_field_count as usize);
+
+        let template = IPFIXOptionsTemplate {
             id: template_id,
-            odid: odid,
-            fields: Vec::new()
+            odid,
+            scope_field_count,
+            scope_fields: fields,
+            fields: option_fields
         };
 
-        //loop through the fields and add them to the template
-        let mut cur_byte = 0; //keep track of where the first byte of the field is in the data packet
-        //the rest of our system wants to work in an offset + width world, but IPFIX templates just have a bunch of widths, so accumulate the widths to give us an offset
+        Ok((loop_rest, TemplateRecord::Template(template)))
+    }
 
-        let mut loop_rest = rest;
-        for i in 0..field_count {
-            let loop_id: u16;
-            let loop_len: u16;
-            let mut loop_en: u32 = 0;
+    //Same contract as IPFIXTemplate::from_v9_set, but for a NetFlow v9 options template flowset (flowset id 1)
+    pub fn from_v9_set(i: &[u8], odid: u32) -> Result<(&[u8], Vec<TemplateRecord<Self>>), ParseError> {
+        let (body, next) = split_template_set(i, V9_OPTIONS_TEMPLATE_FLOWSET_ID)?;
 
-            //get the id
-            (loop_rest, loop_id) = match be_u16::<&[u8], VerboseError<&[u8]>>(loop_rest) {
-                Ok(v) => Ok(v),
-                Err(_) => Err(format!("failed to parse template field id for field {}", i))
-            }?;
+        let mut records = Vec::new();
+        let mut loop_rest = body;
+        //anything too short to hold the header of an options template record is padding
+        while loop_rest.len() >= MIN_V9_OPTIONS_TEMPLATE_RECORD_LEN {
+            let template;
+            let record_offset = 4 + body.len() - loop_rest.len();
+            (loop_rest, template) = IPFIXOptionsTemplate::from_v9(loop_rest, odid).map_err(|e| e.shift(record_offset))?;
+            records.push(TemplateRecord::Template(template));
+        }
+
+        Ok((next, records))
+    }
 
-            //get the size
-            (loop_rest, loop_len) = match be_u16::<&[u8], VerboseError<&[u8]>>(loop_rest) {
+    //Same contract as IPFIXTemplate::from_v9, but for a NetFlow v9 options template record
+    //v9 gives the length in bytes of the scope and option field specifiers instead of field counts, each specifier is 4 bytes
+    pub fn from_v9(i: &[u8], odid: u32) -> Result<(&[u8], Self), ParseError> {
+        let (rest, template_id) = match be_u16::<&[u8], VerboseError<&[u8]>>(i) {
+           Ok(v) => Result::Ok(v),
+           Err(_) => Result::Err(truncated(i, i, 2))
+        }?;
+
+        let (rest, scope_len) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest){
+            Ok(v) => Ok(v),
+            Err(_) => Err(truncated(i, rest, 2))
+        }?;
+
+        let (rest, option_len) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest){
+            Ok(v) => Ok(v),
+            Err(_) => Err(truncated(i, rest, 2))
+        }?;
+
+        if scope_len == 0 || scope_len % 4 != 0 || option_len % 4 != 0 {
+            return Result::Err(ParseError::MalformedTemplate { offset: 0, set_id: V9_OPTIONS_TEMPLATE_FLOWSET_ID, template_id, reason: "scope and option lengths must be multiples of 4, with at least one scope field" });
+        }
+
+        let scope_field_count = scope_len / 4;
+        let fields_offset = i.len() - rest.len();
+        let (rest, mut scope_fields) = parse_v9_fields(rest, scope_field_count, 0).map_err(|e| e.shift(fields_offset))?;
+        for f in scope_fields.iter_mut() {
+            if let Some((_v9, ie)) = V9_SCOPE_FIELD_ELEMENTS.iter().find(|(v9, _ie)| *v9 == f.field_id) {
+                f.field_id = *ie;
+            }
+        }
+
+        //option fields pick up where the scope fields left off in the record
+        let scope_width = scope_fields.iter().map(|f| f.width as u32).sum();
+        let fields_offset = i.len() - rest.len();
+        let (loop_rest, fields) = parse_v9_fields(rest, option_len / 4, scope_width).map_err(|e| e.shift(fields_offset))?;
+
+        let template = IPFIXOptionsTemplate {
+            id: template_id,
+            odid,
+            scope_field_count,
+            scope_fields,
+            fields
+        };
+
+        Ok((loop_rest, template))
+    }
+
+    //same as IPFIXTemplate::min_record_len, the scope fields are part of the record too
+    pub fn min_record_len(&self) -> usize {
+        min_record_len(&self.scope_fields) + min_record_len(&self.fields)
+    }
+
+    //same as IPFIXTemplate::has_variable_length_fields
+    pub fn has_variable_length_fields(&self) -> bool {
+        self.scope_fields.iter().chain(self.fields.iter()).any(|f| f.variable_length)
+    }
+}
+
+fn min_record_len(fields: &[IPFIXField]) -> usize {
+    fields.iter()
+        .map(|f| if f.variable_length { 1 } else { f.width as usize })
+        .sum()
+}
+
+//checks the set header of a (options) template set and splits the set into its records and everything after the set
+//returns (records, rest)
+fn split_template_set(i: &[u8], expected_set_id: u16) -> Result<(&[u8], &[u8]), ParseError> {
+    //this can be minimum 4 bytes: set id, set length
+    if i.len() < 4 {
+        return Result::Err(truncated(i, i, 4));
+    }
+
+    //get set id
+    let (rest, set_id) = match be_u16::<&[u8], VerboseError<&[u8]>>(i) {
+        Ok(val) => Result::Ok(val),
+        Err(_) => Result::Err(truncated(i, i, 2))
+    }?;
+
+    if set_id != expected_set_id {
+        return Result::Err(ParseError::MalformedTemplate { offset: 0, set_id, template_id: 0, reason: "set is not the expected kind of template set" })
+    }
+
+    //get set template length
+    let (_rest, len) = match be_u16::<&[u8], VerboseError<&[u8]>>(rest) {
+        Ok(v) => Result::Ok(v),
+        Err(_) => Result::Err(truncated(i, rest, 2))
+    }?;
+
+    //the length includes the 4 byte set header, so anything less is nonsense
+    //if there are less bytes in our working array than the packet says it has, the template is incomplete
+    if len < 4 || i.len() < len as usize {
+        return Result::Err(ParseError::SetLengthOverflow { offset: 0, set_id, set_len: len, available: i.len() })
+    }
+
+    Ok((&i[4..len as usize], &i[len as usize..]))
+}
+
+//reads field_count NetFlow v9 field specifiers (type, length) starting at the first byte of i, the first field starts at start_byte in the record
+//v9 has neither enterprise numbers nor variable length fields, types above 32767 are vendor specific and are kept as they are
+fn parse_v9_fields(i: &[u8], field_count: u16, start_byte: u32) -> Result<(&[u8], Vec<IPFIXField>), ParseError> {
+    let mut fields = Vec::new();
+    let mut cur_byte = start_byte;
+
+    let mut loop_rest = i;
+    for _ in 0..field_count {
+        let field_id: u16;
+        let width: u16;
+
+        (loop_rest, field_id) = match be_u16::<&[u8], VerboseError<&[u8]>>(loop_rest) {
+            Ok(v) => Ok(v),
+            Err(_) => Err(truncated(i, loop_rest, 2))
+        }?;
+
+        (loop_rest, width) = match be_u16::<&[u8], VerboseError<&[u8]>>(loop_rest) {
+            Ok(v) => Ok(v),
+            Err(_) => Err(truncated(i, loop_rest, 2))
+        }?;
+
+        fields.push(IPFIXField { width, start_byte: cur_byte, en: 0, field_id, variable_length: false });
+        cur_byte += width as u32;
+    }
+
+    Ok((loop_rest, fields))
+}
+
+//reads field_count field specifiers (id, length, and optionally enterprise number) starting at the first byte of i
+//returns the fields plus the first byte after the last field specifier
+fn parse_fields(i: &[u8], field_count: u16) -> Result<(&[u8], Vec<IPFIXField>), ParseError> {
+    let mut fields = Vec::new();
+
+    //loop through the fields and add them to the template
+    let mut cur_byte = 0; //keep track of where the first byte of the field is in the data packet
+    //the rest of our system wants to work in an offset + width world, but IPFIX templates just have a bunch of widths, so accumulate the widths to give us an offset
+
+    let mut loop_rest = i;
+    for _ in 0..field_count {
+        let loop_id: u16;
+        let loop_len: u16;
+        let mut loop_en: u32 = 0;
+
+        //get the id
+        (loop_rest, loop_id) = match be_u16::<&[u8], VerboseError<&[u8]>>(loop_rest) {
+            Ok(v) => Ok(v),
+            Err(_) => Err(truncated(i, loop_rest, 2))
+        }?;
+
+        //get the size
+        (loop_rest, loop_len) = match be_u16::<&[u8], VerboseError<&[u8]>>(loop_rest) {
+            Ok(v) => Ok(v),
+            Err(_) => Err(truncated(i, loop_rest, 2))
+        }?;
+
+        //check if there is an enterprise number and grab it, otherwise it defaults to 0
+        if loop_id & 0x8000u16 > 0 { //one bit at the head of the ID means there is an enterprise number
+            (loop_rest, loop_en) = match be_u32::<&[u8], VerboseError<&[u8]>>(loop_rest) {
                 Ok(v) => Ok(v),
-                Err(_) => Err(format!("failed to parse field length for template field {}", i))
+                Err(_) => Err(truncated(i, loop_rest, 4))
             }?;
+        }
 
-            //check if there is an enterprise number and grab it, otherwise it defaults to 0
-            if loop_id & 0x8000u16 > 0 { //one bit at the head of the ID means there is an enterprise number
-                (loop_rest, loop_en) = match be_u32::<&[u8], VerboseError<&[u8]>>(loop_rest) {
-                    Ok(v) => Ok(v),
-                    Err(_) => Err(format!("failed to parse field length for template field {}", i))
-                }?;
-            }
+        let loop_id_no_en_bit = loop_id & !0x8000u16; //mask off the bit for en number
 
-            let loop_id_no_en_bit = loop_id & !0x8000u16; //mask off the bit for en number
+        let variable_length = loop_len == VARIABLE_LENGTH;
 
-            let row = IPFIXField {
-                field_id: loop_id_no_en_bit,
-                width: loop_len,
-                start_byte: cur_byte,
-                en: loop_en
-            };
+        let row = IPFIXField {
+            field_id: loop_id_no_en_bit,
+            width: loop_len,
+            start_byte: cur_byte,
+            en: loop_en,
+            variable_length
+        };
 
-            template.fields.push(row);
+        fields.push(row);
+        if !variable_length {
             cur_byte += loop_len as u32; //update the offset with the current width
         }
+    }
 
-        Ok((loop_rest, template))
+    Ok((loop_rest, fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a set with the given id holding records, followed by padding zero bytes and then a byte from whatever comes after the set
+    fn set(set_id: u16, records: &[&[u8]], padding: usize) -> Vec<u8> {
+        let len = 4 + records.iter().map(|r| r.len()).sum::<usize>() + padding;
+        let mut set = Vec::with_capacity(len + 1);
+        set.extend_from_slice(&set_id.to_be_bytes());
+        set.extend_from_slice(&(len as u16).to_be_bytes());
+        for r in records {
+            set.extend_from_slice(r);
+        }
+        set.resize(len, 0);
+        set.push(0xaa);
+        set
+    }
+
+    fn template(record: &TemplateRecord<IPFIXTemplate>) -> &IPFIXTemplate {
+        match record {
+            TemplateRecord::Template(t) => t,
+            TemplateRecord::Withdrawal(_) => panic!("expected a template, got a withdrawal")
+        }
+    }
 
+    fn options_template(record: &TemplateRecord<IPFIXOptionsTemplate>) -> &IPFIXOptionsTemplate {
+        match record {
+            TemplateRecord::Template(t) => t,
+            TemplateRecord::Withdrawal(_) => panic!("expected an options template, got a withdrawal")
+        }
     }
-}
\ No newline at end of file
+
+    fn layout(fields: &[IPFIXField]) -> Vec<(u16, u32, u16, u32, bool)> {
+        fields.iter().map(|f| (f.field_id, f.en, f.width, f.start_byte, f.variable_length)).collect()
+    }
+
+    #[test]
+    fn template_sets_hold_several_templates_then_padding() {
+        //sourceIPv4Address and sourceTransportPort
+        let plain: &[u8] = &[1, 0, 0, 2, 0, 8, 0, 4, 0, 7, 0, 2];
+        //an enterprise field, a variable length interfaceName, and destinationIPv4Address
+        let mixed: &[u8] = &[1, 1, 0, 3, 0x80, 100, 0, 4, 0, 0, 0, 9, 0, 82, 0xff, 0xff, 0, 12, 0, 4];
+        let withdrawal: &[u8] = &[1, 2, 0, 0];
+        let bytes = set(2, &[plain, mixed, withdrawal], 3);
+
+        let (next, records) = IPFIXTemplate::from_set(&bytes, 7).unwrap_or_else(|e| panic!("template set should parse: {}", e));
+        assert_eq!(next, &[0xaa]);
+        assert_eq!(records.len(), 3);
+
+        let first = template(&records[0]);
+        assert_eq!((first.id, first.odid), (256, 7));
+        assert_eq!(layout(&first.fields), vec![(8, 0, 4, 0, false), (7, 0, 2, 4, false)]);
+        assert_eq!((first.min_record_len(), first.has_variable_length_fields()), (6, false));
+
+        //the fields after a variable length one don't have a fixed offset, they start where it does until a record says otherwise
+        let second = template(&records[1]);
+        assert_eq!(second.id, 257);
+        assert_eq!(layout(&second.fields), vec![(100, 9, 4, 0, false), (82, 0, VARIABLE_LENGTH, 4, true), (12, 0, 4, 4, false)]);
+        assert_eq!((second.min_record_len(), second.has_variable_length_fields()), (9, true));
+
+        assert!(matches!(&records[2], TemplateRecord::Withdrawal(w) if *w == TemplateWithdrawal::Template { id: 258, odid: 7 }));
+    }
+
+    #[test]
+    fn set_id_equal_to_the_template_id_withdraws_everything() {
+        let (_next, records) = IPFIXTemplate::from_set(&set(2, &[&[0, 2, 0, 0]], 0), 7).unwrap_or_else(|e| panic!("template set should parse: {}", e));
+        assert!(matches!(&records[0], TemplateRecord::Withdrawal(w) if *w == TemplateWithdrawal::AllTemplates { odid: 7 }));
+
+        let (_next, records) = IPFIXOptionsTemplate::from_set(&set(3, &[&[0, 3, 0, 0], &[1, 0, 0, 0]], 0), 7).unwrap_or_else(|e| panic!("options template set should parse: {}", e));
+        assert!(matches!(&records[0], TemplateRecord::Withdrawal(w) if *w == TemplateWithdrawal::AllOptionsTemplates { odid: 7 }));
+        assert!(matches!(&records[1], TemplateRecord::Withdrawal(w) if *w == TemplateWithdrawal::Template { id: 256, odid: 7 }));
+        assert_eq!(records.iter().map(|r| match r { TemplateRecord::Withdrawal(w) => w.odid(), _ => 0 }).collect::<Vec<_>>(), vec![7, 7]);
+    }
+
+    //options template 300 with field_count fields, ingressInterface then sourceIPv4Address then destinationIPv4Address, the first scope_field_count of them are the scope
+    fn options_record(field_count: u16, scope_field_count: u16) -> Vec<u8> {
+        let mut record = vec![1, 44];
+        record.extend_from_slice(&field_count.to_be_bytes());
+        record.extend_from_slice(&scope_field_count.to_be_bytes());
+        record.extend_from_slice(&[0, 10, 0, 4, 0, 8, 0, 4, 0, 12, 0, 4][..4 * field_count as usize]);
+        record
+    }
+
+    #[test]
+    fn options_templates_split_off_their_scope() {
+        let bytes = set(3, &[&options_record(3, 1)], 0);
+        let (next, records) = IPFIXOptionsTemplate::from_set(&bytes, 7).unwrap_or_else(|e| panic!("options template set should parse: {}", e));
+        assert_eq!(next, &[0xaa]);
+        let t = options_template(&records[0]);
+        assert_eq!((t.id, t.odid, t.scope_field_count), (300, 7, 1));
+        assert_eq!(layout(&t.scope_fields), vec![(10, 0, 4, 0, false)]);
+        //option fields start after the scope fields in the record
+        assert_eq!(layout(&t.fields), vec![(8, 0, 4, 4, false), (12, 0, 4, 8, false)]);
+        assert_eq!(t.min_record_len(), 12);
+
+        //every field can be scope
+        let bytes = set(3, &[&options_record(3, 3)], 0);
+        let (_next, records) = IPFIXOptionsTemplate::from_set(&bytes, 7).unwrap_or_else(|e| panic!("options template set should parse: {}", e));
+        let t = options_template(&records[0]);
+        assert_eq!((t.scope_fields.len(), t.fields.len()), (3, 0));
+    }
+
+    #[test]
+    fn options_templates_need_between_one_and_field_count_scope_fields() {
+        for (field_count, scope_field_count) in [(2, 0), (2, 3)] {
+            let record = options_record(field_count, scope_field_count);
+            match IPFIXOptionsTemplate::from_set(&set(3, &[&record], 0), 7) {
+                Err(ParseError::MalformedTemplate { offset, set_id, template_id, .. }) => assert_eq!((offset, set_id, template_id), (4, 3, 300)),
+                Err(e) => panic!("expected a malformed template, got {}", e),
+                Ok(_) => panic!("a scope field count of {} out of {} fields should not parse", scope_field_count, field_count)
+            }
+        }
+    }
+
+    #[test]
+    fn sets_have_to_be_the_right_kind_and_fit() {
+        let bytes = set(2, &[&[1, 0, 0, 1, 0, 8, 0, 4]], 0);
+        assert!(matches!(IPFIXOptionsTemplate::from_set(&bytes, 0), Err(ParseError::MalformedTemplate { set_id: 2, .. })));
+        assert!(matches!(IPFIXTemplate::from_set(&bytes[..8], 0), Err(ParseError::SetLengthOverflow { set_len: 12, available: 8, .. })));
+        //a template that says it has more fields than the set holds
+        let bytes = set(2, &[&[1, 0, 0, 2, 0, 8, 0, 4]], 0);
+        assert!(matches!(IPFIXTemplate::from_set(&bytes, 0), Err(ParseError::TruncatedHeader { offset: 12, needed: 2, available: 0 })));
+    }
+}
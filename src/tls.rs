@@ -0,0 +1,110 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::RootCertStore;
+use rustls::ServerConfig;
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::server::WebPkiClientVerifier;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::FromDer;
+
+use crate::config::TlsConfig;
+
+//who an exporter connected over TLS says it is, taken from the certificate it presented
+//only exporters on a listener with TlsConfig::client_ca set have had their certificate checked, otherwise they don't send one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExporterIdentity {
+    pub subject: String, //the subject's distinguished name, "CN=exporter1, O=Example"
+    pub subject_alt_names: Vec<String>, //DNS names, IP addresses, email addresses, and URIs, in the order the certificate lists them
+    pub certificate: Vec<u8> //the whole certificate (DER) for anything the fields above don't cover
+}
+
+impl ExporterIdentity {
+    //None if the certificate can't be read, rustls has already checked it if it had to be checked
+    pub fn from_certificate(der: &[u8]) -> Option<Self> {
+        let (_rest, cert) = X509Certificate::from_der(der).ok()?;
+
+        let mut subject_alt_names = Vec::new();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in san.value.general_names.iter() {
+                match name {
+                    GeneralName::DNSName(n) => subject_alt_names.push(n.to_string()),
+                    GeneralName::RFC822Name(n) => subject_alt_names.push(n.to_string()),
+                    GeneralName::URI(n) => subject_alt_names.push(n.to_string()),
+                    GeneralName::IPAddress(ip) => {
+                        if let Ok(v4) = <[u8; 4]>::try_from(*ip) {
+                            subject_alt_names.push(Ipv4Addr::from(v4).to_string());
+                        }
+                        else if let Ok(v6) = <[u8; 16]>::try_from(*ip) {
+                            subject_alt_names.push(Ipv6Addr::from(v6).to_string());
+                        }
+                    },
+                    //directory names and the rarer kinds don't identify an exporter any better than the subject does
+                    _ => {}
+                }
+            }
+        }
+
+        Some(ExporterIdentity { subject: cert.subject().to_string(), subject_alt_names, certificate: der.to_vec() })
+    }
+}
+
+//what can go wrong while setting up a TLS listener
+#[derive(Debug)]
+pub enum TlsSetupError {
+    Pem(PathBuf, pem::Error), //the file couldn't be read, or isn't PEM
+    NoCertificate(PathBuf), //the file has no certificates in it
+    Rustls(rustls::Error), //the certificates and key don't go together, or rustls won't use them
+    ClientVerifier(String) //the client CA certificates can't be used to check exporters
+}
+
+impl fmt::Display for TlsSetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsSetupError::Pem(path, e) => write!(f, "failed to read {}: {}", path.display(), e),
+            TlsSetupError::NoCertificate(path) => write!(f, "{} has no certificates in it", path.display()),
+            TlsSetupError::Rustls(e) => write!(f, "TLS setup failed: {}", e),
+            TlsSetupError::ClientVerifier(e) => write!(f, "client CA certificates can't be used: {}", e)
+        }
+    }
+}
+
+impl std::error::Error for TlsSetupError {}
+
+//the rustls configuration every connection on a TLS listener shares
+//ring is named explicitly so it doesn't matter which crypto providers other crates turn on
+pub(crate) fn server_config(cfg: &TlsConfig) -> Result<Arc<ServerConfig>, TlsSetupError> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions().map_err(TlsSetupError::Rustls)?;
+
+    let builder = match &cfg.client_ca {
+        None => builder.with_no_client_auth(),
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certificates(path)? {
+                roots.add(cert).map_err(TlsSetupError::Rustls)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build().map_err(|e| TlsSetupError::ClientVerifier(e.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+
+    let chain = read_certificates(&cfg.certificate_chain)?;
+    let key = PrivateKeyDer::from_pem_file(&cfg.private_key).map_err(|e| TlsSetupError::Pem(cfg.private_key.clone(), e))?;
+    let config = builder.with_single_cert(chain, key).map_err(TlsSetupError::Rustls)?;
+    Ok(Arc::new(config))
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsSetupError> {
+    let certs = CertificateDer::pem_file_iter(path).map_err(|e| TlsSetupError::Pem(path.to_path_buf(), e))?
+        .collect::<Result<Vec<_>, _>>().map_err(|e| TlsSetupError::Pem(path.to_path_buf(), e))?;
+    if certs.is_empty() {
+        return Err(TlsSetupError::NoCertificate(path.to_path_buf()));
+    }
+    Ok(certs)
+}
@@ -0,0 +1,286 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use ipfix_parser_rs::{FileReadError, IPFIXFileReader, Protocol, ValidationMode};
+use ipfix_parser_rs::error::ParseError;
+use ipfix_parser_rs::information_elements::IERegistry;
+use ipfix_parser_rs::ipfix_file::FILE_EXPORTER;
+use ipfix_parser_rs::parse_packet::{parse_packet, PacketInfo, PacketResult};
+use ipfix_parser_rs::sflow;
+use ipfix_parser_rs::template_ring::TemplateRing;
+
+//the fuzz corpus doubles as our collection of known bad packets
+fn corpus_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/parse_packet")
+}
+
+fn read_case(name: &str) -> Vec<u8> {
+    fs::read(corpus_dir().join(name)).unwrap_or_else(|e| panic!("failed to read corpus file {}: {}", name, e))
+}
+
+//every packet in these tests comes from the same exporter, the library's own test helpers aren't built for integration tests
+fn exporter() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 4739))
+}
+
+//same as the fuzz target: parse twice with the same ring, so data sets that come before their template in the packet get decoded the second time
+fn parse_twice(pkt: &[u8]) -> PacketResult {
+    let ring = TemplateRing::new();
+    let _ = parse_packet(&ring, exporter(), pkt, ValidationMode::Strict);
+    parse_packet(&ring, exporter(), pkt, ValidationMode::Strict)
+}
+
+fn parse_lenient(pkt: &[u8]) -> PacketInfo {
+    match parse_packet(&TemplateRing::new(), exporter(), pkt, ValidationMode::Lenient) {
+        PacketResult::AbortError(e) => panic!("lenient mode should have parsed what it could: {}", e),
+        PacketResult::Ok(info) => info
+    }
+}
+
+//sFlow has no templates, so there is nothing to gain from parsing twice
+fn parse_sflow(pkt: &[u8]) -> PacketResult {
+    sflow::parse_datagram(&IERegistry::iana(), exporter(), pkt)
+}
+
+fn abort_error(name: &str) -> ParseError {
+    match parse_twice(&read_case(name)) {
+        PacketResult::AbortError(e) => e,
+        PacketResult::Ok(_) => panic!("{} should not have parsed", name)
+    }
+}
+
+fn set_errors(name: &str) -> Vec<ParseError> {
+    match parse_twice(&read_case(name)) {
+        PacketResult::AbortError(e) => panic!("{} should have parsed, got {}", name, e),
+        PacketResult::Ok(info) => info.set_errors
+    }
+}
+
+#[test]
+fn corpus_never_panics() {
+    let mut count = 0;
+    for entry in fs::read_dir(corpus_dir()).expect("fuzz corpus is missing") {
+        let pkt = fs::read(entry.expect("failed to read corpus entry").path()).expect("failed to read corpus file");
+        parse_twice(&pkt);
+        parse_sflow(&pkt);
+
+        //every prefix of a packet is also a truncated packet
+        for end in 0..pkt.len() {
+            parse_twice(&pkt[..end]);
+            parse_sflow(&pkt[..end]);
+        }
+        count += 1;
+    }
+    assert!(count > 0);
+}
+
+#[test]
+fn bad_message_headers_abort() {
+    assert!(matches!(abort_error("empty"), ParseError::TruncatedHeader { offset: 0, .. }));
+    assert!(matches!(abort_error("short_header"), ParseError::TruncatedHeader { .. }));
+    assert!(matches!(abort_error("length_below_header"), ParseError::BadMessageLength { length: 4, .. }));
+    assert!(matches!(abort_error("length_past_datagram"), ParseError::BadMessageLength { length: 100, available: 16 }));
+    assert!(matches!(abort_error("datagram_past_length"), ParseError::BadMessageLength { length: 52, available: 56 }));
+    assert!(matches!(abort_error("wrong_version"), ParseError::BadVersion { version: 1 }));
+}
+
+#[test]
+fn lenient_mode_flags_length_mismatches() {
+    let info = parse_lenient(&read_case("datagram_past_length"));
+    assert!(matches!(info.header_error.as_deref(), Some(ParseError::BadMessageLength { length: 52, available: 56 })));
+    assert_eq!(info.set_error_count, 0);
+    assert_eq!(info.data.len(), 2);
+
+    //cut off partway through the data set, the template before it is still read
+    let pkt = read_case("template_and_data");
+    let info = parse_lenient(&pkt[..44]);
+    assert!(matches!(info.header_error.as_deref(), Some(ParseError::BadMessageLength { length: 52, available: 44 })));
+    assert_eq!(info.templates.len(), 1);
+    assert!(matches!(info.set_errors[..], [ParseError::SetLengthOverflow { offset: 32, set_id: 256, .. }]));
+
+    //the version is checked whatever the mode
+    match parse_packet(&TemplateRing::new(), exporter(), &read_case("wrong_version"), ValidationMode::Lenient) {
+        PacketResult::AbortError(e) => assert!(matches!(e, ParseError::BadVersion { version: 1 })),
+        PacketResult::Ok(_) => panic!("wrong_version should not have parsed")
+    }
+}
+
+#[test]
+fn bad_set_lengths_abort() {
+    assert!(matches!(abort_error("set_length_zero"), ParseError::SetLengthOverflow { offset: 16, set_id: 2, set_len: 0, .. }));
+    assert!(matches!(abort_error("set_length_overflow"), ParseError::SetLengthOverflow { offset: 16, set_id: 256, set_len: 400, .. }));
+}
+
+#[test]
+fn bad_sets_are_skipped() {
+    assert!(matches!(set_errors("trailing_bytes")[..], [ParseError::TruncatedHeader { offset: 32, .. }]));
+    assert!(matches!(set_errors("template_field_count_overflow")[..], [ParseError::TruncatedHeader { .. }]));
+    assert!(matches!(set_errors("template_enterprise_bit_truncated")[..], [ParseError::TruncatedHeader { .. }]));
+    assert!(matches!(set_errors("options_template_zero_scope")[..], [ParseError::MalformedTemplate { set_id: 3, template_id: 257, .. }]));
+    assert!(matches!(set_errors("options_template_scope_exceeds_fields")[..], [ParseError::MalformedTemplate { set_id: 3, template_id: 257, .. }]));
+    assert!(matches!(set_errors("zero_width_template_with_data")[..], [ParseError::MalformedTemplate { set_id: 258, .. }]));
+    assert!(matches!(set_errors("varlen_prefix_truncated")[..], [ParseError::FieldOutOfRange { set_id: 259, field_id: 82, width: 3, .. }]));
+    assert!(matches!(set_errors("varlen_length_past_set")[..], [ParseError::FieldOutOfRange { set_id: 259, field_id: 82, width: 32, .. }]));
+    assert!(matches!(set_errors("data_without_template")[..], [ParseError::UnknownTemplate { offset: 16, set_id: 256, odid: 1 }]));
+}
+
+#[test]
+fn templates_apply_to_the_rest_of_the_packet() {
+    let ring = TemplateRing::new();
+    match parse_packet(&ring, exporter(), &read_case("template_and_data"), ValidationMode::Strict) {
+        PacketResult::AbortError(e) => panic!("template_and_data failed to parse: {}", e),
+        PacketResult::Ok(info) => {
+            assert_eq!(info.set_error_count, 0);
+            assert_eq!(info.data.len(), 2);
+        }
+    }
+}
+
+#[test]
+fn well_formed_packet_still_parses() {
+    match parse_twice(&read_case("template_and_data")) {
+        PacketResult::AbortError(e) => panic!("template_and_data failed to parse: {}", e),
+        PacketResult::Ok(info) => {
+            assert_eq!(info.set_error_count, 0);
+            assert_eq!(info.templates.len(), 1);
+            assert_eq!(info.data.len(), 2);
+        }
+    }
+}
+
+#[test]
+fn netflow_v9_parses_like_ipfix() {
+    match parse_twice(&read_case("netflow_v9")) {
+        PacketResult::AbortError(e) => panic!("netflow_v9 failed to parse: {}", e),
+        PacketResult::Ok(info) => {
+            assert_eq!(info.version, 9);
+            assert_eq!((info.odid, info.seq_num, info.system_uptime), (1, 7, 1000));
+            assert_eq!(info.set_error_count, 0);
+            assert_eq!(info.templates.len(), 1);
+            assert_eq!(info.data.len(), 2);
+            assert_eq!(info.data[1].fields[1].name(), Some("octetDeltaCount"));
+
+            //the System scope is mapped onto exporterIPv4Address
+            assert_eq!(info.options_templates.len(), 1);
+            assert_eq!(info.options_data.len(), 1);
+            assert_eq!(info.options_data[0].scope_fields[0].name(), Some("exporterIPv4Address"));
+        }
+    }
+}
+
+#[test]
+fn netflow_v5_records_use_ipfix_elements() {
+    let pkt = read_case("netflow_v5");
+    match parse_twice(&pkt) {
+        PacketResult::AbortError(e) => panic!("netflow_v5 failed to parse: {}", e),
+        PacketResult::Ok(info) => {
+            assert_eq!(info.version, 5);
+            assert_eq!((info.odid, info.seq_num, info.system_uptime), (0x0102, 42, 5000));
+            assert_eq!(info.data.len(), 2);
+            assert!(info.data.iter().all(|d| d.template == 5));
+
+            let names: Vec<_> = info.data[0].fields.iter().map(|f| f.name()).collect();
+            assert_eq!(names[0], Some("sourceIPv4Address"));
+            assert!(names.contains(&Some("octetDeltaCount")));
+            assert!(names.contains(&Some("destinationTransportPort")));
+            assert!(names.iter().all(|n| n.is_some()));
+        }
+    }
+
+    //the record count has to match the datagram like an IPFIX length does
+    match parse_twice(&pkt[..100]) {
+        PacketResult::AbortError(e) => assert!(matches!(e, ParseError::BadMessageLength { length: 120, available: 100 })),
+        PacketResult::Ok(_) => panic!("a v5 message missing a record should not have parsed in strict mode")
+    }
+    let info = parse_lenient(&pkt[..100]);
+    assert_eq!(info.data.len(), 1);
+
+    //a record count that works out to more than a 16 bit length is reported as it is
+    let mut huge = pkt.clone();
+    huge[2..4].copy_from_slice(&2000u16.to_be_bytes());
+    match parse_twice(&huge) {
+        PacketResult::AbortError(e) => assert!(matches!(e, ParseError::BadMessageLength { length: 96024, available: 120 })),
+        PacketResult::Ok(_) => panic!("a v5 message missing most of its records should not have parsed in strict mode")
+    }
+}
+
+#[test]
+fn sflow_samples_use_ipfix_elements() {
+    match parse_sflow(&read_case("sflow")) {
+        PacketResult::AbortError(e) => panic!("sflow failed to parse: {}", e),
+        PacketResult::Ok(info) => {
+            assert_eq!((info.protocol, info.version), (Protocol::SFlow, 5));
+            assert_eq!((info.odid, info.seq_num, info.system_uptime), (9, 100, 50000));
+            assert_eq!(info.set_error_count, 0);
+
+            //the flow sample's output interface says the packet was dropped, so only the input interface is kept
+            assert_eq!(info.data.len(), 1);
+            assert_eq!(info.data[0].template, sflow::SFLOW_FLOW_SAMPLE_ID);
+            let names: Vec<_> = info.data[0].fields.iter().map(|f| f.name()).collect();
+            assert_eq!(names, vec![Some("exporterIPv4Address"), Some("samplingPacketInterval"), Some("samplingPopulation"), Some("droppedPacketTotalCount"),
+                Some("ingressInterface"), Some("dataLinkFrameSize"), Some("dataLinkFrameSection")]);
+
+            //the counter sample comes out as options data scoped to the interface, the unknown enterprise sample is skipped
+            assert_eq!(info.options_data.len(), 1);
+            assert_eq!(info.options_data[0].template, sflow::SFLOW_COUNTER_SAMPLE_ID);
+            assert_eq!(info.options_data[0].scope_fields[0].name(), Some("ingressInterface"));
+            assert!(info.options_data[0].fields.iter().any(|f| f.name() == Some("octetTotalCount")));
+        }
+    }
+
+    //a sample running off the end of the datagram means we can't find the ones after it
+    match parse_sflow(&read_case("sflow_sample_past_datagram")) {
+        PacketResult::AbortError(e) => assert!(matches!(e, ParseError::MalformedSFlow { offset: 124, .. })),
+        PacketResult::Ok(_) => panic!("sflow_sample_past_datagram should not have parsed")
+    }
+
+    //IPFIX listeners don't take sFlow
+    assert!(matches!(abort_error("sflow"), ParseError::BadVersion { version: 0 }));
+}
+
+#[test]
+fn ipfix_files_read_message_by_message() {
+    //session details first, then the same template and data twice, then half a message
+    let mut file = read_case("export_session_details");
+    let session_len = file.len() as u64;
+    let message = read_case("template_and_data");
+    file.extend_from_slice(&message);
+    file.extend_from_slice(&message);
+    file.extend_from_slice(&message[..20]);
+
+    let results: Vec<_> = IPFIXFileReader::new(file.as_slice()).collect();
+    assert_eq!(results.len(), 4);
+
+    let session = results[0].as_ref().expect("export session details should have parsed");
+    assert_eq!(session.options_data.len(), 1);
+    assert_eq!(session.options_data[0].scope_fields[0].name(), Some("sessionScope"));
+    assert_eq!(session.options_data[0].fields.last().and_then(|f| f.name()), Some("maxExportSeconds"));
+
+    //the template from the first copy is still known when the second one arrives, and both came from the exporter the session details name
+    for r in &results[1..3] {
+        let info = r.as_ref().expect("template_and_data should have parsed");
+        assert_eq!(info.exporter, SocketAddr::from(([192, 0, 2, 1], 4739)));
+        assert_eq!(info.set_error_count, 0);
+        assert_eq!(info.data.len(), 2);
+    }
+
+    let offset = session_len + 2 * message.len() as u64;
+    assert!(matches!(results[3], Err(FileReadError::Truncated { offset: o, needed: 52, available: 20 }) if o == offset));
+}
+
+#[test]
+fn ipfix_files_can_name_their_exporter() {
+    let message = read_case("template_and_data");
+    let unnamed = IPFIXFileReader::new(message.as_slice()).next().expect("one message").expect("template_and_data should have parsed");
+    assert_eq!(unnamed.exporter, FILE_EXPORTER);
+
+    //templates are kept under the named exporter, so files from exporters using the same ODIDs and template ids can share a ring without clashing
+    let a = SocketAddr::from(([192, 0, 2, 1], 4739));
+    let mut reader = IPFIXFileReader::new(message.as_slice()).with_exporter(a);
+    let info = reader.next().expect("one message").expect("template_and_data should have parsed");
+    assert_eq!(info.exporter, a);
+    let template = &info.templates[0];
+    assert!(reader.templates().get_template(a, template.id, info.odid).is_some());
+    assert!(reader.templates().get_template(FILE_EXPORTER, template.id, info.odid).is_none());
+}